@@ -1,17 +1,174 @@
 use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::hash::Hash;
+use std::io::{Error, ErrorKind};
 use std::ops::Index;
 
-pub trait Character: Default + Copy + Eq + Hash {}
+/// Byte encoding of chars in files and streams.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CharEncoding {
+    /// Fixed width, least significant byte first (UTF-32LE for `char`)
+    LittleEndian,
+    /// Fixed width, most significant byte first (UTF-32BE for `char`)
+    BigEndian,
+    /// UTF-8, with one to four bytes per char
+    Utf8,
+}
+
+/// Outcome of decoding a char from the start of a byte slice.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Decoded<C> {
+    /// The char and the number of bytes of its encoding
+    Char(C, usize),
+    /// More bytes are needed to decode the char
+    Incomplete,
+    /// The bytes do not encode a valid char
+    Invalid,
+}
+
+pub trait Character: Default + Copy + Eq + Hash {
+    /// Number of bytes of the fixed width encodings
+    const WIDTH: usize;
+    /// Encoding of the chars in files, unless stated otherwise
+    const ENCODING: CharEncoding = CharEncoding::LittleEndian;
+
+    /// Numeric code of the char
+    fn to_u32(self) -> u32;
+    /// The char with the given numeric code, if valid
+    fn from_u32(code: u32) -> Option<Self>;
+
+    /// Decodes the char at the start of `bytes`.
+    fn decode(bytes: &[u8], encoding: CharEncoding) -> Decoded<Self> {
+        let (code, len) = match encoding {
+            CharEncoding::Utf8 => {
+                let len = match bytes.first() {
+                    None => return Decoded::Incomplete,
+                    Some(&b) if b < 0x80 => 1,
+                    Some(&b) if b & 0xe0 == 0xc0 => 2,
+                    Some(&b) if b & 0xf0 == 0xe0 => 3,
+                    Some(&b) if b & 0xf8 == 0xf0 => 4,
+                    Some(_) => return Decoded::Invalid,
+                };
+                if bytes.len() < len {
+                    if bytes[1..].iter().any(|&b| b & 0xc0 != 0x80) {
+                        return Decoded::Invalid;
+                    }
+                    return Decoded::Incomplete;
+                }
+                match std::str::from_utf8(&bytes[..len]) {
+                    Ok(s) => (s.chars().next().unwrap() as u32, len),
+                    Err(_) => return Decoded::Invalid,
+                }
+            }
+            CharEncoding::LittleEndian | CharEncoding::BigEndian => {
+                if bytes.len() < Self::WIDTH {
+                    return Decoded::Incomplete;
+                }
+                let be = |code: u32, &b: &u8| code << 8 | b as u32;
+                let bytes = &bytes[..Self::WIDTH];
+                let code = if encoding == CharEncoding::LittleEndian {
+                    bytes.iter().rev().fold(0, be)
+                } else {
+                    bytes.iter().fold(0, be)
+                };
+                (code, Self::WIDTH)
+            }
+        };
+        match Self::from_u32(code) {
+            Some(c) => Decoded::Char(c, len),
+            None => Decoded::Invalid,
+        }
+    }
+
+    /// Views `bytes` as chars without copying, if they are the in-memory
+    /// representation of chars encoded with `encoding`.
+    fn view_bytes(_bytes: &[u8], _encoding: CharEncoding) -> Option<&[Self]> {
+        None
+    }
+
+    /// Appends the encoding of the char to `dst`.
+    /// Fails if the char has no UTF-8 encoding.
+    fn encode(self, encoding: CharEncoding, dst: &mut Vec<u8>) -> Result<(), Error> {
+        let code = self.to_u32();
+        match encoding {
+            CharEncoding::Utf8 => match char::from_u32(code) {
+                Some(c) => dst.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+                None => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("Char code {:#x} has no UTF-8 encoding", code),
+                    ))
+                }
+            },
+            CharEncoding::LittleEndian => {
+                dst.extend_from_slice(&code.to_le_bytes()[..Self::WIDTH]);
+            }
+            CharEncoding::BigEndian => {
+                dst.extend_from_slice(&code.to_be_bytes()[4 - Self::WIDTH..]);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Character for u8 {
+    const WIDTH: usize = 1;
+
+    fn view_bytes(bytes: &[u8], encoding: CharEncoding) -> Option<&[Self]> {
+        match encoding {
+            CharEncoding::Utf8 => None,
+            _ => Some(bytes),
+        }
+    }
+
+    fn to_u32(self) -> u32 {
+        self as u32
+    }
+    fn from_u32(code: u32) -> Option<Self> {
+        u8::try_from(code).ok()
+    }
+}
 
-impl Character for u8 {}
-impl Character for u16 {}
-impl Character for u32 {}
-impl Character for char {}
+impl Character for u16 {
+    const WIDTH: usize = 2;
+
+    fn to_u32(self) -> u32 {
+        self as u32
+    }
+    fn from_u32(code: u32) -> Option<Self> {
+        u16::try_from(code).ok()
+    }
+}
+
+impl Character for u32 {
+    const WIDTH: usize = 4;
+
+    fn to_u32(self) -> u32 {
+        self
+    }
+    fn from_u32(code: u32) -> Option<Self> {
+        Some(code)
+    }
+}
+
+impl Character for char {
+    const WIDTH: usize = 4;
+    const ENCODING: CharEncoding = CharEncoding::Utf8;
+
+    fn to_u32(self) -> u32 {
+        self as u32
+    }
+    fn from_u32(code: u32) -> Option<Self> {
+        char::from_u32(code)
+    }
+}
 
 pub trait Alphabet {
     type CharType: Character;
     fn len(&self) -> usize;
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
     fn chr(&self, ord: usize) -> Option<&Self::CharType>;
     fn ord(&self, chr: &Self::CharType) -> Option<usize>;
 }
@@ -30,10 +187,8 @@ where
 {
     pub fn new(chr_vec: Vec<C>) -> HashAlphabet<C> {
         let mut ord_map = HashMap::new();
-        let mut ord = 0;
-        for c in &chr_vec {
+        for (ord, c) in chr_vec.iter().enumerate() {
             ord_map.insert(*c, ord);
-            ord += 1;
         }
         HashAlphabet { chr_vec, ord_map }
     }
@@ -54,10 +209,7 @@ where
     }
 
     fn ord(&self, chr: &Self::CharType) -> Option<usize> {
-        match self.ord_map.get(chr) {
-            Some(val) => Some(*val),
-            None => None,
-        }
+        self.ord_map.get(chr).copied()
     }
 }
 
@@ -100,4 +252,45 @@ mod tests {
         assert_eq!(ab.ord(&'g',).unwrap(), 2);
         assert_eq!(ab.ord(&'_'), None);
     }
+
+    #[test]
+    fn test_char_encoding() {
+        use CharEncoding::*;
+        assert_eq!(
+            u16::decode(&[1, 2, 3], LittleEndian),
+            Decoded::Char(0x0201, 2)
+        );
+        assert_eq!(u16::decode(&[1, 2, 3], BigEndian), Decoded::Char(0x0102, 2));
+        assert_eq!(u32::decode(&[1, 2, 3], BigEndian), Decoded::Incomplete);
+        assert_eq!(u8::decode(&[0xc3, 0xa9], Utf8), Decoded::Char(0xe9, 2));
+        assert_eq!(u8::decode(&[0xe2, 0x82, 0xac], Utf8), Decoded::Invalid);
+        assert_eq!(char::decode(&[0xe2, 0x82], Utf8), Decoded::Incomplete);
+        assert_eq!(char::decode(&[0xe2, 0x41], Utf8), Decoded::Invalid);
+        assert_eq!(char::decode(&[0xc0, 0x80], Utf8), Decoded::Invalid);
+        assert_eq!(
+            char::decode(&[0, 0xd8, 0, 0], LittleEndian),
+            Decoded::Invalid
+        );
+        assert_eq!(
+            char::decode(&[0, 1, 0xd1, 0x1e], BigEndian),
+            Decoded::Char('𝄞', 4)
+        );
+
+        for &enc in [LittleEndian, BigEndian, Utf8].iter() {
+            let mut bytes = vec![];
+            for &c in ['a', 'é', '€', '𝄞'].iter() {
+                c.encode(enc, &mut bytes).unwrap();
+                0x20acu32.encode(enc, &mut bytes).unwrap();
+            }
+            let mut chars = vec![];
+            let mut pos = 0;
+            while let Decoded::Char(c, len) = char::decode(&bytes[pos..], enc) {
+                chars.push(c);
+                pos += len;
+            }
+            assert_eq!(pos, bytes.len());
+            assert_eq!(chars, ['a', '€', 'é', '€', '€', '€', '𝄞', '€']);
+        }
+        assert!(0xd800u32.encode(Utf8, &mut vec![]).is_err());
+    }
 }
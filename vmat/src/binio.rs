@@ -0,0 +1,138 @@
+//! Little-endian binary encoding helpers used to save and load
+//! data structures to/from disk.
+
+use std::io::{Error, ErrorKind, Read, Write};
+
+/// Largest number of values preallocated for a length read from a file,
+/// so that a garbled length fails at the end of the file instead of
+/// exhausting the memory.
+pub const MAX_PREALLOC: usize = 1 << 16;
+
+/// Makes an empty vector for `n` values whose count was read from a file.
+/// At most [`MAX_PREALLOC`] values are preallocated, the vector grows as
+/// the values are actually read.
+pub fn with_capacity<T>(n: usize) -> Vec<T> {
+    Vec::with_capacity(n.min(MAX_PREALLOC))
+}
+
+/// Reads into `buf` until it is full or EOF is reached.
+/// Returns the number of bytes read.
+pub fn read_up_to<R: Read>(src: &mut R, buf: &mut [u8]) -> Result<usize, Error> {
+    let mut n = 0;
+    while n < buf.len() {
+        match src.read(&mut buf[n..])? {
+            0 => break,
+            m => n += m,
+        }
+    }
+    Ok(n)
+}
+
+pub fn write_u8<W: Write>(dst: &mut W, v: u8) -> Result<(), Error> {
+    dst.write_all(&[v])
+}
+
+pub fn read_u8<R: Read>(src: &mut R) -> Result<u8, Error> {
+    let mut buf = [0u8; 1];
+    src.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+pub fn write_u32<W: Write>(dst: &mut W, v: u32) -> Result<(), Error> {
+    dst.write_all(&v.to_le_bytes())
+}
+
+pub fn read_u32<R: Read>(src: &mut R) -> Result<u32, Error> {
+    let mut buf = [0u8; 4];
+    src.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+pub fn write_u64<W: Write>(dst: &mut W, v: u64) -> Result<(), Error> {
+    dst.write_all(&v.to_le_bytes())
+}
+
+pub fn read_u64<R: Read>(src: &mut R) -> Result<u64, Error> {
+    let mut buf = [0u8; 8];
+    src.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+/// `usize` values are always stored as 64-bit integers.
+pub fn write_usize<W: Write>(dst: &mut W, v: usize) -> Result<(), Error> {
+    write_u64(dst, v as u64)
+}
+
+pub fn read_usize<R: Read>(src: &mut R) -> Result<usize, Error> {
+    let v = read_u64(src)?;
+    if v > usize::MAX as u64 {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "Value does not fit in usize",
+        ));
+    }
+    Ok(v as usize)
+}
+
+/// Writes an optional `usize`, with `None` encoded as `u64::MAX`.
+pub fn write_opt_usize<W: Write>(dst: &mut W, v: Option<usize>) -> Result<(), Error> {
+    write_u64(dst, v.map_or(u64::MAX, |x| x as u64))
+}
+
+pub fn read_opt_usize<R: Read>(src: &mut R) -> Result<Option<usize>, Error> {
+    match read_u64(src)? {
+        u64::MAX => Ok(None),
+        v if v > usize::MAX as u64 => Err(Error::new(
+            ErrorKind::InvalidData,
+            "Value does not fit in usize",
+        )),
+        v => Ok(Some(v as usize)),
+    }
+}
+
+/// Writes a length-prefixed sequence of `usize` values.
+pub fn write_usizes<W: Write>(dst: &mut W, v: &[usize]) -> Result<(), Error> {
+    write_usize(dst, v.len())?;
+    for &x in v {
+        write_usize(dst, x)?;
+    }
+    Ok(())
+}
+
+pub fn read_usizes<R: Read>(src: &mut R) -> Result<Vec<usize>, Error> {
+    let n = read_usize(src)?;
+    let mut v = with_capacity(n);
+    for _ in 0..n {
+        v.push(read_usize(src)?);
+    }
+    Ok(v)
+}
+
+/// Writes a length-prefixed UTF-8 string.
+pub fn write_str<W: Write>(dst: &mut W, s: &str) -> Result<(), Error> {
+    write_usize(dst, s.len())?;
+    dst.write_all(s.as_bytes())
+}
+
+pub fn read_string<R: Read>(src: &mut R) -> Result<String, Error> {
+    let n = read_usize(src)?;
+    let mut buf = with_capacity(n);
+    src.take(n as u64).read_to_end(&mut buf)?;
+    if buf.len() < n {
+        return Err(Error::new(ErrorKind::UnexpectedEof, "Truncated string"));
+    }
+    String::from_utf8(buf).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
+
+/// Reads and checks a magic number identifying a file format.
+pub fn check_magic<R: Read>(src: &mut R, magic: &[u8]) -> Result<(), Error> {
+    let mut buf = vec![0u8; magic.len()];
+    src.read_exact(&mut buf)?;
+    if buf != magic {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "Unrecognised file format",
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,271 @@
+//! Bit vectors with constant time rank and logarithmic time select.
+//!
+//! The rank directory holds the number of ones before every superblock of
+//! `SUPER_BITS` bits, and before every block of `BLOCK_BITS` bits relative
+//! to its superblock, for an overhead of about 5% of the bits.
+
+use std::io::{self, Read, Write};
+use std::iter::FromIterator;
+
+use crate::binio;
+
+const SUPER_BITS: usize = 4096;
+const BLOCK_BITS: usize = 512;
+const BLOCK_WORDS: usize = BLOCK_BITS / 64;
+const SUPER_BLOCKS: usize = SUPER_BITS / BLOCK_BITS;
+
+/// Immutable bit vector supporting rank and select queries.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BitVector {
+    words: Vec<u64>,
+    len: usize,
+    /// Number of ones before each superblock
+    supers: Vec<usize>,
+    /// Number of ones before each block, from the start of its superblock
+    blocks: Vec<u16>,
+}
+
+impl BitVector {
+    /// Makes a bit vector of `len` bits, where bit `i` is bit `i % 64`
+    /// of `words[i / 64]`. Bits of `words` past `len` are ignored.
+    pub fn from_words(mut words: Vec<u64>, len: usize) -> Self {
+        assert!(words.len() * 64 >= len);
+        words.truncate(len.div_ceil(64));
+        if !len.is_multiple_of(64) {
+            *words.last_mut().unwrap() &= (1 << (len % 64)) - 1;
+        }
+        let nblocks = len / BLOCK_BITS + 1;
+        let mut supers = Vec::with_capacity(len / SUPER_BITS + 1);
+        let mut blocks = Vec::with_capacity(nblocks);
+        let mut ones = 0;
+        for b in 0..nblocks {
+            if b.is_multiple_of(SUPER_BLOCKS) {
+                supers.push(ones);
+            }
+            blocks.push((ones - supers[b / SUPER_BLOCKS]) as u16);
+            let end = words.len().min((b + 1) * BLOCK_WORDS);
+            for w in words[b * BLOCK_WORDS..end].iter() {
+                ones += w.count_ones() as usize;
+            }
+        }
+        BitVector {
+            words,
+            len,
+            supers,
+            blocks,
+        }
+    }
+
+    /// The bits packed into 64-bit words, as taken by [`BitVector::from_words`].
+    pub fn words(&self) -> &[u64] {
+        &self.words
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Value of bit `i`.
+    #[inline]
+    pub fn get(&self, i: usize) -> bool {
+        assert!(i < self.len);
+        self.words[i / 64] & (1 << (i % 64)) != 0
+    }
+
+    /// Number of ones in the whole vector.
+    pub fn count_ones(&self) -> usize {
+        self.rank1(self.len)
+    }
+
+    /// Number of ones before position `i`, for `i <= len`.
+    #[inline]
+    pub fn rank1(&self, i: usize) -> usize {
+        assert!(i <= self.len);
+        let b = i / BLOCK_BITS;
+        let mut rank = self.supers[i / SUPER_BITS] + self.blocks[b] as usize;
+        for w in self.words[b * BLOCK_WORDS..i / 64].iter() {
+            rank += w.count_ones() as usize;
+        }
+        if !i.is_multiple_of(64) {
+            rank += (self.words[i / 64] & ((1 << (i % 64)) - 1)).count_ones() as usize;
+        }
+        rank
+    }
+
+    /// Number of zeros before position `i`, for `i <= len`.
+    #[inline]
+    pub fn rank0(&self, i: usize) -> usize {
+        i - self.rank1(i)
+    }
+
+    /// Position of the `k`-th one, counting from 0, if any.
+    pub fn select1(&self, k: usize) -> Option<usize> {
+        self.select(k, true)
+    }
+
+    /// Position of the `k`-th zero, counting from 0, if any.
+    pub fn select0(&self, k: usize) -> Option<usize> {
+        self.select(k, false)
+    }
+
+    fn select(&self, k: usize, bit: bool) -> Option<usize> {
+        let total = if bit {
+            self.count_ones()
+        } else {
+            self.len - self.count_ones()
+        };
+        if k >= total {
+            return None;
+        }
+        // number of matching bits before superblock s and before block b
+        let before_super = |s: usize| {
+            if bit {
+                self.supers[s]
+            } else {
+                s * SUPER_BITS - self.supers[s]
+            }
+        };
+        let before_block = |b: usize| {
+            let ones = self.supers[b / SUPER_BLOCKS] + self.blocks[b] as usize;
+            if bit {
+                ones
+            } else {
+                b * BLOCK_BITS - ones
+            }
+        };
+        // invariant: the k-th bit is in a superblock in [s,h)
+        let (mut s, mut h) = (0, self.supers.len());
+        while h - s > 1 {
+            let m = (s + h) / 2;
+            if before_super(m) <= k {
+                s = m;
+            } else {
+                h = m;
+            }
+        }
+        let end = self.blocks.len().min((s + 1) * SUPER_BLOCKS);
+        let mut b = s * SUPER_BLOCKS;
+        while b + 1 < end && before_block(b + 1) <= k {
+            b += 1;
+        }
+        let mut rest = k - before_block(b);
+        for w in b * BLOCK_WORDS..self.words.len() {
+            let mut word = if bit { self.words[w] } else { !self.words[w] };
+            let count = word.count_ones() as usize;
+            if rest < count {
+                for _ in 0..rest {
+                    word &= word - 1;
+                }
+                return Some(w * 64 + word.trailing_zeros() as usize);
+            }
+            rest -= count;
+        }
+        unreachable!()
+    }
+
+    /// Writes the bit vector to `dst` in binary format.
+    pub fn save<W: Write>(&self, dst: &mut W) -> Result<(), io::Error> {
+        binio::write_usize(dst, self.len)?;
+        for &w in self.words.iter() {
+            binio::write_u64(dst, w)?;
+        }
+        Ok(())
+    }
+
+    /// Reads a bit vector saved with [`BitVector::save`] from `src`.
+    pub fn load<R: Read>(src: &mut R) -> Result<Self, io::Error> {
+        let len = binio::read_usize(src)?;
+        let mut words = binio::with_capacity(len.div_ceil(64));
+        for _ in 0..len.div_ceil(64) {
+            words.push(binio::read_u64(src)?);
+        }
+        Ok(Self::from_words(words, len))
+    }
+}
+
+impl FromIterator<bool> for BitVector {
+    fn from_iter<I: IntoIterator<Item = bool>>(iter: I) -> Self {
+        let mut words = vec![];
+        let mut len: usize = 0;
+        for bit in iter {
+            if len.is_multiple_of(64) {
+                words.push(0);
+            }
+            if bit {
+                words[len / 64] |= 1 << (len % 64);
+            }
+            len += 1;
+        }
+        Self::from_words(words, len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn random_bits(len: usize, density: u64, seed: u64) -> Vec<bool> {
+        let mut x = seed;
+        (0..len)
+            .map(|_| {
+                x = x
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                (x >> 33) % 100 < density
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_rank_select() {
+        for &len in [0, 1, 63, 64, 65, 511, 512, 4095, 4096, 4097, 20000].iter() {
+            for &density in [0, 1, 50, 99, 100].iter() {
+                let bits = random_bits(len, density, len as u64 + density);
+                let bv: BitVector = bits.iter().copied().collect();
+                assert_eq!(bv.len(), len);
+                let (mut ones, mut zeros) = (vec![], vec![]);
+                for (i, &bit) in bits.iter().enumerate() {
+                    assert_eq!(bv.get(i), bit);
+                    assert_eq!(bv.rank1(i), ones.len());
+                    assert_eq!(bv.rank0(i), zeros.len());
+                    if bit {
+                        ones.push(i);
+                    } else {
+                        zeros.push(i);
+                    }
+                }
+                assert_eq!(bv.count_ones(), ones.len());
+                for (k, &i) in ones.iter().enumerate() {
+                    assert_eq!(bv.select1(k), Some(i));
+                }
+                for (k, &i) in zeros.iter().enumerate() {
+                    assert_eq!(bv.select0(k), Some(i));
+                }
+                assert_eq!(bv.select1(ones.len()), None);
+                assert_eq!(bv.select0(zeros.len()), None);
+            }
+        }
+    }
+
+    #[test]
+    fn test_bitvec_save_load() {
+        let bv = BitVector::from_words(vec![u64::MAX, 0b1011, u64::MAX], 130);
+        assert_eq!(bv.count_ones(), 69);
+        assert_eq!(bv.select1(66), Some(67));
+        assert_eq!(bv.select1(68), Some(129));
+        assert_eq!(bv.select1(69), None);
+        assert_eq!(bv.select0(0), Some(66));
+        let mut buf = vec![];
+        bv.save(&mut buf).unwrap();
+        assert_eq!(BitVector::load(&mut &buf[..]).unwrap(), bv);
+        assert!(BitVector::load(&mut &buf[..buf.len() - 1]).is_err());
+        // a garbled length fails at the end of the file
+        buf[..8].copy_from_slice(&(usize::MAX as u64).to_le_bytes());
+        let err = BitVector::load(&mut &buf[..]).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
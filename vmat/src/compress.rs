@@ -0,0 +1,676 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Error, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use flate2::bufread::MultiGzDecoder;
+
+use crate::binio;
+use crate::faidx::index_path;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// Compression formats of sequence files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    /// Blocked GNU Zip Format, a multi-member gzip variant
+    Bgzf,
+    Zstd,
+}
+
+impl Compression {
+    /// Detects the compression format from the first bytes of a file.
+    pub fn detect(head: &[u8]) -> Compression {
+        if head.starts_with(ZSTD_MAGIC) {
+            Compression::Zstd
+        } else if head.starts_with(GZIP_MAGIC) {
+            // BGZF members have the FEXTRA flag set
+            // and a 'BC' extra subfield
+            if head.len() >= 14 && head[3] & 0x04 != 0 && head[12] == b'B' && head[13] == b'C' {
+                Compression::Bgzf
+            } else {
+                Compression::Gzip
+            }
+        } else {
+            Compression::None
+        }
+    }
+}
+
+/// A reader that transparently decompresses its source,
+/// whose compression format is detected from its magic bytes.
+pub enum Decoder<R>
+where
+    R: Read,
+{
+    Plain(BufReader<R>),
+    Gzip(Compression, MultiGzDecoder<BufReader<R>>),
+    Zstd(zstd::Decoder<'static, BufReader<R>>),
+}
+
+impl Decoder<File> {
+    pub fn new_from_path<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::new(File::open(path)?)
+    }
+}
+
+impl<R> Decoder<R>
+where
+    R: Read,
+{
+    pub fn new(src: R) -> Result<Self, Error> {
+        let mut reader = BufReader::new(src);
+        let compression = Compression::detect(reader.fill_buf()?);
+        Ok(match compression {
+            Compression::None => Decoder::Plain(reader),
+            Compression::Gzip | Compression::Bgzf => {
+                Decoder::Gzip(compression, MultiGzDecoder::new(reader))
+            }
+            Compression::Zstd => Decoder::Zstd(zstd::Decoder::with_buffer(reader)?),
+        })
+    }
+
+    /// The detected compression format of the source.
+    pub fn compression(&self) -> Compression {
+        match self {
+            Decoder::Plain(_) => Compression::None,
+            Decoder::Gzip(compression, _) => *compression,
+            Decoder::Zstd(_) => Compression::Zstd,
+        }
+    }
+}
+
+impl<R> Read for Decoder<R>
+where
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        match self {
+            Decoder::Plain(reader) => reader.read(buf),
+            Decoder::Gzip(_, reader) => reader.read(buf),
+            Decoder::Zstd(reader) => reader.read(buf),
+        }
+    }
+}
+
+/// Only uncompressed sources are seekable.
+impl<R> Seek for Decoder<R>
+where
+    R: Read + Seek,
+{
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, Error> {
+        match self {
+            Decoder::Plain(reader) => reader.seek(pos),
+            _ => Err(Error::new(
+                ErrorKind::Unsupported,
+                "Cannot seek in a compressed stream",
+            )),
+        }
+    }
+}
+
+/// Length of the header of a BGZF block
+const BGZF_HEADER_LEN: usize = 18;
+
+/// Index of the blocks of a BGZF file, compatible with the `.gzi`
+/// files written by `bgzip -i` and `samtools faidx`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GziIndex {
+    /// `(compressed, uncompressed)` start offsets of the blocks,
+    /// except the first one which always starts at `(0,0)`
+    blocks: Vec<(u64, u64)>,
+}
+
+impl GziIndex {
+    /// Builds the index by scanning the headers of the BGZF blocks of `src`.
+    pub fn build<R: Read>(src: &mut R) -> Result<Self, Error> {
+        let mut blocks = vec![];
+        let (mut coff, mut uoff) = (0u64, 0u64);
+        let mut header = [0u8; BGZF_HEADER_LEN];
+        loop {
+            match src.read(&mut header[..1])? {
+                0 => break,
+                _ => src.read_exact(&mut header[1..])?,
+            }
+            if Compression::detect(&header) != Compression::Bgzf {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Invalid BGZF block header at offset {}", coff),
+                ));
+            }
+            let bsize = u16::from_le_bytes([header[16], header[17]]) as u64 + 1;
+            // skip compressed data and CRC32, then read ISIZE
+            let skip = match bsize.checked_sub(BGZF_HEADER_LEN as u64 + 4) {
+                // the block holds at least the CRC32
+                Some(skip) if skip >= 4 => skip,
+                _ => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Invalid BGZF block size at offset {}", coff),
+                    ))
+                }
+            };
+            if io::copy(&mut src.take(skip), &mut io::sink())? != skip {
+                return Err(Error::new(ErrorKind::UnexpectedEof, "Truncated BGZF block"));
+            }
+            let isize = binio::read_u32(src)? as u64;
+            if coff > 0 {
+                blocks.push((coff, uoff));
+            }
+            coff += bsize;
+            uoff += isize;
+        }
+        Ok(GziIndex { blocks })
+    }
+
+    pub fn write<W: Write>(&self, dst: &mut W) -> Result<(), Error> {
+        binio::write_u64(dst, self.blocks.len() as u64)?;
+        for &(coff, uoff) in self.blocks.iter() {
+            binio::write_u64(dst, coff)?;
+            binio::write_u64(dst, uoff)?;
+        }
+        Ok(())
+    }
+
+    pub fn read<R: Read>(src: &mut R) -> Result<Self, Error> {
+        let n = binio::read_u64(src)?;
+        let mut blocks = binio::with_capacity(n.min(usize::MAX as u64) as usize);
+        for _ in 0..n {
+            let coff = binio::read_u64(src)?;
+            let uoff = binio::read_u64(src)?;
+            blocks.push((coff, uoff));
+        }
+        Ok(GziIndex { blocks })
+    }
+
+    /// The `(compressed, uncompressed)` start offsets of the block
+    /// containing the uncompressed offset `uoff`.
+    pub fn block_of(&self, uoff: u64) -> (u64, u64) {
+        match self.blocks.partition_point(|b| b.1 <= uoff) {
+            0 => (0, 0),
+            i => self.blocks[i - 1],
+        }
+    }
+}
+
+/// Seekable reader of BGZF files. Positions are uncompressed offsets.
+pub struct BgzfReader<R>
+where
+    R: Read + Seek,
+{
+    gzi: GziIndex,
+    decoder: Option<MultiGzDecoder<BufReader<R>>>,
+    pos: u64,
+}
+
+impl<R> BgzfReader<R>
+where
+    R: Read + Seek,
+{
+    pub fn new(src: R, gzi: GziIndex) -> Self {
+        BgzfReader {
+            gzi,
+            decoder: Some(MultiGzDecoder::new(BufReader::new(src))),
+            pos: 0,
+        }
+    }
+
+    pub fn gzi(&self) -> &GziIndex {
+        &self.gzi
+    }
+
+    fn skip(&mut self, n: u64) -> Result<(), Error> {
+        let decoder = self.decoder.as_mut().unwrap();
+        let skipped = io::copy(&mut decoder.take(n), &mut io::sink())?;
+        self.pos += skipped;
+        if skipped < n {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "Seek beyond the end of the BGZF file",
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl<R> Read for BgzfReader<R>
+where
+    R: Read + Seek,
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let n = self.decoder.as_mut().unwrap().read(buf)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+/// Only `SeekFrom::Start` and `SeekFrom::Current` are supported since
+/// the `.gzi` index does not record the uncompressed length.
+impl<R> Seek for BgzfReader<R>
+where
+    R: Read + Seek,
+{
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, Error> {
+        let target = match pos {
+            SeekFrom::Start(p) => p,
+            SeekFrom::Current(d) if d >= 0 => self.pos + d as u64,
+            SeekFrom::Current(d) if d.unsigned_abs() <= self.pos => self.pos - d.unsigned_abs(),
+            SeekFrom::Current(_) => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "Seek to a negative position",
+                ))
+            }
+            SeekFrom::End(_) => {
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    "Cannot seek from the end of a BGZF file",
+                ))
+            }
+        };
+        let (coff, uoff) = self.gzi.block_of(target);
+        if target < self.pos || uoff > self.pos {
+            // restart decoding at the start of the target block
+            let mut src = self.decoder.take().unwrap().into_inner();
+            src.seek(SeekFrom::Start(coff))?;
+            self.decoder = Some(MultiGzDecoder::new(src));
+            self.pos = uoff;
+        }
+        self.skip(target - self.pos)?;
+        Ok(self.pos)
+    }
+}
+
+/// A seekable source, either uncompressed or BGZF compressed.
+pub enum RandomAccess<R>
+where
+    R: Read + Seek,
+{
+    Plain(R),
+    Bgzf(Box<BgzfReader<R>>),
+}
+
+impl RandomAccess<File> {
+    /// Opens a file for random access. BGZF files are indexed with the
+    /// `.gzi` file next to them, or by scanning their blocks if there is none.
+    pub fn new_from_path<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let mut file = File::open(&path)?;
+        let mut head = [0u8; BGZF_HEADER_LEN];
+        let n = binio::read_up_to(&mut file, &mut head)?;
+        file.seek(SeekFrom::Start(0))?;
+        match Compression::detect(&head[..n]) {
+            Compression::None => Ok(RandomAccess::Plain(file)),
+            Compression::Bgzf => {
+                let gzi = match File::open(index_path(&path, ".gzi")) {
+                    Ok(gzi_file) => GziIndex::read(&mut BufReader::new(gzi_file))?,
+                    Err(e) if e.kind() == ErrorKind::NotFound => {
+                        let gzi = GziIndex::build(&mut BufReader::new(&mut file))?;
+                        file.seek(SeekFrom::Start(0))?;
+                        gzi
+                    }
+                    Err(e) => return Err(e),
+                };
+                Ok(RandomAccess::Bgzf(Box::new(BgzfReader::new(file, gzi))))
+            }
+            c => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "{:?} compressed files cannot be accessed randomly. Use BGZF instead",
+                    c
+                ),
+            )),
+        }
+    }
+}
+
+impl<R> Read for RandomAccess<R>
+where
+    R: Read + Seek,
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        match self {
+            RandomAccess::Plain(reader) => reader.read(buf),
+            RandomAccess::Bgzf(reader) => reader.read(buf),
+        }
+    }
+}
+
+impl<R> Seek for RandomAccess<R>
+where
+    R: Read + Seek,
+{
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, Error> {
+        match self {
+            RandomAccess::Plain(reader) => reader.seek(pos),
+            RandomAccess::Bgzf(reader) => reader.seek(pos),
+        }
+    }
+}
+
+/// Maximum number of uncompressed bytes per BGZF block, as in htslib
+const BGZF_BLOCK_SIZE: usize = 0xff00;
+
+/// Writer of BGZF files. The `.gzi` index of the blocks written so far
+/// is available with `gzi`.
+/// The final empty EOF block is written by `finish` or when dropped.
+pub struct BgzfWriter<W>
+where
+    W: Write,
+{
+    dst: Option<W>,
+    level: flate2::Compression,
+    buf: Vec<u8>,
+    gzi: GziIndex,
+    coff: u64,
+    uoff: u64,
+}
+
+impl<W> BgzfWriter<W>
+where
+    W: Write,
+{
+    pub fn new(dst: W) -> Self {
+        BgzfWriter {
+            dst: Some(dst),
+            level: flate2::Compression::default(),
+            buf: Vec::with_capacity(BGZF_BLOCK_SIZE),
+            gzi: GziIndex::default(),
+            coff: 0,
+            uoff: 0,
+        }
+    }
+
+    pub fn gzi(&self) -> &GziIndex {
+        &self.gzi
+    }
+
+    /// Compresses and writes `self.buf` as a single block.
+    fn write_block(&mut self) -> Result<(), Error> {
+        let mut encoder = flate2::GzBuilder::new()
+            .extra(vec![b'B', b'C', 2, 0, 0, 0])
+            .write(Vec::new(), self.level);
+        encoder.write_all(&self.buf)?;
+        let mut block = encoder.finish()?;
+        // BSIZE = total block size - 1
+        let bsize = (block.len() - 1) as u16;
+        block[16..18].copy_from_slice(&bsize.to_le_bytes());
+        self.dst.as_mut().unwrap().write_all(&block)?;
+        if self.coff > 0 {
+            self.gzi.blocks.push((self.coff, self.uoff));
+        }
+        self.coff += block.len() as u64;
+        self.uoff += self.buf.len() as u64;
+        self.buf.clear();
+        Ok(())
+    }
+
+    /// Writes the pending data and the EOF block, and returns the destination.
+    pub fn finish(mut self) -> Result<W, Error> {
+        self.try_finish()?;
+        Ok(self.dst.take().unwrap())
+    }
+
+    fn try_finish(&mut self) -> Result<(), Error> {
+        if self.dst.is_some() {
+            if !self.buf.is_empty() {
+                self.write_block()?;
+            }
+            // empty EOF block
+            self.write_block()?;
+            self.dst.as_mut().unwrap().flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl<W> Write for BgzfWriter<W>
+where
+    W: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        let n = buf.len().min(BGZF_BLOCK_SIZE - self.buf.len());
+        self.buf.extend_from_slice(&buf[..n]);
+        if self.buf.len() == BGZF_BLOCK_SIZE {
+            self.write_block()?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        if !self.buf.is_empty() {
+            self.write_block()?;
+        }
+        self.dst.as_mut().unwrap().flush()
+    }
+}
+
+impl<W> Drop for BgzfWriter<W>
+where
+    W: Write,
+{
+    fn drop(&mut self) {
+        let _ = self.try_finish();
+    }
+}
+
+/// A writer that compresses its output in the given format.
+/// `finish` must be called to complete the compressed stream.
+pub enum Encoder<W>
+where
+    W: Write,
+{
+    Plain(W),
+    Gzip(flate2::write::GzEncoder<W>),
+    Bgzf(BgzfWriter<W>),
+    Zstd(zstd::Encoder<'static, W>),
+}
+
+impl Encoder<File> {
+    pub fn new_from_path<P: AsRef<Path>>(path: P, compression: Compression) -> Result<Self, Error> {
+        Self::new(File::create(path)?, compression)
+    }
+}
+
+impl<W> Encoder<W>
+where
+    W: Write,
+{
+    pub fn new(dst: W, compression: Compression) -> Result<Self, Error> {
+        Ok(match compression {
+            Compression::None => Encoder::Plain(dst),
+            Compression::Gzip => Encoder::Gzip(flate2::write::GzEncoder::new(
+                dst,
+                flate2::Compression::default(),
+            )),
+            Compression::Bgzf => Encoder::Bgzf(BgzfWriter::new(dst)),
+            Compression::Zstd => Encoder::Zstd(zstd::Encoder::new(dst, 0)?),
+        })
+    }
+
+    pub fn compression(&self) -> Compression {
+        match self {
+            Encoder::Plain(_) => Compression::None,
+            Encoder::Gzip(_) => Compression::Gzip,
+            Encoder::Bgzf(_) => Compression::Bgzf,
+            Encoder::Zstd(_) => Compression::Zstd,
+        }
+    }
+
+    /// Completes the compressed stream and returns the destination.
+    pub fn finish(self) -> Result<W, Error> {
+        match self {
+            Encoder::Plain(mut dst) => {
+                dst.flush()?;
+                Ok(dst)
+            }
+            Encoder::Gzip(encoder) => encoder.finish(),
+            Encoder::Bgzf(writer) => writer.finish(),
+            Encoder::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl<W> Write for Encoder<W>
+where
+    W: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        match self {
+            Encoder::Plain(dst) => dst.write(buf),
+            Encoder::Gzip(dst) => dst.write(buf),
+            Encoder::Bgzf(dst) => dst.write(buf),
+            Encoder::Zstd(dst) => dst.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        match self {
+            Encoder::Plain(dst) => dst.flush(),
+            Encoder::Gzip(dst) => dst.flush(),
+            Encoder::Bgzf(dst) => dst.flush(),
+            Encoder::Zstd(dst) => dst.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+
+    const CONTENT: &[u8] = b">id1\nACGTACGT\n>id2\nGGGG\n";
+
+    fn decode(src: &[u8]) -> (Compression, Vec<u8>) {
+        let mut decoder = Decoder::new(src).expect("Unable to open decoder");
+        let mut out = vec![];
+        decoder.read_to_end(&mut out).expect("Unable to decode");
+        (decoder.compression(), out)
+    }
+
+    fn gzip(src: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(src).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_plain() {
+        assert_eq!(decode(CONTENT), (Compression::None, CONTENT.to_vec()));
+    }
+
+    #[test]
+    fn test_gzip() {
+        assert_eq!(
+            decode(&gzip(CONTENT)),
+            (Compression::Gzip, CONTENT.to_vec())
+        );
+        // multi-member
+        let mut multi = gzip(&CONTENT[..10]);
+        multi.extend(gzip(&CONTENT[10..]));
+        assert_eq!(decode(&multi), (Compression::Gzip, CONTENT.to_vec()));
+    }
+
+    /// BGZF members: gzip header with the BC extra subfield holding the
+    /// block size, followed by the standard BGZF empty EOF block
+    fn bgzf(parts: &[&[u8]]) -> Vec<u8> {
+        let mut bgzf = vec![];
+        for part in parts.iter().chain([&[][..]].iter()) {
+            let mut encoder = flate2::GzBuilder::new()
+                .extra(vec![b'B', b'C', 2, 0, 0, 0])
+                .write(vec![], flate2::Compression::default());
+            encoder.write_all(part).unwrap();
+            let mut member = encoder.finish().unwrap();
+            let bsize = (member.len() - 1) as u16;
+            member[16..18].copy_from_slice(&bsize.to_le_bytes());
+            bgzf.extend(member);
+        }
+        bgzf
+    }
+
+    #[test]
+    fn test_bgzf() {
+        let bgzf = bgzf(&[&CONTENT[..10], &CONTENT[10..]]);
+        assert_eq!(decode(&bgzf), (Compression::Bgzf, CONTENT.to_vec()));
+    }
+
+    #[test]
+    fn test_bgzf_seek() {
+        let parts: Vec<&[u8]> = CONTENT.chunks(5).collect();
+        let bgzf = bgzf(&parts);
+        let gzi = GziIndex::build(&mut &bgzf[..]).unwrap();
+        assert_eq!(gzi.blocks.len(), parts.len());
+        assert_eq!(gzi.block_of(0), (0, 0));
+        assert_eq!(gzi.block_of(7).1, 5);
+
+        let mut saved = vec![];
+        gzi.write(&mut saved).unwrap();
+        assert_eq!(saved.len(), 8 + 16 * parts.len());
+        assert_eq!(GziIndex::read(&mut &saved[..]).unwrap(), gzi);
+        // truncated and garbled block counts
+        assert!(GziIndex::read(&mut &saved[..saved.len() - 1]).is_err());
+        saved[..8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(GziIndex::read(&mut &saved[..]).is_err());
+
+        // garbled block size of the second block
+        let mut garbled = bgzf.clone();
+        let second = gzi.blocks[0].0 as usize;
+        garbled[second + 16..second + 18].copy_from_slice(&20u16.to_le_bytes());
+        let err = GziIndex::build(&mut &garbled[..]).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let mut reader = BgzfReader::new(io::Cursor::new(bgzf), gzi);
+        let mut buf = [0u8; 4];
+        for &p in [14, 3, 9, 20, 0].iter() {
+            assert_eq!(reader.seek(SeekFrom::Start(p)).unwrap(), p);
+            reader.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf, &CONTENT[p as usize..p as usize + 4]);
+        }
+        reader.seek(SeekFrom::Current(-3)).unwrap();
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, &CONTENT[1..5]);
+    }
+
+    #[test]
+    fn test_zstd() {
+        let compressed = zstd::encode_all(CONTENT, 0).unwrap();
+        assert_eq!(decode(&compressed), (Compression::Zstd, CONTENT.to_vec()));
+    }
+
+    #[test]
+    fn test_encoder() {
+        let big: Vec<u8> = CONTENT
+            .iter()
+            .cycle()
+            .take(3 * BGZF_BLOCK_SIZE / 2)
+            .copied()
+            .collect();
+        for &c in [
+            Compression::None,
+            Compression::Gzip,
+            Compression::Bgzf,
+            Compression::Zstd,
+        ]
+        .iter()
+        {
+            let mut encoder = Encoder::new(vec![], c).unwrap();
+            encoder.write_all(&big).unwrap();
+            let compressed = encoder.finish().unwrap();
+            assert_eq!(decode(&compressed), (c, big.clone()));
+        }
+        // BGZF blocks are indexed as written
+        let mut writer = BgzfWriter::new(vec![]);
+        writer.write_all(&big).unwrap();
+        writer.flush().unwrap();
+        let gzi = writer.gzi().clone();
+        let compressed = writer.finish().unwrap();
+        assert_eq!(gzi.blocks.len(), 1);
+        assert_eq!(gzi.block_of(big.len() as u64 - 1).1, BGZF_BLOCK_SIZE as u64);
+        assert_eq!(
+            GziIndex::build(&mut &compressed[..]).unwrap().blocks[0],
+            gzi.blocks[0]
+        );
+    }
+}
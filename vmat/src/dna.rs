@@ -1,4 +1,5 @@
 use crate::alphabet::Alphabet;
+use crate::simd;
 use crate::xstring::{XStrHasher, XStrRollHasher};
 use std::ops::Index;
 use std::rc::Rc;
@@ -8,6 +9,12 @@ pub struct DNAAlphabet {
     ranks: [usize; 256],
 }
 
+impl Default for DNAAlphabet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl DNAAlphabet {
     const NULL_RK: usize = 255usize;
     const USIZE_MASK: usize = 255usize;
@@ -67,7 +74,7 @@ impl Alphabet for DNAAlphabet {
     fn ord(&self, chr: &Self::CharType) -> Option<usize> {
         match self.ranks[Self::USIZE_MASK & *chr as usize] {
             Self::NULL_RK => None,
-            r => Some(r)
+            r => Some(r),
         }
     }
 }
@@ -79,6 +86,21 @@ impl Index<usize> for DNAAlphabet {
     }
 }
 
+/// Complement of a DNA base, of either case. Other chars are kept as they are.
+pub fn complement(c: u8) -> u8 {
+    match c {
+        b'A' => b'T',
+        b'C' => b'G',
+        b'G' => b'C',
+        b'T' => b'A',
+        b'a' => b't',
+        b'c' => b'g',
+        b'g' => b'c',
+        b't' => b'a',
+        _ => c,
+    }
+}
+
 pub struct DNAHasher {
     ab: Rc<DNAAlphabet>,
 }
@@ -94,19 +116,44 @@ impl XStrHasher for DNAHasher {
     fn hash(&self, s: &[Self::CharType]) -> u64 {
         let mut h: u64 = 0;
         for c in s {
-            h = (h << 2)
-                | self.ab.ord(c).expect("Char not in DNA alphabet") as u64;
+            h = (h << 2) | self.ab.ord(c).expect("Char not in DNA alphabet") as u64;
         }
         h
     }
+
+    fn chars(&self) -> Vec<u8> {
+        self.ab.letters.to_vec()
+    }
+
+    fn accepts(&self, c: &u8) -> bool {
+        self.ab.ord(c).is_some()
+    }
 }
 
 impl XStrRollHasher for DNAHasher {
-    fn roll_hash(&self, s: &[Self::CharType], h: u64, c: u8) -> u64 {
+    fn roll_hash(&self, _s: &[Self::CharType], h: u64, c: u8) -> u64 {
         (h << 2) | self.ab.ord(&c).expect("Char not in DNA alphabet") as u64
     }
+
+    /// Computes the hashes of the `k`-mers, for `k <= 32`, in bulk with
+    /// vectorised kernels. The hashes keep the last `2k` bits of the rolled
+    /// hashes, so they are the same as those of a `KmerXStrLexHasher` over
+    /// the same alphabet.
+    fn kmer_hashes(&self, s: &[u8], k: usize, hashes: &mut Vec<u64>) {
+        assert!(k > 0 && k <= 32);
+        if s.len() < k {
+            hashes.clear();
+            return;
+        }
+        let mut codes = vec![0; s.len()];
+        if let Some(i) = simd::encode_2bit(&self.ab.letters, s, &mut codes) {
+            panic!("Char {:?} not in DNA alphabet", s[i] as char);
+        }
+        simd::hash_kmers_2bit(&codes, k, hashes);
+    }
 }
 
+#[cfg(test)]
 mod tests {
     use super::*;
 
@@ -120,16 +167,32 @@ mod tests {
     #[test]
     fn test_dna_ab_chr() {
         let ab = DNAAlphabet::new();
-        assert_eq!(*ab.chr(0).unwrap(), 'A' as u8);
-        assert_eq!(*ab.chr(1).unwrap(), 'C' as u8);
+        assert_eq!(*ab.chr(0).unwrap(), b'A');
+        assert_eq!(*ab.chr(1).unwrap(), b'C');
         assert_eq!(ab.chr(6), None);
     }
 
     #[test]
     fn test_dna_ab_ord() {
         let ab = DNAAlphabet::new();
-        assert_eq!(ab.ord(&('A' as u8),).unwrap(), 0);
-        assert_eq!(ab.ord(&('G' as u8),).unwrap(), 2);
-        assert_eq!(ab.ord(&('_' as u8)), None);
+        assert_eq!(ab.ord(&b'A').unwrap(), 0);
+        assert_eq!(ab.ord(&b'G').unwrap(), 2);
+        assert_eq!(ab.ord(&b'_'), None);
+    }
+
+    #[test]
+    fn test_dna_kmer_hashes() {
+        use crate::xstring::KmerXStrLexHasher;
+        let ab = Rc::new(DNAAlphabet::new_with_permutation(b"TGCA"));
+        let seq: Vec<u8> = b"ACGTTGCAAGT".iter().cycle().take(200).copied().collect();
+        let dna = DNAHasher::new(Rc::clone(&ab));
+        for &k in [1, 4, 13, 32].iter() {
+            let lex = KmerXStrLexHasher::new(Rc::clone(&ab), k);
+            let (mut expected, mut hashes) = (vec![], vec![]);
+            lex.kmer_hashes(&seq, k, &mut expected);
+            dna.kmer_hashes(&seq, k, &mut hashes);
+            assert_eq!(hashes, expected);
+            assert_eq!(dna.hash(&seq[..k]), expected[0]);
+        }
     }
 }
@@ -0,0 +1,282 @@
+//! Elias-Fano encoding of non-decreasing sequences of integers.
+//!
+//! Each value is split into its `l` low bits, stored verbatim, and its high
+//! bits, stored in unary as the gaps between the ones of a [`BitVector`].
+//! A sequence of `n` values below `u` takes about `n * (2 + log2(u/n))` bits.
+
+use std::io::{self, Read, Write};
+
+use crate::binio;
+use crate::bitvec::BitVector;
+
+/// Non-decreasing sequence of integers in Elias-Fano encoding, supporting
+/// random access and predecessor and successor queries.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EliasFano {
+    len: usize,
+    /// Number of low bits of each value
+    low_bits: usize,
+    /// Low bits of the values, packed
+    low: Vec<u64>,
+    /// One for each value at the position of its high bits plus its index
+    high: BitVector,
+}
+
+impl EliasFano {
+    /// Encodes `values`, which must be sorted in non-decreasing order
+    /// and smaller than `usize::MAX`.
+    pub fn new(values: &[usize]) -> Self {
+        assert!(values.windows(2).all(|w| w[0] <= w[1]));
+        let len = values.len();
+        let universe = values.last().map_or(0, |&v| {
+            v.checked_add(1)
+                .expect("Elias-Fano values must be smaller than usize::MAX")
+        });
+        let low_bits = if universe > len {
+            (universe / len.max(1)).ilog2() as usize
+        } else {
+            0
+        };
+        let mut low = vec![0u64; (len * low_bits).div_ceil(64)];
+        let nhigh = (universe >> low_bits) + len + 1;
+        let mut high = vec![0u64; nhigh.div_ceil(64)];
+        for (i, &v) in values.iter().enumerate() {
+            let h = (v >> low_bits) + i;
+            high[h / 64] |= 1 << (h % 64);
+            set_bits(&mut low, i * low_bits, low_bits, v as u64);
+        }
+        EliasFano {
+            len,
+            low_bits,
+            low,
+            high: BitVector::from_words(high, nhigh),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    #[inline]
+    fn low(&self, i: usize) -> usize {
+        get_bits(&self.low, i * self.low_bits, self.low_bits) as usize
+    }
+
+    /// The `i`-th value.
+    pub fn get(&self, i: usize) -> usize {
+        assert!(i < self.len);
+        let h = self.high.select1(i).unwrap() - i;
+        (h << self.low_bits) | self.low(i)
+    }
+
+    /// Iterates over the values.
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.len).map(move |i| self.get(i))
+    }
+
+    /// Indices `[start,end)` of the values whose high bits are `h`.
+    fn bucket(&self, h: usize) -> (usize, usize) {
+        let start = if h == 0 {
+            0
+        } else {
+            self.high.select0(h - 1).map_or(self.len, |p| p + 1 - h)
+        };
+        let end = self.high.select0(h).map_or(self.len, |p| p - h);
+        (start, end)
+    }
+
+    /// Index of the first value of the bucket of `x` whose low bits are
+    /// not accepted by `before`, which must hold for a prefix of them.
+    fn search<F>(&self, x: usize, before: F) -> usize
+    where
+        F: Fn(usize) -> bool,
+    {
+        let (mut lo, mut hi) = self.bucket(x >> self.low_bits);
+        while lo < hi {
+            let m = (lo + hi) / 2;
+            if before(self.low(m)) {
+                lo = m + 1;
+            } else {
+                hi = m;
+            }
+        }
+        lo
+    }
+
+    /// Index and value of the last value `<= x`, if any.
+    pub fn pred(&self, x: usize) -> Option<(usize, usize)> {
+        let xl = x & ((1 << self.low_bits) - 1);
+        let i = self.search(x, |l| l <= xl);
+        if i == 0 {
+            None
+        } else {
+            Some((i - 1, self.get(i - 1)))
+        }
+    }
+
+    /// Index and value of the first value `>= x`, if any.
+    pub fn succ(&self, x: usize) -> Option<(usize, usize)> {
+        let xl = x & ((1 << self.low_bits) - 1);
+        let i = self.search(x, |l| l < xl);
+        if i == self.len {
+            None
+        } else {
+            Some((i, self.get(i)))
+        }
+    }
+
+    /// Writes the sequence to `dst` in binary format.
+    pub fn save<W: Write>(&self, dst: &mut W) -> Result<(), io::Error> {
+        binio::write_usize(dst, self.len)?;
+        binio::write_usize(dst, self.low_bits)?;
+        for &w in self.low.iter() {
+            binio::write_u64(dst, w)?;
+        }
+        self.high.save(dst)
+    }
+
+    /// Reads a sequence saved with [`EliasFano::save`] from `src`.
+    pub fn load<R: Read>(src: &mut R) -> Result<Self, io::Error> {
+        let len = binio::read_usize(src)?;
+        let low_bits = binio::read_usize(src)?;
+        if low_bits >= 64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Invalid Elias-Fano low bit count",
+            ));
+        }
+        let nlow = len
+            .checked_mul(low_bits)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid length"))?
+            .div_ceil(64);
+        let mut low = binio::with_capacity(nlow);
+        for _ in 0..nlow {
+            low.push(binio::read_u64(src)?);
+        }
+        let high = BitVector::load(src)?;
+        if high.count_ones() != len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Invalid Elias-Fano high bits",
+            ));
+        }
+        Ok(EliasFano {
+            len,
+            low_bits,
+            low,
+            high,
+        })
+    }
+}
+
+/// Sets the `n < 64` bits of `words` at bit offset `pos` to the low bits of `v`.
+fn set_bits(words: &mut [u64], pos: usize, n: usize, v: u64) {
+    if n == 0 {
+        return;
+    }
+    let v = v & ((1 << n) - 1);
+    let (w, b) = (pos / 64, pos % 64);
+    words[w] |= v << b;
+    if b + n > 64 {
+        words[w + 1] |= v >> (64 - b);
+    }
+}
+
+/// Returns the `n < 64` bits of `words` at bit offset `pos`.
+fn get_bits(words: &[u64], pos: usize, n: usize) -> u64 {
+    if n == 0 {
+        return 0;
+    }
+    let (w, b) = (pos / 64, pos % 64);
+    let mut v = words[w] >> b;
+    if b + n > 64 {
+        v |= words[w + 1] << (64 - b);
+    }
+    v & ((1 << n) - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn naive_pred(v: &[usize], x: usize) -> Option<(usize, usize)> {
+        let i = v.partition_point(|&y| y <= x);
+        if i == 0 {
+            None
+        } else {
+            Some((i - 1, v[i - 1]))
+        }
+    }
+
+    fn naive_succ(v: &[usize], x: usize) -> Option<(usize, usize)> {
+        let i = v.partition_point(|&y| y < x);
+        v.get(i).map(|&y| (i, y))
+    }
+
+    #[test]
+    fn test_elias_fano() {
+        let mut x = 17u64;
+        let mut rand = |m: u64| {
+            x = x
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            ((x >> 33) % m) as usize
+        };
+        let mut cases: Vec<Vec<usize>> = vec![vec![], vec![0], vec![5], vec![3, 3, 3, 3]];
+        cases.push(vec![usize::MAX - 1]);
+        cases.push(vec![0, 7, usize::MAX >> 1, usize::MAX - 1]);
+        cases.push((0..1000).collect());
+        cases.push((0..300).map(|i| i * 1000).collect());
+        for &(n, gap) in [(100, 2), (2000, 50), (500, 100_000), (3000, 1)].iter() {
+            let mut v = 0;
+            cases.push(
+                (0..n)
+                    .map(|_| {
+                        v += rand(gap);
+                        v
+                    })
+                    .collect(),
+            );
+        }
+        for values in cases.iter() {
+            let ef = EliasFano::new(values);
+            assert_eq!(ef.len(), values.len());
+            assert_eq!(ef.iter().collect::<Vec<_>>(), *values);
+            let max = values.last().map_or(10, |&m| m.saturating_add(10));
+            let mut queries: Vec<usize> = (0..200).map(|_| rand(max as u64)).collect();
+            for &v in values.iter().step_by(7) {
+                queries.extend([v.saturating_sub(1), v, v + 1].iter());
+            }
+            queries.push(usize::MAX >> 1);
+            for &q in queries.iter() {
+                assert_eq!(ef.pred(q), naive_pred(values, q), "pred {}", q);
+                assert_eq!(ef.succ(q), naive_succ(values, q), "succ {}", q);
+            }
+            let mut buf = vec![];
+            ef.save(&mut buf).unwrap();
+            assert_eq!(EliasFano::load(&mut &buf[..]).unwrap(), ef);
+        }
+    }
+
+    #[test]
+    fn test_elias_fano_load_garbled() {
+        let ef = EliasFano::new(&[1, 4, 9, 16, 25]);
+        let mut buf = vec![];
+        ef.save(&mut buf).unwrap();
+        // a garbled length fails at the end of the file
+        let mut garbled = buf.clone();
+        garbled[..8].copy_from_slice(&(1u64 << 58).to_le_bytes());
+        let err = EliasFano::load(&mut &garbled[..]).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        // so does a garbled length of the high bits
+        let mut garbled = buf.clone();
+        let high_off = 16 + 8 * ef.low.len();
+        garbled[high_off..high_off + 8].copy_from_slice(&(1u64 << 60).to_le_bytes());
+        let err = EliasFano::load(&mut &garbled[..]).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
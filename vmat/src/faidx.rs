@@ -0,0 +1,288 @@
+//! FASTA index (`.fai`) files, compatible with `samtools faidx`.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Entry of a FASTA index.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FaiRecord {
+    /// Sequence name: the description line up to the first whitespace
+    pub name: String,
+    /// Sequence length
+    pub length: u64,
+    /// Byte offset of the first residue of the sequence
+    pub offset: u64,
+    /// Number of residues per line
+    pub linebases: u64,
+    /// Number of bytes per line, including EOL chars
+    pub linewidth: u64,
+}
+
+impl FaiRecord {
+    /// Byte offset of the residue at (0-based) position `pos`.
+    pub fn offset_of(&self, pos: u64) -> u64 {
+        if self.linebases == 0 {
+            return self.offset;
+        }
+        self.offset + (pos / self.linebases) * self.linewidth + pos % self.linebases
+    }
+}
+
+/// FASTA index.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FaiIndex {
+    records: Vec<FaiRecord>,
+    by_name: HashMap<String, usize>,
+}
+
+/// Path of the index file of `path`, i.e. `path` with `ext` appended.
+pub fn index_path<P: AsRef<Path>>(path: P, ext: &str) -> PathBuf {
+    let mut index_path = path.as_ref().as_os_str().to_owned();
+    index_path.push(ext);
+    PathBuf::from(index_path)
+}
+
+fn invalid_data(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+impl FaiIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn push(&mut self, rec: FaiRecord) -> Result<(), Error> {
+        if self.by_name.contains_key(&rec.name) {
+            return Err(invalid_data(format!(
+                "Duplicate sequence name {}",
+                rec.name
+            )));
+        }
+        self.by_name.insert(rec.name.clone(), self.records.len());
+        self.records.push(rec);
+        Ok(())
+    }
+
+    /// Builds the index of the FASTA contents of `src`.
+    ///
+    /// As in samtools, all the lines of a sequence but the last must have
+    /// the same length, and blank lines are only allowed at its end.
+    pub fn build<R: Read>(src: R) -> Result<Self, Error> {
+        let mut reader = BufReader::new(src);
+        let mut fai = FaiIndex::new();
+        let mut line: Vec<u8> = Vec::new();
+        let mut offset = 0u64;
+        let mut lineno = 0usize;
+        let mut cur: Option<FaiRecord> = None;
+        // the current sequence had a short or blank line, hence must end
+        let mut ended = false;
+        loop {
+            line.clear();
+            let n = reader.read_until(b'\n', &mut line)? as u64;
+            if n == 0 {
+                break;
+            }
+            offset += n;
+            lineno += 1;
+            if line[0] == b'>' {
+                if let Some(rec) = cur.take() {
+                    fai.push(rec)?;
+                }
+                let name = line[1..]
+                    .split(|c| c.is_ascii_whitespace())
+                    .next()
+                    .unwrap_or(&[]);
+                cur = Some(FaiRecord {
+                    name: String::from_utf8_lossy(name).into_owned(),
+                    length: 0,
+                    offset,
+                    linebases: 0,
+                    linewidth: 0,
+                });
+                ended = false;
+                continue;
+            }
+            let rec = match cur.as_mut() {
+                Some(rec) => rec,
+                None if line.iter().all(u8::is_ascii_whitespace) => continue,
+                None => {
+                    return Err(invalid_data(format!(
+                        "Expected '>' at start of FASTA record at line {}",
+                        lineno
+                    )))
+                }
+            };
+            let bases = line
+                .iter()
+                .rev()
+                .skip_while(|&&c| c == b'\n' || c == b'\r')
+                .count() as u64;
+            if bases == 0 {
+                ended = true;
+                continue;
+            }
+            if ended {
+                return Err(invalid_data(format!(
+                    "Different line length in sequence {} at line {}",
+                    rec.name, lineno
+                )));
+            }
+            // the last line of the file may have no newline
+            let newline = line.last() == Some(&b'\n');
+            if rec.linebases == 0 {
+                rec.linebases = bases;
+                rec.linewidth = n;
+            } else if bases > rec.linebases
+                || (newline && n - bases != rec.linewidth - rec.linebases)
+            {
+                return Err(invalid_data(format!(
+                    "Different line length in sequence {} at line {}",
+                    rec.name, lineno
+                )));
+            }
+            ended = bases < rec.linebases;
+            rec.length += bases;
+        }
+        if let Some(rec) = cur.take() {
+            fai.push(rec)?;
+        }
+        Ok(fai)
+    }
+
+    /// Builds the index of the FASTA file at `path`,
+    /// which may be gzip, BGZF or zstd compressed.
+    pub fn build_from_path<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::build(crate::compress::Decoder::new_from_path(path)?)
+    }
+
+    /// Reads a `.fai` file.
+    pub fn read<R: Read>(src: R) -> Result<Self, Error> {
+        let mut fai = FaiIndex::new();
+        for (i, line) in BufReader::new(src).lines().enumerate() {
+            let line = line?;
+            let fields: Vec<&str> = line.trim_end().split('\t').collect();
+            if fields.len() < 5 {
+                return Err(invalid_data(format!(
+                    "Expected 5 fields at line {} of FASTA index",
+                    i + 1
+                )));
+            }
+            let num = |j: usize| {
+                fields[j].parse::<u64>().map_err(|_| {
+                    invalid_data(format!(
+                        "Invalid number {} at line {} of FASTA index",
+                        fields[j],
+                        i + 1
+                    ))
+                })
+            };
+            fai.push(FaiRecord {
+                name: String::from(fields[0]),
+                length: num(1)?,
+                offset: num(2)?,
+                linebases: num(3)?,
+                linewidth: num(4)?,
+            })?;
+        }
+        Ok(fai)
+    }
+
+    /// Reads the `.fai` index of the FASTA file at `path`.
+    pub fn read_from_path<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::read(File::open(index_path(path, ".fai"))?)
+    }
+
+    /// Writes the index in `.fai` format.
+    pub fn write<W: Write>(&self, dst: &mut W) -> Result<(), Error> {
+        for rec in self.records.iter() {
+            writeln!(
+                dst,
+                "{}\t{}\t{}\t{}\t{}",
+                rec.name, rec.length, rec.offset, rec.linebases, rec.linewidth
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn records(&self) -> &[FaiRecord] {
+        &self.records
+    }
+
+    pub fn get(&self, name: &str) -> Option<&FaiRecord> {
+        self.by_name.get(name).map(|&i| &self.records[i])
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // same as `samtools faidx`
+    const FASTA_FILE: &[u8] = b">seq1 first
+ACGTACGTAC
+GTACGTACGT
+ACG
+>seq2
+TTTT\r
+TT\r
+>seq3
+>seq4\tlast
+GGGGG
+GGGGG
+
+";
+    const FAI_FILE: &str = "seq1\t23\t12\t10\t11
+seq2\t6\t44\t4\t6
+seq3\t0\t60\t0\t0
+seq4\t10\t71\t5\t6
+";
+
+    #[test]
+    fn test_build_write_read() {
+        let fai = FaiIndex::build(FASTA_FILE).unwrap();
+        assert_eq!(fai.len(), 4);
+        let mut out = vec![];
+        fai.write(&mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), FAI_FILE);
+        assert_eq!(FaiIndex::read(FAI_FILE.as_bytes()).unwrap(), fai);
+
+        let rec = fai.get("seq1").unwrap();
+        assert_eq!(FASTA_FILE[rec.offset_of(0) as usize], b'A');
+        assert_eq!(FASTA_FILE[rec.offset_of(10) as usize], b'G');
+        assert_eq!(FASTA_FILE[rec.offset_of(22) as usize], b'G');
+        assert_eq!(rec.offset_of(22), 12 + 22 + 2);
+        assert!(fai.get("seq5").is_none());
+
+        // the last line may have no newline
+        for &(fasta, fai_file) in [
+            (&b">s\nACGT\nAC"[..], "s\t6\t3\t4\t5\n"),
+            (&b">s\nACGT\nACGT"[..], "s\t8\t3\t4\t5\n"),
+            (&b">s\nACGT"[..], "s\t4\t3\t4\t4\n"),
+        ]
+        .iter()
+        {
+            let mut out = vec![];
+            FaiIndex::build(fasta).unwrap().write(&mut out).unwrap();
+            assert_eq!(String::from_utf8(out).unwrap(), fai_file);
+        }
+    }
+
+    #[test]
+    fn test_build_malformed() {
+        assert!(FaiIndex::build(&b">s\nACGT\nAC\nACGT\n"[..]).is_err());
+        assert!(FaiIndex::build(&b">s\nACGT\nACGTA\n"[..]).is_err());
+        assert!(FaiIndex::build(&b">s\nACGT\n\nACGT\n"[..]).is_err());
+        assert!(FaiIndex::build(&b">s\nAC\n>s\nAC\n"[..]).is_err());
+        assert!(FaiIndex::build(&b"AC\n"[..]).is_err());
+    }
+}
@@ -1,65 +1,686 @@
+use std::borrow::Cow;
+use std::error;
+use std::fmt;
 use std::fs::File;
-use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::io::{self, BufRead, BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
 use std::path::Path;
 
+use crate::compress::{Compression, Decoder, Encoder, RandomAccess};
+use crate::faidx::FaiIndex;
+use crate::xstream::{end_read_until, take_pushback, DelimiterMode, ReadStatus, XStream};
 use crate::xstring::XString;
 
+/// Error reading a FASTA source.
+#[derive(Debug)]
+pub enum FastaError {
+    /// Error reading from the underlying source
+    Io(io::Error),
+    /// Malformed input at the given (1-based) line and (0-based) byte offset
+    Malformed {
+        line: usize,
+        offset: u64,
+        msg: String,
+    },
+}
+
+impl fmt::Display for FastaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FastaError::Io(e) => write!(f, "FASTA read error: {}", e),
+            FastaError::Malformed { line, offset, msg } => write!(
+                f,
+                "Malformed FASTA at line {} (byte offset {}): {}",
+                line, offset, msg
+            ),
+        }
+    }
+}
+
+impl error::Error for FastaError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            FastaError::Io(e) => Some(e),
+            FastaError::Malformed { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for FastaError {
+    fn from(e: io::Error) -> Self {
+        FastaError::Io(e)
+    }
+}
+
+/// Malformed input is reported as `InvalidData`, wrapping the `FastaError`.
+impl From<FastaError> for io::Error {
+    fn from(e: FastaError) -> Self {
+        match e {
+            FastaError::Io(e) => e,
+            e => io::Error::new(ErrorKind::InvalidData, e),
+        }
+    }
+}
+
+/// A FASTA record borrowed from the buffers of a `FastaReader`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FastaRecordRef<'a> {
+    /// Description line, without the starting `>`
+    pub desc: &'a [u8],
+    /// The sequence
+    pub seq: &'a [u8],
+}
+
+impl<'a> FastaRecordRef<'a> {
+    /// The sequence name, i.e. the description up to the first whitespace.
+    pub fn name(&self) -> &'a [u8] {
+        self.desc
+            .split(|c| c.is_ascii_whitespace())
+            .next()
+            .unwrap_or(&[])
+    }
+
+    /// Copies the record into an owned `(desc, seq)` pair.
+    pub fn into_owned(self) -> (String, XString<u8>) {
+        (
+            String::from_utf8_lossy(self.desc).into_owned(),
+            XString::from(self.seq),
+        )
+    }
+}
+
+/**
+ * FASTA reader.
+ *
+ * By default the reader is tolerant: it accepts LF and CRLF line endings,
+ * a missing EOL at the end of the file, blank lines, `;` comment lines and
+ * whitespace within sequence lines or before `>` and `;`, none of which
+ * make it into the records.
+ * In strict mode all of these are rejected with a `FastaError`.
+ */
 pub struct FastaReader<R>
 where
     R: Read,
 {
     reader: BufReader<R>,
+    strict: bool,
+    line: Vec<u8>,   // last line read, without EOL
+    seq: Vec<u8>,    // sequence of last record read
+    lineno: usize,   // number of lines read
+    line_start: u64, // byte offset of last line read
+    offset: u64,     // number of bytes read
+    fai: Option<FaiIndex>,
 }
 
-impl FastaReader<File> {
+impl FastaReader<Decoder<File>> {
+    /// Opens a FASTA file, which may be gzip, BGZF or zstd compressed.
     pub fn new_from_path<P: AsRef<Path>>(path: P) -> Result<Self, std::io::Error> {
-        Ok(FastaReader {
-            reader: BufReader::new(File::open(path)?),
-        })
+        Ok(Self::from_bufreader(BufReader::new(
+            Decoder::new_from_path(path)?,
+        )))
+    }
+}
+
+impl FastaReader<RandomAccess<File>> {
+    /// Opens an uncompressed or BGZF compressed FASTA file for random access
+    /// with `fetch`.
+    /// The `.fai` index (and the `.gzi` index of BGZF files) is read from
+    /// the files next to it if they exist, or else built by scanning the file.
+    pub fn new_indexed_from_path<P: AsRef<Path>>(path: P) -> Result<Self, std::io::Error> {
+        let fai = match FaiIndex::read_from_path(&path) {
+            Ok(fai) => fai,
+            Err(e) if e.kind() == ErrorKind::NotFound => FaiIndex::build_from_path(&path)?,
+            Err(e) => return Err(e),
+        };
+        let mut reader = Self::new(RandomAccess::new_from_path(&path)?)?;
+        reader.set_fai(fai);
+        Ok(reader)
     }
 }
 
 impl<R> FastaReader<R>
 where
-    R: Read + Seek,
+    R: Read,
 {
     pub fn new(src: R) -> Result<Self, std::io::Error> {
-        Ok(FastaReader {
-            reader: BufReader::new(src),
-        })
+        Ok(Self::from_bufreader(BufReader::new(src)))
+    }
+
+    pub(crate) fn from_bufreader(reader: BufReader<R>) -> Self {
+        FastaReader {
+            reader,
+            strict: false,
+            line: Vec::new(),
+            seq: Vec::new(),
+            lineno: 0,
+            line_start: 0,
+            offset: 0,
+            fai: None,
+        }
+    }
+
+    /// Enables or disables strict parsing.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    /// Sets the `.fai` index used by `fetch`.
+    pub fn set_fai(&mut self, fai: FaiIndex) {
+        self.fai = Some(fai);
+    }
+
+    pub fn fai(&self) -> Option<&FaiIndex> {
+        self.fai.as_ref()
+    }
+
+    /// Number of lines read so far.
+    pub fn lines_read(&self) -> usize {
+        self.lineno
+    }
+
+    fn error_at(&self, col: usize, msg: &str) -> FastaError {
+        FastaError::Malformed {
+            line: self.lineno,
+            offset: self.line_start + col as u64,
+            msg: String::from(msg),
+        }
+    }
+
+    /// Consumes the whitespace at the start of the next line, if not strict,
+    /// so that the line is classified by its first other char.
+    fn skip_leading_whitespace(&mut self) -> Result<(), io::Error> {
+        if self.strict {
+            return Ok(());
+        }
+        loop {
+            let buf = self.reader.fill_buf()?;
+            let n = buf
+                .iter()
+                .take_while(|&&c| c != b'\n' && c.is_ascii_whitespace())
+                .count();
+            let more = n > 0 && n == buf.len();
+            self.reader.consume(n);
+            self.offset += n as u64;
+            if !more {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Reads the next line, trimming its EOL, either into `self.line`
+    /// or appended to `self.seq` if `to_seq`.
+    /// Returns the start of the line in the buffer, or None on EOF.
+    fn read_line(&mut self, to_seq: bool) -> Result<Option<usize>, FastaError> {
+        self.line_start = self.offset;
+        let buf = if to_seq {
+            &mut self.seq
+        } else {
+            self.line.clear();
+            &mut self.line
+        };
+        let start = buf.len();
+        let n = self.reader.read_until(b'\n', buf)?;
+        if n == 0 {
+            return Ok(None);
+        }
+        self.lineno += 1;
+        self.offset += n as u64;
+        let has_eol = buf.last() == Some(&b'\n');
+        if has_eol {
+            buf.pop();
+        }
+        let has_cr = buf.len() > start && buf.last() == Some(&b'\r');
+        if has_cr {
+            buf.pop();
+        }
+        if self.strict && !has_eol {
+            return Err(self.error_at(n, "missing end of line at end of file"));
+        }
+        if self.strict && has_cr {
+            return Err(self.error_at(n - 2, "CRLF line ending"));
+        }
+        Ok(Some(start))
+    }
+
+    /// Reads the next FASTA record into the internal buffers of the reader,
+    /// which are reused from record to record, and returns a view of it.
+    /// The record is valid until the next call.
+    ///
+    /// EOL chars are not included in `desc` or `seq`
+    pub fn next_ref(&mut self) -> Result<Option<FastaRecordRef<'_>>, FastaError> {
+        // read next id, skipping blank and comment lines
+        loop {
+            self.skip_leading_whitespace()?;
+            if self.read_line(false)?.is_none() {
+                return Ok(None);
+            }
+            match self.line.first() {
+                Some(b'>') => break,
+                Some(b';') if !self.strict => continue,
+                Some(b';') => return Err(self.error_at(0, "comment line")),
+                _ if self.strict && self.line.is_empty() => {
+                    return Err(self.error_at(0, "blank line"))
+                }
+                _ if !self.strict && self.line.iter().all(u8::is_ascii_whitespace) => continue,
+                _ => return Err(self.error_at(0, "expected '>' at start of record")),
+            }
+        }
+        self.seq.clear();
+        loop {
+            // peek at the next line without consuming it, so that
+            // the source need not be seekable
+            self.skip_leading_whitespace()?;
+            if self.reader.fill_buf()?.first() == Some(&b'>') {
+                // description of next sequence. leave it in the buffer
+                break;
+            }
+            let start = match self.read_line(true)? {
+                Some(start) => start,
+                None => break, // EOF
+            };
+            let line = &self.seq[start..];
+            if self.strict {
+                if line.is_empty() {
+                    return Err(self.error_at(0, "blank line"));
+                }
+                if let Some(col) = line.iter().position(|c| !c.is_ascii_graphic()) {
+                    return Err(self.error_at(
+                        col,
+                        &format!("invalid sequence char {:?}", line[col] as char),
+                    ));
+                }
+                if line[0] == b';' {
+                    return Err(self.error_at(0, "comment line"));
+                }
+            } else if line.first() == Some(&b';') {
+                self.seq.truncate(start);
+            } else {
+                // remove whitespace in place
+                let mut end = start;
+                for i in start..self.seq.len() {
+                    let c = self.seq[i];
+                    if !c.is_ascii_whitespace() {
+                        self.seq[end] = c;
+                        end += 1;
+                    }
+                }
+                self.seq.truncate(end);
+            }
+        }
+        Ok(Some(FastaRecordRef {
+            desc: &self.line[1..],
+            seq: &self.seq,
+        }))
     }
 
     /// Reads the next FASTA record as a `(desc, seq)` pair where
     /// * `desc` is a String with the record description line without the starting `>`
     /// * `seq` is the actual sequence as a XString<u8>
+    ///
     /// EOL chars are not included in `desc` or `seq`
-    pub fn next_as_xstring(&mut self) -> Result<Option<(String, XString<u8>)>, std::io::Error> {
-        // read next id
-        let mut desc = String::new();
-        let mut n = self.reader.read_line(&mut desc)?;
-        if n == 0 {
-            return Ok(None);
+    pub fn next_as_xstring(&mut self) -> Result<Option<(String, XString<u8>)>, FastaError> {
+        Ok(self.next_ref()?.map(FastaRecordRef::into_owned))
+    }
+}
+
+impl<R> FastaReader<R>
+where
+    R: Read + Seek,
+{
+    /// Fetches the residues in the (0-based) positions `[start,end)` of the
+    /// sequence `name`, seeking directly to them with the `.fai` index.
+    /// `end` is clipped to the sequence length.
+    /// The position of sequential reading with `next_as_xstring` is preserved.
+    pub fn fetch(&mut self, name: &str, start: u64, end: u64) -> Result<XString<u8>, io::Error> {
+        let rec = match self.fai.as_ref() {
+            Some(fai) => fai.get(name).ok_or_else(|| {
+                io::Error::new(
+                    ErrorKind::NotFound,
+                    format!("Sequence {} not found in FASTA index", name),
+                )
+            })?,
+            None => {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidInput,
+                    "FASTA index not set",
+                ))
+            }
+        };
+        let end = end.min(rec.length);
+        if start > end {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid region {}:{}-{}", name, start, end),
+            ));
         }
-        assert_eq!(desc.as_bytes()[0], '>' as u8);
-        desc.pop();
-        desc.remove(0);
-        let mut seq: Vec<u8> = Vec::new();
+        let (from, to) = (rec.offset_of(start), rec.offset_of(end));
+        let mut seq: Vec<u8> = Vec::with_capacity((end - start) as usize);
+        let mut raw: Vec<u8> = Vec::with_capacity((to - from) as usize);
+        let pos = self.reader.stream_position()?;
+        self.reader.seek(SeekFrom::Start(from))?;
+        let res = (&mut self.reader).take(to - from).read_to_end(&mut raw);
+        self.reader.seek(SeekFrom::Start(pos))?;
+        res?;
+        seq.extend(raw.iter().filter(|&&c| c != b'\n' && c != b'\r'));
+        if seq.len() as u64 != end - start {
+            return Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                format!("FASTA file is shorter than its index for {}", name),
+            ));
+        }
+        Ok(XString::from(seq))
+    }
+}
+
+/**
+ * Stream over the residues of the records of a FASTA source, without
+ * materialising whole records.
+ *
+ * `next_record` moves to the next record and returns its description.
+ * Then the stream yields the residues of that record, without EOL chars,
+ * whitespace or `;` comment lines, and reaches its end at the next
+ * record boundary. Like the tolerant `FastaReader`, the stream skips
+ * whitespace before `>` and `;`.
+ */
+pub struct FastaStream<R>
+where
+    R: Read,
+{
+    reader: BufReader<R>,
+    in_record: bool,
+    at_line_start: bool,
+    in_comment: bool,
+    lineno: usize,
+    offset: u64,
+    nrec: usize,
+    pushback: Vec<u8>,
+}
+
+impl FastaStream<Decoder<File>> {
+    /// Opens a FASTA file, which may be gzip, BGZF or zstd compressed.
+    pub fn new_from_path<P: AsRef<Path>>(path: P) -> Result<Self, std::io::Error> {
+        Self::new(Decoder::new_from_path(path)?)
+    }
+}
+
+impl<R> FastaStream<R>
+where
+    R: Read,
+{
+    pub fn new(src: R) -> Result<Self, std::io::Error> {
+        Ok(FastaStream {
+            reader: BufReader::new(src),
+            in_record: false,
+            at_line_start: true,
+            in_comment: false,
+            lineno: 0,
+            offset: 0,
+            nrec: 0,
+            pushback: Vec::new(),
+        })
+    }
+
+    /// Number of records started so far.
+    pub fn records_read(&self) -> usize {
+        self.nrec
+    }
+
+    /// Skips the rest of the current record and reads the description
+    /// (without the starting `>`) of the next one, if any.
+    /// Residues pushed back with `unget` are discarded.
+    pub fn next_record(&mut self) -> Result<Option<String>, FastaError> {
+        self.pushback.clear();
+        let mut skipped = [0u8; 4096];
+        while self.in_record {
+            self.read_residues(&mut skipped, None)?;
+        }
+        // skip blank and comment lines up to the next description
+        let mut line: Vec<u8> = Vec::new();
         loop {
-            n = self.reader.read_until(0xA, &mut seq)?;
+            line.clear();
+            let line_start = self.offset;
+            let n = self.reader.read_until(b'\n', &mut line)?;
             if n == 0 {
-                // EOF
+                return Ok(None);
+            }
+            self.lineno += 1;
+            self.offset += n as u64;
+            while let Some(b'\n') | Some(b'\r') = line.last() {
+                line.pop();
+            }
+            let lead = line.iter().take_while(|c| c.is_ascii_whitespace()).count();
+            line.drain(..lead);
+            match line.first() {
+                Some(b'>') => break,
+                Some(b';') => continue,
+                None => continue,
+                _ => {
+                    return Err(FastaError::Malformed {
+                        line: self.lineno,
+                        offset: line_start,
+                        msg: String::from("expected '>' at start of record"),
+                    })
+                }
+            }
+        }
+        self.in_record = true;
+        self.at_line_start = true;
+        self.nrec += 1;
+        Ok(Some(String::from_utf8_lossy(&line[1..]).into_owned()))
+    }
+
+    /// Reads residues of the current record into `buf`, stopping
+    /// before `delimiter` if any. Returns the number of residues read.
+    fn read_residues(&mut self, buf: &mut [u8], delimiter: Option<u8>) -> Result<usize, io::Error> {
+        let mut n = 0;
+        while n < buf.len() && self.in_record {
+            let avail = self.reader.fill_buf()?;
+            if avail.is_empty() {
+                self.in_record = false;
                 break;
-            } else if seq[seq.len() - n] == '>' as u8 {
-                // read description of next sequence. put it back
-                seq.truncate(seq.len() - n);
-                self.reader.seek(SeekFrom::Current(-(n as i64)))?;
+            }
+            let mut consumed = 0;
+            let mut stop = false;
+            for &c in avail.iter() {
+                if self.at_line_start && c != b'\n' && c.is_ascii_whitespace() {
+                    // leading whitespace
+                    consumed += 1;
+                    continue;
+                }
+                if self.at_line_start {
+                    if c == b'>' {
+                        // next record. leave its description in the buffer
+                        self.in_record = false;
+                        break;
+                    }
+                    self.in_comment = c == b';';
+                }
+                if c == b'\n' {
+                    consumed += 1;
+                    self.lineno += 1;
+                    self.at_line_start = true;
+                    self.in_comment = false;
+                    continue;
+                }
+                self.at_line_start = false;
+                if self.in_comment || c.is_ascii_whitespace() {
+                    consumed += 1;
+                    continue;
+                }
+                if Some(c) == delimiter {
+                    stop = true;
+                    break;
+                }
+                consumed += 1;
+                buf[n] = c;
+                n += 1;
+                if n == buf.len() {
+                    break;
+                }
+            }
+            self.reader.consume(consumed);
+            self.offset += consumed as u64;
+            if stop {
                 break;
-            } else {
-                // read one more line. trim EOL.
-                assert_eq!(seq.pop(), Some(0xA));
             }
         }
-        Ok(Some((desc, XString::from(seq))))
+        Ok(n)
+    }
+}
+
+impl<R> XStream for FastaStream<R>
+where
+    R: Read,
+{
+    type CharType = u8;
+
+    fn get(&mut self) -> Result<Option<u8>, std::io::Error> {
+        if let Some(c) = self.pushback.pop() {
+            return Ok(Some(c));
+        }
+        let mut c = [0u8; 1];
+        Ok(match self.read_residues(&mut c, None)? {
+            0 => None,
+            _ => Some(c[0]),
+        })
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, std::io::Error> {
+        let (n, _) = take_pushback(&mut self.pushback, buf, None);
+        Ok(n + self.read_residues(&mut buf[n..], None)?)
+    }
+
+    fn read_until(
+        &mut self,
+        buf: &mut [u8],
+        delimiter: u8,
+        mode: DelimiterMode,
+    ) -> Result<(usize, ReadStatus), std::io::Error> {
+        let (mut n, found) = take_pushback(&mut self.pushback, buf, Some(delimiter));
+        if !found {
+            n += self.read_residues(&mut buf[n..], Some(delimiter))?;
+        }
+        end_read_until(self, buf, n, delimiter, mode)
+    }
+
+    fn unget(&mut self, c: u8) {
+        self.pushback.push(c);
+    }
+}
+
+/// Description line formats of written records.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HeaderFormat {
+    /// The whole description
+    Full,
+    /// The sequence name, i.e. the description up to the first whitespace
+    Name,
+    /// The given prefix followed by the (1-based) record number
+    Serial(String),
+}
+
+impl HeaderFormat {
+    /// Formats the description `desc` of the `n`-th (1-based) record.
+    pub fn format<'a>(&self, desc: &'a str, n: usize) -> Cow<'a, str> {
+        match self {
+            HeaderFormat::Full => Cow::Borrowed(desc),
+            HeaderFormat::Name => Cow::Borrowed(desc.split_whitespace().next().unwrap_or("")),
+            HeaderFormat::Serial(prefix) => Cow::Owned(format!("{}{}", prefix, n)),
+        }
+    }
+}
+
+/// Writes the chars of `seq` to `dst` in lines of `width` chars at most,
+/// or in a single line if `width` is 0. Nothing is written for empty sequences.
+pub(crate) fn write_wrapped<W: Write>(dst: &mut W, seq: &[u8], width: usize) -> io::Result<()> {
+    let width = if width == 0 { seq.len().max(1) } else { width };
+    for line in seq.chunks(width) {
+        dst.write_all(line)?;
+        dst.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+/// FASTA writer.
+/// Sequences are wrapped in lines of 60 chars by default.
+pub struct FastaWriter<W>
+where
+    W: Write,
+{
+    writer: BufWriter<W>,
+    line_width: usize,
+    header: HeaderFormat,
+    nrec: usize,
+}
+
+impl FastaWriter<Encoder<File>> {
+    /// Creates a FASTA file with the given compression.
+    pub fn new_from_path<P: AsRef<Path>>(
+        path: P,
+        compression: Compression,
+    ) -> Result<Self, std::io::Error> {
+        Self::new(Encoder::new_from_path(path, compression)?)
+    }
+}
+
+impl<W> FastaWriter<Encoder<W>>
+where
+    W: Write,
+{
+    /// Writes the pending records, completes the compressed stream
+    /// and returns its destination.
+    pub fn finish(self) -> Result<W, std::io::Error> {
+        self.into_inner()?.finish()
+    }
+}
+
+impl<W> FastaWriter<W>
+where
+    W: Write,
+{
+    pub fn new(dst: W) -> Result<Self, std::io::Error> {
+        Ok(FastaWriter {
+            writer: BufWriter::new(dst),
+            line_width: 60,
+            header: HeaderFormat::Full,
+            nrec: 0,
+        })
+    }
+
+    /// Sets the maximum number of sequence chars per line.
+    /// Sequences are written in a single line if `width` is 0.
+    pub fn set_line_width(&mut self, width: usize) {
+        self.line_width = width;
+    }
+
+    pub fn set_header_format(&mut self, header: HeaderFormat) {
+        self.header = header;
+    }
+
+    /// Number of records written so far.
+    pub fn records_written(&self) -> usize {
+        self.nrec
+    }
+
+    /// Writes a FASTA record with description `desc` (without the starting `>`)
+    /// and sequence `seq`.
+    pub fn write_xstring(&mut self, desc: &str, seq: &XString<u8>) -> Result<(), std::io::Error> {
+        self.nrec += 1;
+        let header = self.header.format(desc, self.nrec);
+        writeln!(self.writer, ">{}", header)?;
+        write_wrapped(&mut self.writer, &seq[..], self.line_width)
+    }
+
+    pub fn flush(&mut self) -> Result<(), std::io::Error> {
+        self.writer.flush()
+    }
+
+    /// Writes the pending records and returns the destination.
+    pub fn into_inner(self) -> Result<W, std::io::Error> {
+        self.writer.into_inner().map_err(|e| e.into_error())
     }
 }
 
@@ -68,7 +689,7 @@ mod tests {
     use super::*;
     use std::io::{BufWriter, Write};
 
-    const FASTA_FILE: &'static [u8] = b">id1 desc
+    const FASTA_FILE: &[u8] = b">id1 desc
 AAAAAAAAAA
 AAAAAAAAAA
 AAAAAAAAAA
@@ -91,7 +712,7 @@ GGGGGGGGGG
     fn file_setup(filename: &str) {
         let f = File::create(filename).expect("Unabe to create the file");
         let mut writer = BufWriter::new(f);
-        writer.write(&FASTA_FILE);
+        writer.write_all(FASTA_FILE).unwrap();
     }
 
     fn file_teardown(filename: &str) {
@@ -101,10 +722,10 @@ GGGGGGGGGG
     #[test]
     fn read_fasta() {
         let filename = "read_fasta.fas";
-        file_setup(&filename);
+        file_setup(filename);
 
-        let mut reader = FastaReader::new_from_path(&filename)
-            .expect(&format!("Unable to open file {}", filename));
+        let mut reader = FastaReader::new_from_path(filename)
+            .unwrap_or_else(|_| panic!("Unable to open file {}", filename));
         let mut nseq = 0;
         while let Some((desc, s)) = reader
             .next_as_xstring()
@@ -114,6 +735,347 @@ GGGGGGGGGG
             nseq += 1;
         }
         assert_eq!(nseq, 3);
-        file_teardown(&filename);
+        file_teardown(filename);
+    }
+
+    #[test]
+    fn read_fasta_gz() {
+        use flate2::write::GzEncoder;
+
+        let filename = "read_fasta_gz.fas.gz";
+        let mut encoder = GzEncoder::new(
+            File::create(filename).expect("Unable to create the file"),
+            flate2::Compression::default(),
+        );
+        encoder
+            .write_all(FASTA_FILE)
+            .expect("Unable to write to file");
+        encoder.finish().expect("Unable to write to file");
+
+        let mut reader = FastaReader::new_from_path(filename)
+            .unwrap_or_else(|_| panic!("Unable to open file {}", filename));
+        let mut descs = vec![];
+        let mut total_len = 0;
+        while let Some((desc, s)) = reader
+            .next_as_xstring()
+            .expect("Unable to read from fasta file")
+        {
+            descs.push(desc);
+            total_len += s.len();
+        }
+        assert_eq!(descs, vec!["id1 desc", "id2", "id3 lots of Gs"]);
+        assert_eq!(total_len, 45 + 80 + 60);
+        file_teardown(filename);
+    }
+
+    /// A non-seekable source that returns at most `chunk` bytes per read,
+    /// so that headers are split across buffer refills.
+    struct Trickle<'a> {
+        src: &'a [u8],
+        chunk: usize,
+    }
+
+    impl<'a> Read for Trickle<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, std::io::Error> {
+            let n = self.chunk.min(buf.len()).min(self.src.len());
+            buf[..n].copy_from_slice(&self.src[..n]);
+            self.src = &self.src[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn read_fasta_unseekable() {
+        for chunk in 1..8 {
+            let src = Trickle {
+                src: FASTA_FILE,
+                chunk,
+            };
+            let mut reader = FastaReader::new(src).unwrap();
+            let (desc, s) = reader.next_as_xstring().unwrap().unwrap();
+            assert_eq!(desc, "id1 desc");
+            assert_eq!(&s[..], &[b'A'; 45][..]);
+            let (desc, s) = reader.next_as_xstring().unwrap().unwrap();
+            assert_eq!(desc, "id2");
+            assert_eq!(&s[..], &[b'C'; 80][..]);
+            let (desc, s) = reader.next_as_xstring().unwrap().unwrap();
+            assert_eq!(desc, "id3 lots of Gs");
+            assert_eq!(&s[..], &[b'G'; 60][..]);
+            assert!(reader.next_as_xstring().unwrap().is_none());
+        }
+    }
+
+    fn read_all(src: &[u8], strict: bool) -> Result<Vec<(String, Vec<u8>)>, FastaError> {
+        let mut reader = FastaReader::new(src).unwrap();
+        reader.set_strict(strict);
+        let mut recs = vec![];
+        while let Some((desc, s)) = reader.next_as_xstring()? {
+            recs.push((desc, s[..].to_vec()));
+        }
+        Ok(recs)
+    }
+
+    fn malformed_at(res: Result<Vec<(String, Vec<u8>)>, FastaError>) -> (usize, u64) {
+        match res {
+            Err(FastaError::Malformed { line, offset, .. }) => (line, offset),
+            r => panic!("expected malformed FASTA error, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn read_fasta_tolerant() {
+        let src = b"; comment\r\n\r\n>id1 desc\r\nAC GT\r\n\r\n;more\r\n  TT\t\r\n>id2\nGG";
+        let recs = read_all(src, false).unwrap();
+        assert_eq!(
+            recs,
+            vec![
+                (String::from("id1 desc"), b"ACGTTT".to_vec()),
+                (String::from("id2"), b"GG".to_vec()),
+            ]
+        );
+        assert!(read_all(b">id1\nACGT\n", true).is_ok());
+    }
+
+    #[test]
+    fn read_fasta_leading_whitespace() {
+        // a header with leading whitespace, and one which would be
+        // absorbed into the sequence of the previous record
+        let src = b"  >id1\nAC\n \t>id2 desc\n  ;c\n GT\n \n>id3\nTT\n";
+        let expected = vec![
+            (String::from("id1"), b"AC".to_vec()),
+            (String::from("id2 desc"), b"GT".to_vec()),
+            (String::from("id3"), b"TT".to_vec()),
+        ];
+        assert_eq!(read_all(src, false).unwrap(), expected);
+        assert_eq!(malformed_at(read_all(src, true)), (1, 0));
+        assert_eq!(malformed_at(read_all(b">a\nAC\n >b\nGT\n", true)), (3, 6));
+
+        let mut stream = FastaStream::new(&src[..]).unwrap();
+        let mut recs = vec![];
+        while let Some(desc) = stream.next_record().unwrap() {
+            let mut seq = XString::new();
+            stream
+                .read_until_xstring(&mut seq, b'\n', DelimiterMode::Exclusive)
+                .unwrap();
+            recs.push((desc, seq[..].to_vec()));
+        }
+        assert_eq!(recs, expected);
+    }
+
+    #[test]
+    fn read_fasta_strict() {
+        // missing header
+        assert_eq!(malformed_at(read_all(b"ACGT\n", false)), (1, 0));
+        assert_eq!(malformed_at(read_all(b"\n;c\n>a\nAC\n", true)), (1, 0));
+        assert_eq!(malformed_at(read_all(b";c\n>a\nAC\n", true)), (1, 0));
+        assert_eq!(malformed_at(read_all(b">a\nAC\n\nGT\n", true)), (3, 6));
+        assert_eq!(malformed_at(read_all(b">a\r\nAC\n", true)), (1, 2));
+        assert_eq!(malformed_at(read_all(b">a\nAC\n A\n", true)), (3, 6));
+        assert_eq!(malformed_at(read_all(b">a\nAC\n>b\nGT", true)), (4, 11));
+        let e = io::Error::from(read_all(b">a\nA C\n", true).unwrap_err());
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+        assert_eq!(
+            e.to_string(),
+            "Malformed FASTA at line 2 (byte offset 4): invalid sequence char ' '"
+        );
+    }
+
+    #[test]
+    fn fetch_fasta() {
+        let mut fai_src = vec![];
+        let fai = FaiIndex::build(FASTA_FILE).unwrap();
+        fai.write(&mut fai_src).unwrap();
+        let mut reader = FastaReader::new(io::Cursor::new(FASTA_FILE)).unwrap();
+        assert!(reader.fetch("id1", 0, 1).is_err());
+        reader.set_fai(FaiIndex::read(&fai_src[..]).unwrap());
+        assert_eq!(reader.next_as_xstring().unwrap().unwrap().0, "id1 desc");
+        assert_eq!(&reader.fetch("id1", 8, 12).unwrap()[..], b"AAAA");
+        assert_eq!(&reader.fetch("id3", 55, 100).unwrap()[..], b"GGGGG");
+        assert_eq!(&reader.fetch("id2", 20, 20).unwrap()[..], b"");
+        assert!(reader.fetch("id4", 0, 1).is_err());
+        assert!(reader.fetch("id2", 10, 5).is_err());
+        // sequential reading is not disturbed
+        assert_eq!(reader.next_as_xstring().unwrap().unwrap().0, "id2");
+    }
+
+    #[test]
+    fn fetch_fasta_bgzf() {
+        use crate::faidx::index_path;
+
+        let filename = "fetch_fasta_bgzf.fas.gz";
+        // one BGZF block per line
+        let mut bgzf = vec![];
+        for part in FASTA_FILE.split_inclusive(|&c| c == b'\n').chain([&[][..]]) {
+            let mut encoder = flate2::GzBuilder::new()
+                .extra(vec![b'B', b'C', 2, 0, 0, 0])
+                .write(vec![], flate2::Compression::default());
+            encoder.write_all(part).unwrap();
+            let mut member = encoder.finish().unwrap();
+            let bsize = (member.len() - 1) as u16;
+            member[16..18].copy_from_slice(&bsize.to_le_bytes());
+            bgzf.extend(member);
+        }
+        std::fs::write(filename, &bgzf).expect("Unable to write to file");
+
+        // without and with index files
+        for _ in 0..2 {
+            let mut reader = FastaReader::new_indexed_from_path(filename).unwrap();
+            assert_eq!(&reader.fetch("id1", 38, 48).unwrap()[..], b"AAAAAAA");
+            assert_eq!(&reader.fetch("id2", 15, 25).unwrap()[..], b"CCCCCCCCCC");
+            assert_eq!(&reader.fetch("id3", 0, 3).unwrap()[..], b"GGG");
+            let fai = reader.fai().unwrap().clone();
+            fai.write(&mut File::create(index_path(filename, ".fai")).unwrap())
+                .unwrap();
+            let gzi = crate::compress::GziIndex::build(&mut &bgzf[..]).unwrap();
+            gzi.write(&mut File::create(index_path(filename, ".gzi")).unwrap())
+                .unwrap();
+        }
+        file_teardown(&index_path(filename, ".fai").to_string_lossy());
+        file_teardown(&index_path(filename, ".gzi").to_string_lossy());
+        file_teardown(filename);
+    }
+
+    #[test]
+    fn write_fasta_roundtrip() {
+        let mut reader = FastaReader::new(FASTA_FILE).unwrap();
+        let mut recs = vec![];
+        while let Some(rec) = reader.next_as_xstring().unwrap() {
+            recs.push(rec);
+        }
+        for &width in [0, 1, 7, 10, 20, 100].iter() {
+            let mut writer = FastaWriter::new(vec![]).unwrap();
+            writer.set_line_width(width);
+            for (desc, seq) in recs.iter() {
+                writer.write_xstring(desc, seq).unwrap();
+            }
+            let out = writer.into_inner().unwrap();
+            if width == 10 {
+                // same as the original, except for the wrapping of id2
+                assert!(out.starts_with(&FASTA_FILE[..70]));
+            }
+            if width > 0 {
+                assert!(out.split(|&c| c == b'\n').all(|l| l.len() <= width.max(15)));
+            }
+            let mut reader = FastaReader::new(&out[..]).unwrap();
+            reader.set_strict(true);
+            for rec in recs.iter() {
+                assert_eq!(&reader.next_as_xstring().unwrap().unwrap(), rec);
+            }
+            assert!(reader.next_as_xstring().unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn write_fasta_headers() {
+        let seq = XString::from(&b"ACGT"[..]);
+        let mut writer = FastaWriter::new(vec![]).unwrap();
+        writer.set_header_format(HeaderFormat::Name);
+        writer.write_xstring("chr1 some desc", &seq).unwrap();
+        writer.set_header_format(HeaderFormat::Serial(String::from("seq")));
+        writer.write_xstring("chr2", &seq).unwrap();
+        writer.write_xstring("", &XString::new()).unwrap();
+        assert_eq!(writer.records_written(), 3);
+        let out = writer.into_inner().unwrap();
+        assert_eq!(&out[..], &b">chr1\nACGT\n>seq2\nACGT\n>seq3\n"[..]);
+    }
+
+    #[test]
+    fn write_fasta_bgzf() {
+        let filename = "write_fasta_bgzf.fas.gz";
+        let mut writer = FastaWriter::new_from_path(filename, Compression::Bgzf).unwrap();
+        writer.set_line_width(10);
+        let seq = XString::from(vec![b'T'; 25]);
+        writer.write_xstring("id1", &seq).unwrap();
+        writer.finish().unwrap();
+        let mut reader = FastaReader::new_indexed_from_path(filename).unwrap();
+        assert_eq!(&reader.fetch("id1", 20, 30).unwrap()[..], b"TTTTT");
+        assert_eq!(reader.next_as_xstring().unwrap().unwrap().1, seq);
+        file_teardown(filename);
+
+        // in memory
+        let dst = Encoder::new(vec![], Compression::Gzip).unwrap();
+        let mut writer = FastaWriter::new(dst).unwrap();
+        writer.write_xstring("id1", &seq).unwrap();
+        let out = writer.finish().unwrap();
+        let mut reader = FastaReader::new(Decoder::new(&out[..]).unwrap()).unwrap();
+        assert_eq!(reader.next_as_xstring().unwrap().unwrap().1, seq);
+    }
+
+    #[test]
+    fn read_fasta_ref() {
+        let mut reader = FastaReader::new(FASTA_FILE).unwrap();
+        let mut names = vec![];
+        let mut total_len = 0;
+        while let Some(rec) = reader.next_ref().unwrap() {
+            names.push(String::from_utf8(rec.name().to_vec()).unwrap());
+            total_len += rec.seq.len();
+            if rec.desc == b"id3 lots of Gs" {
+                assert_eq!(rec.seq, &[b'G'; 60][..]);
+            }
+        }
+        assert_eq!(names, vec!["id1", "id2", "id3"]);
+        assert_eq!(total_len, 45 + 80 + 60);
+        // buffers grow to the longest record only
+        assert!(reader.seq.capacity() < 2 * 80 + 16);
+    }
+
+    #[test]
+    fn stream_fasta() {
+        let src: &[u8] =
+            b"; comment\n\n>id1 desc\r\nACGT\r\nAC GT\n;skip>\nTT\n>id2\n>id3\nGG>G\nCC";
+        let mut stream = FastaStream::new(src).unwrap();
+        assert_eq!(stream.get().unwrap(), None);
+        assert_eq!(stream.next_record().unwrap().unwrap(), "id1 desc");
+        // lookahead across lines
+        let mut ahead = [0u8; 6];
+        assert_eq!(stream.peek_n(&mut ahead).unwrap(), 6);
+        assert_eq!(&ahead, b"ACGTAC");
+        let mut buf = [0u8; 3];
+        assert_eq!(stream.read(&mut buf).unwrap(), 3);
+        assert_eq!(&buf, b"ACG");
+        let r = stream.read_until(&mut buf, b'C', DelimiterMode::Exclusive);
+        assert_eq!(r.unwrap(), (2, ReadStatus::Found));
+        assert_eq!(&buf[..2], b"TA");
+        // the record ends the stream
+        let mut rest = XString::new();
+        let r = stream.read_until_xstring(&mut rest, b'C', DelimiterMode::Inclusive);
+        assert_eq!(r.unwrap(), ReadStatus::Eof);
+        assert_eq!(&rest[..], b"GTTT");
+        assert_eq!(stream.next_record().unwrap().unwrap(), "id2");
+        assert_eq!(stream.peek().unwrap(), None);
+        stream.unget(b'N');
+        // skip the rest of a record
+        assert_eq!(stream.next_record().unwrap().unwrap(), "id3");
+        assert_eq!(stream.get().unwrap(), Some(b'G'));
+        assert_eq!(stream.next_record().unwrap(), None);
+        assert_eq!(stream.records_read(), 3);
+
+        let mut stream = FastaStream::new(&b"\nACGT\n>id1\n"[..]).unwrap();
+        match stream.next_record() {
+            Err(FastaError::Malformed { line, offset, .. }) => assert_eq!((line, offset), (2, 1)),
+            r => panic!("expected malformed FASTA error, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn stream_fasta_index() {
+        use crate::dna::DNAAlphabet;
+        use crate::minimiser::MmIndex;
+        use crate::xstring::KmerXStrLexHasher;
+        use std::rc::Rc;
+
+        let ranker = KmerXStrLexHasher::new(Rc::new(DNAAlphabet::new()), 4);
+        let mut from_stream = MmIndex::new(&[5], &[4], &[&ranker]);
+        let mut stream = FastaStream::new(FASTA_FILE).unwrap();
+        while stream.next_record().unwrap().is_some() {
+            from_stream.index(&mut stream).unwrap();
+        }
+        let mut from_xstr = MmIndex::new(&[5], &[4], &[&ranker]);
+        let mut reader = FastaReader::new(FASTA_FILE).unwrap();
+        while let Some((_, seq)) = reader.next_as_xstring().unwrap() {
+            from_xstr.index_xstr(&seq).unwrap();
+        }
+        assert_eq!(from_stream.seq_offsets(), from_xstr.seq_offsets());
+        assert_eq!(from_stream.stats(), from_xstr.stats());
     }
 }
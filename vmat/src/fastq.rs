@@ -0,0 +1,397 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Read, Write};
+use std::path::Path;
+
+use crate::compress::{Compression, Decoder, Encoder};
+use crate::fasta::{write_wrapped, HeaderFormat};
+use crate::xstring::XString;
+
+/// A `(desc, seq, qual)` FASTQ record
+pub type FastqRecord = (String, XString<u8>, XString<u8>);
+
+/// A FASTQ record borrowed from the buffers of a `FastqReader`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FastqRecordRef<'a> {
+    /// Description line, without the starting `@`
+    pub desc: &'a [u8],
+    /// The sequence
+    pub seq: &'a [u8],
+    /// The quality string
+    pub qual: &'a [u8],
+}
+
+impl<'a> FastqRecordRef<'a> {
+    /// The sequence name, i.e. the description up to the first whitespace.
+    pub fn name(&self) -> &'a [u8] {
+        self.desc
+            .split(|c| c.is_ascii_whitespace())
+            .next()
+            .unwrap_or(&[])
+    }
+
+    /// Copies the record into an owned `(desc, seq, qual)` triple.
+    pub fn into_owned(self) -> FastqRecord {
+        (
+            String::from_utf8_lossy(self.desc).into_owned(),
+            XString::from(self.seq),
+            XString::from(self.qual),
+        )
+    }
+}
+
+pub struct FastqReader<R>
+where
+    R: Read,
+{
+    reader: BufReader<R>,
+    desc: Vec<u8>,
+    seq: Vec<u8>,
+    qual: Vec<u8>,
+}
+
+impl FastqReader<Decoder<File>> {
+    /// Opens a FASTQ file, which may be gzip, BGZF or zstd compressed.
+    pub fn new_from_path<P: AsRef<Path>>(path: P) -> Result<Self, std::io::Error> {
+        Ok(Self::from_bufreader(BufReader::new(
+            Decoder::new_from_path(path)?,
+        )))
+    }
+}
+
+/// Appends the next line of `reader` to `buf`, without its EOL chars.
+/// Returns the start of the line in `buf`, or None on EOF.
+fn read_line<R: Read>(
+    reader: &mut BufReader<R>,
+    buf: &mut Vec<u8>,
+) -> Result<Option<usize>, Error> {
+    let start = buf.len();
+    if reader.read_until(b'\n', buf)? == 0 {
+        return Ok(None);
+    }
+    while let Some(b'\n') | Some(b'\r') = buf.last() {
+        buf.pop();
+    }
+    Ok(Some(start))
+}
+
+impl<R> FastqReader<R>
+where
+    R: Read,
+{
+    pub fn new(src: R) -> Result<Self, std::io::Error> {
+        Ok(Self::from_bufreader(BufReader::new(src)))
+    }
+
+    pub(crate) fn from_bufreader(reader: BufReader<R>) -> Self {
+        FastqReader {
+            reader,
+            desc: Vec::new(),
+            seq: Vec::new(),
+            qual: Vec::new(),
+        }
+    }
+
+    /// Reads the next FASTQ record into the internal buffers of the reader,
+    /// which are reused from record to record, and returns a view of it.
+    /// The record is valid until the next call.
+    ///
+    /// Sequence and quality strings may span several lines.
+    /// EOL chars are not included in `desc`, `seq` or `qual`.
+    /// Blank lines between records and at the end are skipped.
+    pub fn next_ref(&mut self) -> Result<Option<FastqRecordRef<'_>>, std::io::Error> {
+        // read next id, skipping blank lines
+        loop {
+            self.desc.clear();
+            if read_line(&mut self.reader, &mut self.desc)?.is_none() {
+                return Ok(None);
+            }
+            if !self.desc.iter().all(u8::is_ascii_whitespace) {
+                break;
+            }
+        }
+        if self.desc.first() != Some(&b'@') {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "FASTQ record does not start with '@'",
+            ));
+        }
+        // read sequence lines up to the '+' separator line
+        self.seq.clear();
+        loop {
+            let start = match read_line(&mut self.reader, &mut self.seq)? {
+                Some(start) => start,
+                None => {
+                    return Err(Error::new(
+                        ErrorKind::UnexpectedEof,
+                        format!(
+                            "FASTQ record {} has no quality string",
+                            String::from_utf8_lossy(&self.desc[1..])
+                        ),
+                    ))
+                }
+            };
+            if self.seq.get(start) == Some(&b'+') {
+                self.seq.truncate(start);
+                break;
+            }
+        }
+        // read quality lines until there are as many values as sequence chars
+        // (there is always at least one, possibly empty, quality line)
+        self.qual.clear();
+        while read_line(&mut self.reader, &mut self.qual)?.is_some()
+            && self.qual.len() < self.seq.len()
+        {}
+        if self.qual.len() != self.seq.len() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "FASTQ record {} has {} sequence chars but {} quality values",
+                    String::from_utf8_lossy(&self.desc[1..]),
+                    self.seq.len(),
+                    self.qual.len()
+                ),
+            ));
+        }
+        Ok(Some(FastqRecordRef {
+            desc: &self.desc[1..],
+            seq: &self.seq,
+            qual: &self.qual,
+        }))
+    }
+
+    /// Reads the next FASTQ record as a `(desc, seq, qual)` triple where
+    /// * `desc` is a String with the record description line without the starting `@`
+    /// * `seq` is the actual sequence as a XString<u8>
+    /// * `qual` is the quality string, with one (Phred+33 encoded) quality
+    ///   value per char of `seq`
+    ///
+    /// Sequence and quality strings may span several lines.
+    /// EOL chars are not included in `desc`, `seq` or `qual`.
+    pub fn next_as_xstring(&mut self) -> Result<Option<FastqRecord>, std::io::Error> {
+        Ok(self.next_ref()?.map(FastqRecordRef::into_owned))
+    }
+}
+
+/// FASTQ writer.
+/// Sequence and quality strings are written in a single line by default.
+pub struct FastqWriter<W>
+where
+    W: Write,
+{
+    writer: BufWriter<W>,
+    line_width: usize,
+    header: HeaderFormat,
+    nrec: usize,
+}
+
+impl FastqWriter<Encoder<File>> {
+    /// Creates a FASTQ file with the given compression.
+    pub fn new_from_path<P: AsRef<Path>>(
+        path: P,
+        compression: Compression,
+    ) -> Result<Self, std::io::Error> {
+        Self::new(Encoder::new_from_path(path, compression)?)
+    }
+}
+
+impl<W> FastqWriter<Encoder<W>>
+where
+    W: Write,
+{
+    /// Writes the pending records, completes the compressed stream
+    /// and returns its destination.
+    pub fn finish(self) -> Result<W, std::io::Error> {
+        self.into_inner()?.finish()
+    }
+}
+
+impl<W> FastqWriter<W>
+where
+    W: Write,
+{
+    pub fn new(dst: W) -> Result<Self, std::io::Error> {
+        Ok(FastqWriter {
+            writer: BufWriter::new(dst),
+            line_width: 0,
+            header: HeaderFormat::Full,
+            nrec: 0,
+        })
+    }
+
+    /// Sets the maximum number of sequence and quality chars per line.
+    /// They are written in a single line if `width` is 0.
+    pub fn set_line_width(&mut self, width: usize) {
+        self.line_width = width;
+    }
+
+    pub fn set_header_format(&mut self, header: HeaderFormat) {
+        self.header = header;
+    }
+
+    /// Number of records written so far.
+    pub fn records_written(&self) -> usize {
+        self.nrec
+    }
+
+    /// Writes a FASTQ record with description `desc` (without the starting `@`),
+    /// sequence `seq` and quality string `qual`, which must have the same length.
+    pub fn write_xstring(
+        &mut self,
+        desc: &str,
+        seq: &XString<u8>,
+        qual: &XString<u8>,
+    ) -> Result<(), std::io::Error> {
+        if seq.len() != qual.len() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "FASTQ record {} has {} sequence chars but {} quality values",
+                    desc,
+                    seq.len(),
+                    qual.len()
+                ),
+            ));
+        }
+        self.nrec += 1;
+        let header = self.header.format(desc, self.nrec);
+        writeln!(self.writer, "@{}", header)?;
+        if seq.is_empty() {
+            return self.writer.write_all(b"\n+\n\n");
+        }
+        write_wrapped(&mut self.writer, &seq[..], self.line_width)?;
+        self.writer.write_all(b"+\n")?;
+        write_wrapped(&mut self.writer, &qual[..], self.line_width)
+    }
+
+    pub fn flush(&mut self) -> Result<(), std::io::Error> {
+        self.writer.flush()
+    }
+
+    /// Writes the pending records and returns the destination.
+    pub fn into_inner(self) -> Result<W, std::io::Error> {
+        self.writer.into_inner().map_err(|e| e.into_error())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FASTQ_FILE: &[u8] = b"@read1 desc
+ACGTACGTAC
++
+IIIIIIIII#
+@read2
+AAAAACCCCC
+GGGGG
++read2
+!!!!!!!!!!
+IIIII
+@read3
+T
++
+I
+";
+
+    #[test]
+    fn read_fastq() {
+        let mut reader = FastqReader::new(FASTQ_FILE).expect("Unable to open FASTQ source");
+        let (desc, seq, qual) = reader.next_as_xstring().unwrap().unwrap();
+        assert_eq!(desc, "read1 desc");
+        assert_eq!(&seq[..], b"ACGTACGTAC");
+        assert_eq!(&qual[..], b"IIIIIIIII#");
+        let (desc, seq, qual) = reader.next_as_xstring().unwrap().unwrap();
+        assert_eq!(desc, "read2");
+        assert_eq!(&seq[..], b"AAAAACCCCCGGGGG");
+        assert_eq!(&qual[..], b"!!!!!!!!!!IIIII");
+        let (desc, seq, qual) = reader.next_as_xstring().unwrap().unwrap();
+        assert_eq!(desc, "read3");
+        assert_eq!(&seq[..], b"T");
+        assert_eq!(&qual[..], b"I");
+        assert!(reader.next_as_xstring().unwrap().is_none());
+    }
+
+    #[test]
+    fn read_fastq_length_mismatch() {
+        let src: &[u8] = b"@read1\nACGT\n+\nIII\n@read2\nACGT\n+\nIIII\n";
+        let mut reader = FastqReader::new(src).expect("Unable to open FASTQ source");
+        assert!(reader.next_as_xstring().is_err());
+    }
+
+    #[test]
+    fn read_fastq_empty_seq() {
+        let src: &[u8] = b"@empty\n\n+\n\n@read1\nACGT\n+\nIIII\n";
+        let mut reader = FastqReader::new(src).expect("Unable to open FASTQ source");
+        let (desc, seq, qual) = reader.next_as_xstring().unwrap().unwrap();
+        assert_eq!(desc, "empty");
+        assert!(seq.is_empty() && qual.is_empty());
+        let (desc, seq, _) = reader.next_as_xstring().unwrap().unwrap();
+        assert_eq!(desc, "read1");
+        assert_eq!(&seq[..], b"ACGT");
+        assert!(reader.next_as_xstring().unwrap().is_none());
+    }
+
+    #[test]
+    fn read_fastq_blank_lines() {
+        let src: &[u8] = b"\n@read1\nACGT\n+\nIIII\n\n \r\n@read2\nAC\n+\nII\n\n";
+        let mut reader = FastqReader::new(src).expect("Unable to open FASTQ source");
+        let (desc, seq, _) = reader.next_as_xstring().unwrap().unwrap();
+        assert_eq!((desc.as_str(), &seq[..]), ("read1", &b"ACGT"[..]));
+        let (desc, seq, _) = reader.next_as_xstring().unwrap().unwrap();
+        assert_eq!((desc.as_str(), &seq[..]), ("read2", &b"AC"[..]));
+        assert!(reader.next_as_xstring().unwrap().is_none());
+    }
+
+    #[test]
+    fn write_fastq_roundtrip() {
+        let mut reader = FastqReader::new(FASTQ_FILE).unwrap();
+        let mut recs = vec![];
+        while let Some(rec) = reader.next_as_xstring().unwrap() {
+            recs.push(rec);
+        }
+        recs.push((String::from("empty"), XString::new(), XString::new()));
+        for &width in [0, 1, 4, 10].iter() {
+            let mut writer = FastqWriter::new(vec![]).unwrap();
+            writer.set_line_width(width);
+            for (desc, seq, qual) in recs.iter() {
+                writer.write_xstring(desc, seq, qual).unwrap();
+            }
+            let out = writer.into_inner().unwrap();
+            let mut reader = FastqReader::new(&out[..]).unwrap();
+            for rec in recs.iter() {
+                assert_eq!(&reader.next_as_xstring().unwrap().unwrap(), rec);
+            }
+            assert!(reader.next_as_xstring().unwrap().is_none());
+        }
+
+        let mut writer = FastqWriter::new(vec![]).unwrap();
+        writer.set_header_format(HeaderFormat::Name);
+        let (_, seq, qual) = &recs[0];
+        writer.write_xstring("r1 desc", seq, qual).unwrap();
+        assert!(writer.write_xstring("r2", seq, &XString::new()).is_err());
+        assert_eq!(writer.records_written(), 1);
+        let out = writer.into_inner().unwrap();
+        assert_eq!(&out[..], &b"@r1\nACGTACGTAC\n+\nIIIIIIIII#\n"[..]);
+
+        let dst = Encoder::new(vec![], Compression::Zstd).unwrap();
+        let mut writer = FastqWriter::new(dst).unwrap();
+        writer.write_xstring("r1", seq, qual).unwrap();
+        let out = writer.finish().unwrap();
+        let mut reader = FastqReader::new(Decoder::new(&out[..]).unwrap()).unwrap();
+        assert_eq!(reader.next_as_xstring().unwrap().unwrap().1, *seq);
+    }
+
+    #[test]
+    fn read_fastq_ref() {
+        let mut reader = FastqReader::new(FASTQ_FILE).expect("Unable to open FASTQ source");
+        let rec = reader.next_ref().unwrap().unwrap();
+        assert_eq!(rec.name(), b"read1");
+        assert_eq!(rec.desc, b"read1 desc");
+        let rec = reader.next_ref().unwrap().unwrap();
+        assert_eq!(rec.seq, b"AAAAACCCCCGGGGG");
+        assert_eq!(rec.qual, b"!!!!!!!!!!IIIII");
+        let rec = reader.next_ref().unwrap().unwrap();
+        assert_eq!((rec.seq, rec.qual), (&b"T"[..], &b"I"[..]));
+        assert!(reader.next_ref().unwrap().is_none());
+    }
+}
@@ -0,0 +1,483 @@
+//! FM-index of one or more sequences, for counting and locating the
+//! exact occurrences of patterns.
+//!
+//! The index holds the Burrows-Wheeler transform (BWT) of the sequences,
+//! with occurrence counts sampled every `OCC_RATE` rows, and a sample of
+//! the suffix array. It is built from a [`SuffixArray`], with the same
+//! separated concatenation of the sequences and the same absolute positions.
+
+use std::io::{self, Read, Write};
+use std::ops::Range;
+use std::rc::Rc;
+
+use crate::alphabet::{Alphabet, Character};
+use crate::binio;
+use crate::bitvec::BitVector;
+use crate::srchutil;
+use crate::sufarray::SuffixArray;
+
+/// Identifies saved FM-index files
+const FMINDEX_MAGIC: &[u8] = b"VMATFMI1";
+
+/// Number of BWT rows between occurrence count checkpoints
+const OCC_RATE: usize = 64;
+
+/// BWT code of the separators and of the sentinel
+const SEP_CODE: u32 = 0;
+
+/**
+ * FM-index of the concatenation of sequences over an alphabet.
+ *
+ * The BWT rows are the suffixes of the concatenation terminated by a
+ * sentinel, which sorts first: row `i + 1` is the suffix at `sa[i]` of the
+ * suffix array. Intervals returned by [`FmIndex::find`] are however given
+ * in suffix array coordinates, as by [`SuffixArray::find`].
+ *
+ * The absolute positions of the suffixes are sampled every `sa_rate`
+ * positions and at the start of each sequence, so that locating an
+ * occurrence takes at most `sa_rate - 1` LF steps.
+ */
+pub struct FmIndex<A>
+where
+    A: Alphabet,
+{
+    ab: Rc<A>,
+    /// BWT as `SEP_CODE` or as char ranks shifted by one. Codes take
+    /// 4 bytes per row, in memory and in saved indexes, whatever the
+    /// alphabet size: 4 times the size of a byte text for DNA
+    bwt: Vec<u32>,
+    /// Number of BWT chars smaller than each code
+    less: Vec<usize>,
+    /// Counts of each code in the BWT rows before each checkpoint
+    occ: Vec<usize>,
+    sa_rate: usize,
+    /// Marks of the rows whose position is sampled
+    sampled: BitVector,
+    /// Sampled positions, in row order
+    samples: Vec<usize>,
+    /// Start positions of the sequences, and the total length at the end
+    starts: Vec<usize>,
+}
+
+impl<A> FmIndex<A>
+where
+    A: Alphabet,
+{
+    /// Builds the FM-index of the sequences of a suffix array,
+    /// sampling one position every `sa_rate`.
+    pub fn new(sa: &SuffixArray<A>, sa_rate: usize) -> Self {
+        assert!(sa_rate > 0);
+        let text = sa.symbols();
+        let nseq = sa.nseq();
+        let code = |sym: usize| {
+            if sym <= nseq {
+                SEP_CODE
+            } else {
+                (sym - nseq) as u32
+            }
+        };
+        let n = text.len() + 1;
+        let mut bwt = Vec::with_capacity(n);
+        let mut sampled = vec![0u64; n.div_ceil(64)];
+        let mut samples = Vec::with_capacity(n / sa_rate + nseq + 1);
+        let rows = std::iter::once(text.len()).chain(sa.sa().iter().copied());
+        for (i, p) in rows.enumerate() {
+            let c = if p == 0 { SEP_CODE } else { code(text[p - 1]) };
+            bwt.push(c);
+            if p.is_multiple_of(sa_rate) || c == SEP_CODE {
+                sampled[i / 64] |= 1 << (i % 64);
+                samples.push(p);
+            }
+        }
+        let mut fmindex = FmIndex {
+            ab: Rc::clone(sa.alphabet()),
+            bwt,
+            less: vec![],
+            occ: vec![],
+            sa_rate,
+            sampled: BitVector::from_words(sampled, n),
+            samples,
+            starts: sa.starts().to_vec(),
+        };
+        fmindex.init_counts();
+        fmindex
+    }
+
+    /// Builds the FM-index of `seqs`, whose chars must all be in `ab`,
+    /// sampling one position every `sa_rate`.
+    pub fn new_from_seqs(
+        ab: Rc<A>,
+        seqs: &[&[A::CharType]],
+        sa_rate: usize,
+    ) -> Result<Self, io::Error> {
+        Ok(Self::new(&SuffixArray::new(ab, seqs)?, sa_rate))
+    }
+
+    /// Computes the occurrence counts.
+    fn init_counts(&mut self) {
+        let nsym = self.ab.len() + 1;
+        let mut counts = vec![0; nsym];
+        self.occ = Vec::with_capacity((self.bwt.len() / OCC_RATE + 1) * nsym);
+        for (i, &c) in self.bwt.iter().enumerate() {
+            if i.is_multiple_of(OCC_RATE) {
+                self.occ.extend_from_slice(&counts);
+            }
+            counts[c as usize] += 1;
+        }
+        if self.bwt.len().is_multiple_of(OCC_RATE) {
+            self.occ.extend_from_slice(&counts);
+        }
+        self.less = Vec::with_capacity(nsym);
+        let mut sum = 0;
+        for &count in counts.iter() {
+            self.less.push(sum);
+            sum += count;
+        }
+    }
+
+    /// Number of suffixes, i.e. the total length of the sequences
+    /// plus one separator per sequence.
+    pub fn len(&self) -> usize {
+        self.bwt.len() - 1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of indexed sequences.
+    pub fn nseq(&self) -> usize {
+        self.starts.len() - 1
+    }
+
+    /// Returns the index of the sequence holding the absolute position `pos`,
+    /// and the offset of `pos` in it.
+    pub fn seq_pos(&self, pos: usize) -> (usize, usize) {
+        assert!(pos < self.len());
+        let seq = srchutil::pred(&self.starts, &pos).unwrap();
+        (seq, pos - self.starts[seq])
+    }
+
+    /// Number of occurrences of code `c` in the BWT rows before `row`.
+    #[inline]
+    fn occ(&self, c: u32, row: usize) -> usize {
+        let b = row / OCC_RATE;
+        let base = self.occ[b * self.less.len() + c as usize];
+        base + self.bwt[b * OCC_RATE..row]
+            .iter()
+            .filter(|&&x| x == c)
+            .count()
+    }
+
+    /// Row of the suffix starting one position before the suffix of `row`.
+    #[inline]
+    fn lf(&self, row: usize) -> usize {
+        let c = self.bwt[row];
+        self.less[c as usize] + self.occ(c, row)
+    }
+
+    /// Returns the sampled position of `row`, if it is sampled.
+    #[inline]
+    fn sample(&self, row: usize) -> Option<usize> {
+        if self.sampled.get(row) {
+            Some(self.samples[self.sampled.rank1(row)])
+        } else {
+            None
+        }
+    }
+
+    /// Returns the interval of BWT rows of the suffixes which start with
+    /// `pattern`, found by backward search.
+    fn backward_search(&self, pattern: &[A::CharType]) -> Option<Range<usize>> {
+        let (mut lo, mut hi) = (0, self.bwt.len());
+        for ch in pattern.iter().rev() {
+            let c = self.ab.ord(ch)? as u32 + 1;
+            lo = self.less[c as usize] + self.occ(c, lo);
+            hi = self.less[c as usize] + self.occ(c, hi);
+            if lo >= hi {
+                return None;
+            }
+        }
+        Some(lo..hi)
+    }
+
+    /// Returns the interval of the suffix array holding the suffixes which
+    /// start with `pattern`, or an empty interval if there is none.
+    pub fn find(&self, pattern: &[A::CharType]) -> Range<usize> {
+        match self.backward_search(pattern) {
+            // the sentinel row only starts with the empty pattern
+            Some(rows) => rows.start.saturating_sub(1)..rows.end - 1,
+            None => 0..0,
+        }
+    }
+
+    /// Number of occurrences of `pattern`.
+    pub fn count(&self, pattern: &[A::CharType]) -> usize {
+        self.find(pattern).len()
+    }
+
+    /// Absolute position of the suffix at index `i` of the suffix array.
+    pub fn locate_suffix(&self, i: usize) -> usize {
+        let mut row = i + 1;
+        let mut steps = 0;
+        loop {
+            if let Some(pos) = self.sample(row) {
+                return pos + steps;
+            }
+            row = self.lf(row);
+            steps += 1;
+        }
+    }
+
+    /// Returns the absolute positions of the occurrences of `pattern`,
+    /// in suffix array order.
+    pub fn locate(&self, pattern: &[A::CharType]) -> Vec<usize> {
+        self.find(pattern).map(|i| self.locate_suffix(i)).collect()
+    }
+
+    /// Writes the index to `dst` in binary format. The alphabet is not
+    /// saved, only its chars, used to validate the one given to
+    /// [`FmIndex::load`].
+    pub fn save<W: Write>(&self, dst: &mut W) -> Result<(), io::Error> {
+        dst.write_all(FMINDEX_MAGIC)?;
+        binio::write_usize(dst, self.ab.len())?;
+        for r in 0..self.ab.len() {
+            binio::write_u32(dst, self.ab.chr(r).unwrap().to_u32())?;
+        }
+        binio::write_usize(dst, self.sa_rate)?;
+        binio::write_usize(dst, self.bwt.len())?;
+        for &c in self.bwt.iter() {
+            binio::write_u32(dst, c)?;
+        }
+        for &word in self.sampled.words() {
+            binio::write_u64(dst, word)?;
+        }
+        binio::write_usizes(dst, &self.samples)?;
+        binio::write_usizes(dst, &self.starts)
+    }
+
+    /// Reads an index saved with [`FmIndex::save`] from `src`.
+    /// Fails with `InvalidData` if the saved index was not built
+    /// over the chars of `ab`.
+    pub fn load<R: Read>(src: &mut R, ab: Rc<A>) -> Result<Self, io::Error> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg);
+        binio::check_magic(src, FMINDEX_MAGIC)?;
+        let nchr = binio::read_usize(src)?;
+        let mut matches = nchr == ab.len();
+        for r in 0..nchr {
+            let c = binio::read_u32(src)?;
+            matches = matches && ab.chr(r).map(|&x| x.to_u32()) == Some(c);
+        }
+        if !matches {
+            return Err(invalid(
+                "Alphabet does not match the one used to build the index",
+            ));
+        }
+        let sa_rate = binio::read_usize(src)?;
+        let n = binio::read_usize(src)?;
+        if sa_rate == 0 || n == 0 {
+            return Err(invalid("Invalid FM-index"));
+        }
+        let mut bwt = binio::with_capacity(n);
+        for _ in 0..n {
+            let c = binio::read_u32(src)?;
+            if c as usize > nchr {
+                return Err(invalid("Invalid BWT code"));
+            }
+            bwt.push(c);
+        }
+        let mut sampled = binio::with_capacity(n.div_ceil(64));
+        for _ in 0..n.div_ceil(64) {
+            sampled.push(binio::read_u64(src)?);
+        }
+        let samples = binio::read_usizes(src)?;
+        let starts = binio::read_usizes(src)?;
+        let sampled = BitVector::from_words(sampled, n);
+        if sampled.count_ones() != samples.len()
+            || samples.iter().any(|&p| p >= n)
+            || starts.first() != Some(&0)
+            || starts.last() != Some(&(n - 1))
+            || starts.windows(2).any(|s| s[0] >= s[1])
+        {
+            return Err(invalid("Invalid FM-index"));
+        }
+        // locating stops at the sampled rows, at the latest at separators
+        if bwt
+            .iter()
+            .enumerate()
+            .any(|(i, &c)| c == SEP_CODE && !sampled.get(i))
+        {
+            return Err(invalid("Invalid FM-index: unsampled separator"));
+        }
+        let mut fmindex = FmIndex {
+            ab,
+            bwt,
+            less: vec![],
+            occ: vec![],
+            sa_rate,
+            sampled,
+            samples,
+            starts,
+        };
+        fmindex.init_counts();
+        if !fmindex.check_walks() {
+            return Err(invalid("Invalid FM-index: rows not reaching a sampled row"));
+        }
+        Ok(fmindex)
+    }
+
+    /// Tells whether locating any row reaches a sampled row, i.e. whether
+    /// every unsampled row is on the LF walk from a sampled row to the next
+    /// one. This takes one LF step per row, as locating every suffix once.
+    fn check_walks(&self) -> bool {
+        let n = self.bwt.len();
+        let mut unsampled = 0;
+        for start in (0..n).filter(|&row| self.sampled.get(row)) {
+            // LF is a permutation, so the walk returns to its start at worst
+            let mut row = self.lf(start);
+            while !self.sampled.get(row) {
+                unsampled += 1;
+                row = self.lf(row);
+            }
+        }
+        unsampled == n - self.samples.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alphabet::HashAlphabet;
+    use crate::dna::DNAAlphabet;
+
+    fn random_seq(len: usize, seed: u64) -> Vec<u8> {
+        let mut x = seed;
+        (0..len)
+            .map(|_| {
+                x = x
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                b"AACGTT"[(x >> 33) as usize % 6]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_fmindex() {
+        let ab = Rc::new(DNAAlphabet::new());
+        let (a, b) = (random_seq(700, 5), random_seq(200, 6));
+        let seqs: Vec<&[u8]> = vec![&a, b"", &b, &a[300..500], b"ACGT"];
+        let sa = SuffixArray::new(Rc::clone(&ab), &seqs).unwrap();
+        let mut patterns: Vec<&[u8]> = vec![b"", b"A", b"T", b"ACGT", b"TTTTTTTTTTTT", b"N"];
+        for i in (0..650).step_by(13) {
+            patterns.push(&a[i..i + 1 + i % 40]);
+        }
+        for &sa_rate in [1, 3, 32, 1000].iter() {
+            let fm = FmIndex::new(&sa, sa_rate);
+            assert_eq!((fm.len(), fm.nseq()), (sa.len(), sa.nseq()));
+            let mut buf = vec![];
+            fm.save(&mut buf).unwrap();
+            assert!(FmIndex::load(&mut &buf[..], Rc::clone(&ab)).is_ok());
+            for i in 0..sa.len() {
+                assert_eq!(fm.locate_suffix(i), sa.sa()[i]);
+            }
+            for pat in patterns.iter() {
+                let expected = sa.find(pat);
+                if expected.is_empty() {
+                    assert!(fm.find(pat).is_empty());
+                } else {
+                    assert_eq!(fm.find(pat), expected);
+                }
+                assert_eq!(fm.count(pat), expected.len());
+                assert_eq!(&fm.locate(pat)[..], &sa.sa()[expected]);
+            }
+        }
+        let fm = FmIndex::new(&sa, 8);
+        let mut occs: Vec<(usize, usize)> =
+            fm.locate(b"ACGT").iter().map(|&p| fm.seq_pos(p)).collect();
+        occs.sort_unstable();
+        assert_eq!(occs.last(), Some(&(4, 0)));
+    }
+
+    #[test]
+    fn test_fmindex_save_load() {
+        let ab = Rc::new(HashAlphabet::new("xyz".chars().collect()));
+        let seqs: Vec<Vec<char>> = ["xyzzy", "zyx", "yyy"]
+            .iter()
+            .map(|s| s.chars().collect())
+            .collect();
+        let refs: Vec<&[char]> = seqs.iter().map(|s| &s[..]).collect();
+        let fm = FmIndex::new_from_seqs(Rc::clone(&ab), &refs, 2).unwrap();
+        let mut buf = vec![];
+        fm.save(&mut buf).unwrap();
+        let loaded = FmIndex::load(&mut &buf[..], ab).unwrap();
+        for pat in ["y", "yx", "zy", "yyy", "xz"].iter() {
+            let pat: Vec<char> = pat.chars().collect();
+            assert_eq!(loaded.find(&pat), fm.find(&pat));
+            assert_eq!(loaded.locate(&pat), fm.locate(&pat));
+        }
+        assert_eq!(loaded.count(&['y']), 6);
+
+        let other = Rc::new(HashAlphabet::new("xzy".chars().collect()));
+        let err = FmIndex::load(&mut &buf[..], other).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(FmIndex::load(&mut &buf[..buf.len() - 1], Rc::clone(&fm.ab)).is_err());
+    }
+
+    #[test]
+    fn test_fmindex_load_garbled() {
+        let ab = Rc::new(DNAAlphabet::new());
+        let seqs: Vec<&[u8]> = vec![b"ACGTTGCA", b"GATTACA"];
+        let fm = FmIndex::new_from_seqs(Rc::clone(&ab), &seqs, 4).unwrap();
+        let mut buf = vec![];
+        fm.save(&mut buf).unwrap();
+        let n = fm.bwt.len();
+        // offsets of the number of rows, the bit words, the samples and the starts
+        let n_off = FMINDEX_MAGIC.len() + 8 + 4 * ab.len() + 8;
+        let words_off = n_off + 8 + 4 * n;
+        let samples_off = words_off + 8 * n.div_ceil(64) + 8;
+        let starts_off = samples_off + 8 * fm.samples.len() + 8;
+        let load = |buf: &[u8]| FmIndex::load(&mut &buf[..], Rc::clone(&ab)).err().unwrap();
+
+        // a huge number of rows fails without being preallocated
+        let mut garbled = buf.clone();
+        garbled[n_off..n_off + 8].copy_from_slice(&(1u64 << 60).to_le_bytes());
+        assert!(FmIndex::load(&mut &garbled[..], Rc::clone(&ab)).is_err());
+
+        let mut garbled = buf.clone();
+        garbled[samples_off..samples_off + 8].copy_from_slice(&(n as u64).to_le_bytes());
+        assert_eq!(load(&garbled).kind(), io::ErrorKind::InvalidData);
+
+        let mut garbled = buf.clone();
+        garbled[starts_off + 8..starts_off + 16].copy_from_slice(&100u64.to_le_bytes());
+        assert_eq!(load(&garbled).kind(), io::ErrorKind::InvalidData);
+
+        // move the mark of a separator row to an unsampled row
+        let sep = (1..n).find(|&i| fm.bwt[i] == SEP_CODE).unwrap();
+        let other = (0..n).find(|&i| !fm.sampled.get(i)).unwrap();
+        let mut words = fm.sampled.words().to_vec();
+        words[sep / 64] &= !(1 << (sep % 64));
+        words[other / 64] |= 1 << (other % 64);
+        let mut garbled = buf.clone();
+        for (i, w) in words.iter().enumerate() {
+            garbled[words_off + 8 * i..words_off + 8 * i + 8].copy_from_slice(&w.to_le_bytes());
+        }
+        assert_eq!(load(&garbled).kind(), io::ErrorKind::InvalidData);
+
+        // LF maps the unsampled rows 1 and 2 to themselves,
+        // so locating them would never reach a sampled row
+        let looping = FmIndex {
+            ab: Rc::clone(&ab),
+            bwt: vec![SEP_CODE, 1, 1],
+            less: vec![],
+            occ: vec![],
+            sa_rate: 8,
+            sampled: BitVector::from_words(vec![1], 3),
+            samples: vec![0],
+            starts: vec![0, 2],
+        };
+        let mut buf = vec![];
+        looping.save(&mut buf).unwrap();
+        assert_eq!(load(&buf).kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -1,9 +1,22 @@
 pub mod alphabet;
+pub mod binio;
+pub mod bitvec;
+pub mod compress;
 pub mod dna;
+pub mod eliasfano;
+pub mod faidx;
+pub mod fmindex;
 pub mod fasta;
+pub mod fastq;
 pub mod minimiser;
+pub mod mmiter;
 pub mod mqueue;
 pub mod mstack;
+pub mod parallel;
+pub mod seqio;
+pub mod simd;
 pub mod srchutil;
+pub mod sufarray;
+pub mod twobit;
 pub mod xstream;
 pub mod xstring;
@@ -1,19 +1,27 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read};
 use std::rc::Rc;
+use std::str::FromStr;
 
-use clap::{App, Arg, SubCommand};
+use clap::{App, Arg, ArgMatches, SubCommand};
 
-use vmat::dna::DNAAlphabet;
-use vmat::dna::DNAHasher;
-use vmat::fasta::FastaReader;
-use vmat::minimiser::MmIndex;
-use vmat::xstream::XStrFileReader;
-use vmat::xstring::KmerXStrLexHasher;
-use vmat::xstring::XString;
+use vmat::compress::{Compression, Decoder, GziIndex};
+use vmat::dna::{DNAAlphabet, DNAHasher};
+use vmat::faidx::{index_path, FaiIndex};
+use vmat::fasta::{FastaReader, FastaStream};
+use vmat::minimiser::{self, HighFreqAction, MmIndex, OccThreshold};
+use vmat::parallel::{self, ParallelSeqReader};
+use vmat::seqio::{self, SeqFormat, SeqReader};
+use vmat::twobit::TwoBitReader;
+use vmat::xstream::XStrStream;
 
-fn index(input_filename: &str, output_filename: &str, w: &[usize], k: &[usize]) {
-    println!("Indexing {} to {}", input_filename, output_filename);
+type DNARanker = DNAHasher;
+
+/// Longest minimisers the DNA rankers can rank
+const MAX_K: usize = 32;
 
-    if (w.len() != k.len()) {
+fn check_schemes(w: &[usize], k: &[usize]) {
+    if w.len() != k.len() {
         println!(
             "Error: The quantities of window sizes (-w) and minimiser lengths (-k) must agree."
         );
@@ -21,12 +29,22 @@ fn index(input_filename: &str, output_filename: &str, w: &[usize], k: &[usize])
         println!("       Minimiser lengths: {0:?}", k);
         std::process::exit(1);
     }
+    if w.contains(&0) {
+        println!("Error: The window sizes (-w) must be at least 1.");
+        println!("       Window sizes: {0:?}", w);
+        std::process::exit(1);
+    }
+    if k.iter().any(|&ki| ki == 0 || ki > MAX_K) {
+        println!(
+            "Error: The minimiser lengths (-k) must be between 1 and {}.",
+            MAX_K
+        );
+        println!("       Minimiser lengths: {0:?}", k);
+        std::process::exit(1);
+    }
+}
 
-    let mut reader: XStrFileReader<u8> =
-        XStrFileReader::new(input_filename).expect("Cannot open input file");
-    let m = w.len();
-
-    let dna_ab = DNAAlphabet::new();
+fn make_rankers(k: &[usize]) -> Vec<DNARanker> {
     let mut letters = [
         DNAAlphabet::A,
         DNAAlphabet::C,
@@ -34,32 +52,422 @@ fn index(input_filename: &str, output_filename: &str, w: &[usize], k: &[usize])
         DNAAlphabet::T,
     ];
     let mut ranker = vec![];
-    for i in 0..m {
-        ranker.push(KmerXStrLexHasher::new(
-            Rc::new(DNAAlphabet::new_with_permutation(&letters)),
-            k[i],
-        ));
+    for _ in k {
+        ranker.push(DNAHasher::new(Rc::new(DNAAlphabet::new_with_permutation(
+            &letters,
+        ))));
         letters.rotate_left(1);
     }
-    let ranker_refs: Vec<&KmerXStrLexHasher<u8, DNAAlphabet>> = ranker.iter().collect();
+    ranker
+}
+
+/// Path given for reading from the standard input
+const STDIN_PATH: &str = "-";
+
+/// Indexes the sequences of `input_filename`. Malformed FASTA input is
+/// rejected if `strict`, otherwise what can be is skipped.
+fn index_seqs(mmindex: &mut MmIndex<u8, DNARanker>, input_filename: &str, strict: bool) -> usize {
+    if input_filename.ends_with(".2bit") {
+        index_twobit(mmindex, input_filename)
+    } else if input_filename == STDIN_PATH {
+        index_source(
+            mmindex,
+            Decoder::new(std::io::stdin()).expect("Cannot read FASTA/FASTQ from standard input"),
+            strict,
+        )
+    } else {
+        index_source(
+            mmindex,
+            Decoder::new_from_path(input_filename).expect("Cannot open input FASTA/FASTQ file"),
+            strict,
+        )
+    }
+}
+
+/// Indexes FASTA records as they are streamed, in constant memory,
+/// and FASTQ records, or FASTA records parsed strictly, one by one.
+fn index_source<R: Read>(mmindex: &mut MmIndex<u8, DNARanker>, src: R, strict: bool) -> usize {
+    let mut reader = BufReader::new(src);
+    let format = seqio::detect_format(&mut reader).expect("Unable to read from FASTA/FASTQ file");
+    if format == SeqFormat::Fastq || strict {
+        let mut seq_reader = SeqReader::new(reader).expect("Unable to read from FASTA/FASTQ file");
+        seq_reader.set_strict(strict);
+        return index_records(mmindex, seq_reader);
+    }
+    let mut stream = FastaStream::new(reader).expect("Unable to read from FASTA file");
+    let mut nseq = 0;
+    while let Some(desc) = stream
+        .next_record()
+        .expect("Unable to read from FASTA file")
+    {
+        println!("Indexing sequence = {}\n", desc);
+        mmindex.index(&mut stream).expect("Error indexing sequence");
+        mmindex.set_seq_name(mmindex.nseq() - 1, &desc);
+        nseq += 1;
+    }
+    nseq
+}
+
+fn index_twobit(mmindex: &mut MmIndex<u8, DNARanker>, input_filename: &str) -> usize {
+    let mut reader =
+        TwoBitReader::new_from_path(input_filename).expect("Cannot open input .2bit file");
+    // the minimiser rankers only know uppercase bases
+    reader.set_soft_mask(false);
+    let names: Vec<String> = reader.seqs().iter().map(|s| s.name.clone()).collect();
+    for name in names.iter() {
+        println!("Indexing sequence = {}\n", name);
+        let mut stream = reader.stream(name).expect("Unable to read from .2bit file");
+        mmindex.index(&mut stream).expect("Error indexing sequence");
+        mmindex.set_seq_name(mmindex.nseq() - 1, name);
+    }
+    names.len()
+}
+
+fn index_seqs_parallel(
+    mmindex: &mut MmIndex<u8, DNARanker>,
+    input_filename: &str,
+    nthreads: usize,
+) -> usize {
+    let seq_reader = if input_filename == STDIN_PATH {
+        Decoder::new(std::io::stdin())
+            .and_then(|src| ParallelSeqReader::new(src, nthreads, parallel::DEFAULT_CHUNK_SIZE))
+    } else {
+        ParallelSeqReader::new_from_path(input_filename, nthreads)
+    }
+    .expect("Cannot open input FASTA/FASTQ file");
+    let mut nseq = 0;
+    for batch in seq_reader {
+        for rec in batch.expect("Unable to read from FASTA/FASTQ file") {
+            println!("Indexing sequence = {}\n", rec.desc);
+            mmindex
+                .index_xstr(&rec.seq)
+                .expect("Error indexing sequence");
+            mmindex.set_seq_name(mmindex.nseq() - 1, &rec.desc);
+            nseq += 1;
+        }
+    }
+    nseq
+}
 
-    let mut mmindex = MmIndex::new(w, k, &ranker_refs);
-    //minimiser::index_minimisers(&mut reader, w, k, &ranker_refs);
-    let mut fasta_reader =
-        FastaReader::new_from_path(input_filename).expect("Cannot open input FASTA file");
+fn index_records<R: Read>(
+    mmindex: &mut MmIndex<u8, DNARanker>,
+    mut seq_reader: SeqReader<R>,
+) -> usize {
     let mut nseq = 0;
-    while let Some((desc, s)) = fasta_reader
-        .next_as_xstring()
-        .expect("Unable to read from fasta file")
+    while let Some(rec) = seq_reader
+        .next_ref()
+        .expect("Unable to read from FASTA/FASTQ file")
     {
+        let desc = String::from_utf8_lossy(rec.desc);
         println!("Indexing sequence = {}\n", desc);
-        mmindex.index_xstr(&s).expect("Error indexing sequence");
+        mmindex
+            .index(&mut XStrStream::open_slice(rec.seq))
+            .expect("Error indexing sequence");
+        mmindex.set_seq_name(mmindex.nseq() - 1, &desc);
         nseq += 1;
     }
+    nseq
+}
+
+/// Reads the `(w,k)` schemes of a saved index.
+fn read_schemes(index_filename: &str) -> Result<(Vec<usize>, Vec<usize>), io::Error> {
+    let file = File::open(index_filename)?;
+    minimiser::read_schemes(&mut BufReader::new(file))
+}
+
+/// Reads the `(w,k)` schemes of a saved index, exiting with an error
+/// message if it cannot be read.
+fn index_schemes(index_filename: &str) -> (Vec<usize>, Vec<usize>) {
+    read_schemes(index_filename).unwrap_or_else(|e| {
+        println!("Error: Unable to load index {}: {}", index_filename, e);
+        std::process::exit(1);
+    })
+}
+
+fn load_index<'a>(
+    index_filename: &str,
+    w: &[usize],
+    k: &[usize],
+    ranker_refs: &[&'a DNARanker],
+) -> MmIndex<'a, u8, DNARanker> {
+    let file = File::open(index_filename).expect("Cannot open index file");
+    match MmIndex::load(&mut BufReader::new(file), w, k, ranker_refs) {
+        Ok(mmindex) => mmindex,
+        Err(e) => {
+            println!("Error: Unable to load index {}: {}", index_filename, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn save_index(mmindex: &MmIndex<u8, DNARanker>, index_filename: &str) {
+    let file = File::create(index_filename).expect("Cannot create index file");
+    mmindex
+        .save(&mut BufWriter::new(file))
+        .expect("Error saving index");
+}
+
+#[allow(clippy::too_many_arguments)]
+fn index(
+    input_filename: &str,
+    nthreads: usize,
+    output_filename: &str,
+    append_filename: Option<&str>,
+    w: &[usize],
+    k: &[usize],
+    occ_thresh: Option<OccThreshold>,
+    hf_action: HighFreqAction,
+    strict: bool,
+) {
+    println!("Indexing {} to {}", input_filename, output_filename);
+    check_schemes(w, k);
+
+    let ranker = make_rankers(k);
+    let ranker_refs: Vec<&DNARanker> = ranker.iter().collect();
+
+    let mut mmindex = match append_filename {
+        Some(filename) => {
+            let mmindex = load_index(filename, w, k, &ranker_refs);
+            println!(
+                "Appending to index {} with {} sequences",
+                filename,
+                mmindex.nseq()
+            );
+            mmindex
+        }
+        None => MmIndex::new(w, k, &ranker_refs),
+    };
+    // strictly parsed FASTA records are read by a single thread
+    let nseq = if nthreads > 1 && !strict && !input_filename.ends_with(".2bit") {
+        index_seqs_parallel(&mut mmindex, input_filename, nthreads)
+    } else {
+        index_seqs(&mut mmindex, input_filename, strict)
+    };
+
+    if let Some(thresh) = occ_thresh {
+        let cutoffs = match mmindex.filter_high_freq(thresh, hf_action) {
+            Ok(cutoffs) => cutoffs,
+            Err(e) => {
+                println!("Error: Unable to filter high-frequency minimisers: {}", e);
+                std::process::exit(1);
+            }
+        };
+        println!(
+            "High-frequency minimisers {}. Occurrence cutoffs = {:?}",
+            match hf_action {
+                HighFreqAction::Drop => "dropped",
+                HighFreqAction::Flag => "flagged",
+            },
+            cutoffs
+        );
+    }
 
+    save_index(&mmindex, output_filename);
     println!("Done. {} sequences successfuly indexed.", nseq);
 }
 
+fn merge(input_filenames: &[&str], output_filename: &str) {
+    println!("Merging {:?} to {}", input_filenames, output_filename);
+    let (w, k) = index_schemes(input_filenames[0]);
+    check_schemes(&w, &k);
+
+    let ranker = make_rankers(&k);
+    let ranker_refs: Vec<&DNARanker> = ranker.iter().collect();
+
+    let mut mmindex = MmIndex::new(&w, &k, &ranker_refs);
+    for filename in input_filenames {
+        let part = load_index(filename, &w, &k, &ranker_refs);
+        println!("Merging index {} with {} sequences", filename, part.nseq());
+        if let Err(e) = mmindex.merge(&part) {
+            println!("Error: Unable to merge index {}: {}", filename, e);
+            std::process::exit(1);
+        }
+    }
+
+    save_index(&mmindex, output_filename);
+    println!("Done. {} sequences in merged index.", mmindex.nseq());
+}
+
+/// Reports the statistics of a saved index, if `saved` is set, or of the
+/// index of the sequences of `input_filename`.
+fn stats(input_filename: &str, saved: bool, w: &[usize], k: &[usize], strict: bool) {
+    println!("Computing index statistics of {}", input_filename);
+    check_schemes(w, k);
+
+    let ranker = make_rankers(k);
+    let ranker_refs: Vec<&DNARanker> = ranker.iter().collect();
+
+    let mmindex = if saved {
+        load_index(input_filename, w, k, &ranker_refs)
+    } else {
+        let mut mmindex = MmIndex::new(w, k, &ranker_refs);
+        index_seqs(&mut mmindex, input_filename, strict);
+        mmindex
+    };
+
+    let stats = mmindex.stats();
+    println!("Sequences: {}", stats.nseq);
+    println!("Total length: {}", stats.total_len);
+    for s in stats.schemes.iter() {
+        println!("\n(w,k) = ({},{})", s.w, s.k);
+        println!("  Distinct minimisers: {}", s.occ.distinct);
+        println!("  Total occurrences: {}", s.occ.total);
+        println!("  Max occurrences: {}", s.occ.max);
+        println!(
+            "  Density: {:.4} (expected {:.4})",
+            s.density, s.expected_density
+        );
+        println!("  Memory: {} bytes", s.mem_bytes);
+        println!("  Occurrence histogram (occurrences: minimisers):");
+        for (c, n) in s.occ.hist.iter() {
+            println!("    {}: {}", c, n);
+        }
+    }
+}
+
+/// Parses a samtools-like region `name[:start[-end]]`, with 1-based
+/// inclusive coordinates, into a 0-based half-open interval.
+/// As in samtools, the whole region is a name if `fai` has a sequence
+/// with that name, so that names holding ':' can be fetched whole.
+fn parse_region<'r>(region: &'r str, fai: &FaiIndex) -> Option<(&'r str, u64, u64)> {
+    if fai.get(region).is_some() {
+        return Some((region, 0, u64::MAX));
+    }
+    let (name, range) = match region.rfind(':') {
+        Some(i) => (&region[..i], &region[i + 1..]),
+        None => return Some((region, 0, u64::MAX)),
+    };
+    let range = range.replace(',', "");
+    let mut bounds = range.splitn(2, '-');
+    let start = bounds.next()?.parse::<u64>().ok()?;
+    let end = match bounds.next() {
+        Some(e) => e.parse::<u64>().ok()?,
+        None => u64::MAX,
+    };
+    if start == 0 {
+        return None;
+    }
+    Some((name, start - 1, end))
+}
+
+fn faidx(input_filename: &str, regions: &[&str]) {
+    if input_filename == STDIN_PATH {
+        println!("Error: The standard input cannot be indexed, faidx needs a FASTA file.");
+        std::process::exit(1);
+    }
+    let fai_filename = index_path(input_filename, ".fai");
+    if !fai_filename.exists() {
+        let fai = FaiIndex::build_from_path(input_filename).unwrap_or_else(|e| {
+            println!("Error: Unable to index {}: {}", input_filename, e);
+            std::process::exit(1);
+        });
+        let file = File::create(&fai_filename).expect("Cannot create FASTA index file");
+        fai.write(&mut BufWriter::new(file))
+            .expect("Error saving FASTA index");
+    }
+    let decoder = Decoder::new_from_path(input_filename).expect("Cannot open input FASTA file");
+    let gzi_filename = index_path(input_filename, ".gzi");
+    if decoder.compression() == Compression::Bgzf && !gzi_filename.exists() {
+        let file = File::open(input_filename).expect("Cannot open input FASTA file");
+        let gzi = GziIndex::build(&mut BufReader::new(file)).expect("Error indexing BGZF blocks");
+        let file = File::create(&gzi_filename).expect("Cannot create BGZF index file");
+        gzi.write(&mut BufWriter::new(file))
+            .expect("Error saving BGZF index");
+    }
+    if regions.is_empty() {
+        return;
+    }
+    let mut reader = FastaReader::new_indexed_from_path(input_filename).unwrap_or_else(|e| {
+        println!("Error: Unable to open {}: {}", input_filename, e);
+        std::process::exit(1);
+    });
+    for region in regions {
+        let (name, start, end) = match parse_region(region, reader.fai().unwrap()) {
+            Some(r) => r,
+            None => {
+                println!("Error: Invalid region {}", region);
+                std::process::exit(1);
+            }
+        };
+        match reader.fetch(name, start, end) {
+            Ok(seq) => {
+                println!(">{}", region);
+                for line in seq[..].chunks(60) {
+                    println!("{}", String::from_utf8_lossy(line));
+                }
+            }
+            Err(e) => {
+                println!("Error: Unable to fetch {}: {}", region, e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+fn input_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("input")
+        .index(1)
+        .short("i")
+        .help("input FASTA, FASTQ or .2bit file, or - for the standard input")
+        .value_name("FILE")
+        .takes_value(true)
+        .required(true)
+}
+
+fn strict_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("strict")
+        .long("strict")
+        .help("Reject malformed FASTA input instead of skipping blank lines, comments and whitespace (single-threaded)")
+}
+
+fn window_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("window")
+        .short("w")
+        .help("Window sizes = # of kmers")
+        .value_name("SIZES")
+        .required(false)
+        .requires("kmer")
+        .takes_value(true)
+        .default_value("20")
+        .min_values(1)
+}
+
+fn kmer_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("kmer")
+        .short("k")
+        .help("Minimiser lengths")
+        .value_name("SIZES")
+        .required(false)
+        .requires("window")
+        .takes_value(true)
+        .default_value("10")
+        .min_values(1)
+}
+
+/// Parses the value of option `name`, if given, exiting with an error
+/// message if it is not a valid `what`.
+fn value_of<T: FromStr>(matches: &ArgMatches, name: &str, what: &str) -> Option<T> {
+    matches.value_of(name).map(|v| {
+        v.parse::<T>().unwrap_or_else(|_| {
+            println!(
+                "Error: Invalid {} {} for --{}.",
+                what,
+                v,
+                name.replace('_', "-")
+            );
+            std::process::exit(1);
+        })
+    })
+}
+
+fn sizes_of(matches: &ArgMatches, name: &str) -> Vec<usize> {
+    matches
+        .values_of(name)
+        .unwrap()
+        .map(|x| x.parse::<usize>().unwrap())
+        .collect()
+}
+
 fn main() {
     let matches = App::new("VMAT - Variable Minimiser Alignment Tool")
         .version("0.1")
@@ -69,15 +477,7 @@ fn main() {
                 .about("Creates the index")
                 //.version("1.3")
                 //.author("Someone E. <someone_else@other.com>")
-                .arg(
-                    Arg::with_name("input")
-                        .index(1)
-                        .short("i")
-                        .help("input file")
-                        .value_name("FILE")
-                        .takes_value(true)
-                        .required(true),
-                )
+                .arg(input_arg())
                 .arg(
                     Arg::with_name("output")
                         .short("o")
@@ -87,48 +487,175 @@ fn main() {
                         .required(false),
                 )
                 .arg(
-                    Arg::with_name("window")
-                        .short("w")
-                        .help("Window sizes = # of kmers")
-                        .value_name("SIZES")
-                        .required(false)
-                        .requires("kmer")
+                    Arg::with_name("append")
+                        .short("a")
+                        .long("append")
+                        .help("Append the input sequences to an existing index, with its window sizes and minimiser lengths")
+                        .value_name("INDEX")
+                        .takes_value(true),
+                )
+                .arg(window_arg())
+                .arg(kmer_arg())
+                .arg(strict_arg())
+                .arg(
+                    Arg::with_name("threads")
+                        .short("t")
+                        .long("threads")
+                        .help("Number of threads parsing the input")
+                        .value_name("N")
+                        .takes_value(true)
+                        .default_value("1"),
+                )
+                .arg(
+                    Arg::with_name("max_occ")
+                        .long("max-occ")
+                        .help("Filter out minimisers with more than N occurrences")
+                        .value_name("N")
                         .takes_value(true)
-                        .default_value("20")
-                        .min_values(1),
+                        .conflicts_with("max_occ_frac"),
                 )
                 .arg(
-                    Arg::with_name("kmer")
-                        .short("k")
-                        .help("Minimiser lengths")
-                        .value_name("SIZES")
-                        .required(false)
-                        .requires("window")
+                    Arg::with_name("max_occ_frac")
+                        .long("max-occ-frac")
+                        .help("Filter out the fraction F of most frequent minimisers")
+                        .value_name("F")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("flag_high_freq")
+                        .long("flag-high-freq")
+                        .help("Keep filtered minimisers flagged as high-frequency instead of dropping them"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("merge")
+                .about("Merges indexes built with the same parameters")
+                .arg(
+                    Arg::with_name("input")
+                        .index(1)
+                        .help("Index files")
+                        .value_name("FILES")
+                        .takes_value(true)
+                        .required(true)
+                        .min_values(2),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .help("Merged index output file")
+                        .value_name("FILE")
+                        .takes_value(true)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("stats")
+                .about("Reports the statistics of a saved index, or of the index of sequences")
+                .arg(input_arg().help(
+                    "input index, FASTA, FASTQ or .2bit file, or - for the standard input",
+                ))
+                .arg(window_arg())
+                .arg(kmer_arg())
+                .arg(strict_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("faidx")
+                .about("Indexes a FASTA file (.fai) and fetches regions name[:start[-end]]")
+                .arg(input_arg().help("input FASTA file, uncompressed or BGZF compressed"))
+                .arg(
+                    Arg::with_name("region")
+                        .index(2)
+                        .help("Regions to fetch, with 1-based inclusive coordinates")
+                        .value_name("REGIONS")
                         .takes_value(true)
-                        .default_value("10")
-                        .min_values(1),
+                        .multiple(true),
                 ),
         )
         .get_matches();
 
     if let Some(matches) = matches.subcommand_matches("index") {
         let input_filename = matches.value_of("input").unwrap();
-        let output_filename = match matches.value_of("output") {
-            Some(filename) => String::from(filename),
-            None => format!("{}.idx", input_filename),
+        let append_filename = matches.value_of("append");
+        let output_filename = match (matches.value_of("output"), append_filename) {
+            (Some(filename), _) => String::from(filename),
+            (None, Some(filename)) => String::from(filename),
+            (None, None) if input_filename == STDIN_PATH => {
+                println!(
+                    "Error: An output file (-o) is required when indexing the standard input."
+                );
+                std::process::exit(1);
+            }
+            (None, None) => format!("{}.idx", input_filename),
+        };
+        let (w, k) = match append_filename {
+            Some(filename)
+                if matches.occurrences_of("window") == 0 && matches.occurrences_of("kmer") == 0 =>
+            {
+                index_schemes(filename)
+            }
+            _ => (sizes_of(matches, "window"), sizes_of(matches, "kmer")),
+        };
+        let occ_thresh = if let Some(c) = value_of(matches, "max_occ", "occurrence count") {
+            Some(OccThreshold::Count(c))
+        } else if let Some(f) = value_of::<f64>(matches, "max_occ_frac", "fraction") {
+            if !(0.0..=1.0).contains(&f) {
+                println!(
+                    "Error: The fraction of most frequent minimisers (--max-occ-frac) must be between 0 and 1."
+                );
+                std::process::exit(1);
+            }
+            Some(OccThreshold::TopFraction(f))
+        } else {
+            None
+        };
+        let hf_action = if matches.is_present("flag_high_freq") {
+            HighFreqAction::Flag
+        } else {
+            HighFreqAction::Drop
         };
-        let w: Vec<usize> = matches
-            .values_of("window")
-            .unwrap()
-            .map(|x| usize::from_str_radix(x, 10).unwrap())
-            .collect();
-        let k: Vec<usize> = matches
-            .values_of("kmer")
-            .unwrap()
-            .map(|x| usize::from_str_radix(x, 10).unwrap())
-            .collect();
         println!("Windows={0:?}", w);
         println!("Kmer={0:?}", k);
-        index(input_filename, output_filename.as_str(), &w, &k);
+        let nthreads: usize = value_of(matches, "threads", "number of threads").unwrap();
+        if nthreads == 0 {
+            println!("Error: The number of threads (--threads) must be at least 1.");
+            std::process::exit(1);
+        }
+        index(
+            input_filename,
+            nthreads,
+            output_filename.as_str(),
+            append_filename,
+            &w,
+            &k,
+            occ_thresh,
+            hf_action,
+            matches.is_present("strict"),
+        );
+    } else if let Some(matches) = matches.subcommand_matches("merge") {
+        let input_filenames: Vec<&str> = matches.values_of("input").unwrap().collect();
+        let output_filename = matches.value_of("output").unwrap();
+        merge(&input_filenames, output_filename);
+    } else if let Some(matches) = matches.subcommand_matches("stats") {
+        let input_filename = matches.value_of("input").unwrap();
+        let saved_schemes = if input_filename == STDIN_PATH {
+            None
+        } else {
+            read_schemes(input_filename).ok()
+        };
+        match saved_schemes {
+            Some((w, k)) => stats(input_filename, true, &w, &k, false),
+            None => {
+                let w = sizes_of(matches, "window");
+                let k = sizes_of(matches, "kmer");
+                stats(input_filename, false, &w, &k, matches.is_present("strict"));
+            }
+        }
+    } else if let Some(matches) = matches.subcommand_matches("faidx") {
+        let input_filename = matches.value_of("input").unwrap();
+        let regions: Vec<&str> = matches
+            .values_of("region")
+            .map(|r| r.collect())
+            .unwrap_or_default();
+        faidx(input_filename, &regions);
     }
 }
@@ -1,14 +1,135 @@
-use std::collections::HashMap;
-use std::io;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{self, Read, Write};
 use std::marker::PhantomData;
+use std::mem::size_of;
 
 use crate::alphabet::Character;
-use crate::mqueue::MQueue;
+use crate::binio;
+use crate::mmiter::MmIter;
+use crate::srchutil;
 use crate::xstream::{XStrStream, XStream};
 use crate::xstring::{XString, XStrRollHasher};
 
 
-type TMmRank = u64;
+pub type TMmRank = u64;
+
+/// Identifies saved minimiser index files
+const MMINDEX_MAGIC: &[u8] = b"VMATMMI1";
+
+/// Number of probe kmers kept, with their ranks, to check that the hashers
+/// given to a loaded or merged index are the ones it was built with.
+/// The probes are made of the chars of the hashers, or sampled from the
+/// indexed sequences if the hashers do not tell their chars.
+const HASHER_SAMPLES: usize = 8;
+
+/// Occurrence count threshold above which a minimiser is considered
+/// a *high-frequency* minimiser.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OccThreshold {
+    /// Minimisers with more than the given number of occurrences.
+    Count(usize),
+    /// The given fraction of the most frequent distinct minimisers.
+    TopFraction(f64),
+}
+
+/// What to do with high-frequency minimisers when the index is filtered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HighFreqAction {
+    /// Remove them and their positions from the index.
+    Drop,
+    /// Keep them in the index but mark them as high-frequency.
+    Flag,
+}
+
+/// How queries treat flagged high-frequency minimisers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HighFreqPolicy {
+    /// Report all occurrences.
+    Keep,
+    /// Report no occurrence.
+    Skip,
+    /// Report at most (approximately) the given number of evenly spaced
+    /// occurrences. `Subsample(0)` reports no occurrence, like `Skip`.
+    Subsample(usize),
+}
+
+/// Occurrence count statistics of the minimisers of one `(w,k)` scheme.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OccStats {
+    /// Number of distinct minimisers
+    pub distinct: usize,
+    /// Total number of minimiser occurrences
+    pub total: usize,
+    /// Largest occurrence count of a single minimiser
+    pub max: usize,
+    /// Occurrence count histogram: `hist[c]` is the number of distinct
+    /// minimisers that occur exactly `c` times.
+    pub hist: BTreeMap<usize, usize>,
+}
+
+impl OccStats {
+    /// Returns the occurrence count above which minimisers are
+    /// high-frequency according to `thresh`, or `None` if no minimiser is.
+    /// Fails with `InvalidInput` if a `TopFraction` is not in `[0,1]`.
+    pub fn cutoff(&self, thresh: OccThreshold) -> Result<Option<usize>, io::Error> {
+        let cutoff = match thresh {
+            OccThreshold::Count(c) => c,
+            OccThreshold::TopFraction(f) => {
+                if !(0.0..=1.0).contains(&f) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("Invalid fraction {} of most frequent minimisers", f),
+                    ));
+                }
+                // rank of the least frequent minimiser among the top ones
+                let r = ((1.0 - f) * self.distinct as f64) as usize;
+                let mut seen = 0;
+                let mut cutoff = self.max;
+                for (&c, &n) in self.hist.iter() {
+                    seen += n;
+                    if seen > r {
+                        cutoff = c;
+                        break;
+                    }
+                }
+                cutoff
+            }
+        };
+        if cutoff < self.max {
+            Ok(Some(cutoff))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// Statistics of the minimisers of one `(w,k)` scheme of an index.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SchemeStats {
+    pub w: usize,
+    pub k: usize,
+    /// Occurrence count statistics
+    pub occ: OccStats,
+    /// Number of kmers in the indexed sequences
+    pub nkmers: usize,
+    /// Fraction of the kmers that are indexed minimiser occurrences
+    pub density: f64,
+    /// Expected density `2/(w+1)` of random sequences
+    pub expected_density: f64,
+    /// Approximate memory used by the table, in bytes
+    pub mem_bytes: usize,
+}
+
+/// Statistics of a minimiser index.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IndexStats {
+    /// Number of indexed sequences
+    pub nseq: usize,
+    /// Total length of the indexed sequences
+    pub total_len: usize,
+    /// Per `(w,k)` scheme statistics
+    pub schemes: Vec<SchemeStats>,
+}
 
 /**
  * The minimiser index is as associative array that keeps references to the
@@ -31,11 +152,19 @@ where
     ac: PhantomData<C>,
     w: Vec<usize>,
     k: Vec<usize>,
-    max_wlen: usize,
     hasher: Vec<&'a H>,
     tables: Vec<HashMap<TMmRank, Vec<usize>>>,
     nseq: usize,
     offs: Vec<usize>,
+    occ_cutoff: Vec<Option<usize>>,
+    /// What is done with the minimisers above the occurrence cutoffs
+    hf_action: HighFreqAction,
+    /// Flagged high-frequency minimisers, which are kept in the tables
+    high_freq: Vec<HashSet<TMmRank>>,
+    /// Dropped high-frequency minimisers, which are no longer indexed
+    dropped: Vec<HashSet<TMmRank>>,
+    names: Vec<String>,
+    samples: Vec<Vec<(XString<C>, TMmRank)>>,
 }
 
 impl<'a, C, H> MmIndex<'a, C, H>
@@ -48,48 +177,459 @@ where
         assert_eq!(l, k.len());
         assert!(*w.iter().min_by(|x, y| x.cmp(y)).unwrap() > 0);
         assert!(*k.iter().min_by(|x, y| x.cmp(y)).unwrap() > 0);
-        // maximum necessary window buffer length
-        // (!) all w,k are > 0 but, when w=1, the window has only one kmer.
-        //     because we need the previous last window kmer to
-        //     roll the hash value, we make sure that the window will
-        //     will always contain at least two kmers for any (w,k).
-        let max_wlen = w
-            .iter()
-            .zip(k.iter())
-            .map(|(a, b)| a + b)
-            .max()
-            .unwrap(); // (!) if w[i] == 1, w[i]+k[i] == k[i]+1, which holds two kmers
 
         MmIndex {
             ac: Default::default(),
             w: Vec::from(w),
             k: Vec::from(k),
-            max_wlen,
             hasher: Vec::from(hashers),
             tables: vec![HashMap::new(); l],
             nseq: 0,
             offs: vec![0],
+            occ_cutoff: vec![None; l],
+            hf_action: HighFreqAction::Drop,
+            high_freq: vec![HashSet::new(); l],
+            dropped: vec![HashSet::new(); l],
+            names: vec![],
+            samples: hashers
+                .iter()
+                .zip(k.iter())
+                .map(|(hasher, &k)| Self::probes(hasher, k))
+                .collect(),
         }
     }
 
+    /// Makes the probe kmers of a scheme from the chars of its hasher,
+    /// with their ranks, or none if the chars are unknown.
+    fn probes(hasher: &H, k: usize) -> Vec<(XString<C>, TMmRank)> {
+        let chars = hasher.chars();
+        if chars.is_empty() {
+            return vec![];
+        }
+        let mut x: u64 = 0;
+        let mut next_char = || {
+            x = x
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            chars[(x >> 33) as usize % chars.len()]
+        };
+        (0..HASHER_SAMPLES)
+            .map(|_| {
+                let kmer = XString::from((0..k).map(|_| next_char()).collect::<Vec<C>>());
+                let rk = hasher.hash(&kmer);
+                (kmer, rk)
+            })
+            .collect()
+    }
+
+    /// Adds an occurrence of a minimiser, which is dropped or flagged
+    /// as soon as it exceeds the occurrence cutoff of the scheme, if any.
     #[inline]
     fn insert(&mut self, index: usize, mmrk: TMmRank, pos: usize) {
-        if !self.tables[index].contains_key(&mmrk) {
-            self.tables[index].insert(mmrk, vec![pos]);
-        } else {
-            self.tables[index].get_mut(&mmrk).unwrap().push(pos);
+        let cutoff = match self.occ_cutoff[index] {
+            None => {
+                self.tables[index].entry(mmrk).or_default().push(pos);
+                return;
+            }
+            Some(cutoff) => cutoff,
+        };
+        if self.dropped[index].contains(&mmrk) {
+            return;
+        }
+        let occ = self.tables[index].entry(mmrk).or_default();
+        occ.push(pos);
+        if occ.len() > cutoff {
+            match self.hf_action {
+                HighFreqAction::Drop => {
+                    self.tables[index].remove(&mmrk);
+                    self.dropped[index].insert(mmrk);
+                }
+                HighFreqAction::Flag => {
+                    self.high_freq[index].insert(mmrk);
+                }
+            }
         }
     }
 
-    fn get_abs(&self, index: usize, mmrk: TMmRank) -> Option<&[usize]> {
+    /// Returns all the absolute positions of the minimiser of rank `mmrk`
+    /// in the `index`-th `(w,k)` scheme, including high-frequency ones.
+    pub fn get_abs(&self, index: usize, mmrk: TMmRank) -> Option<&[usize]> {
         match self.tables[index].get(&mmrk) {
             None => None,
-            Some(v) => Some(&v),
+            Some(v) => Some(v),
+        }
+    }
+
+    /// Returns the absolute positions of the minimiser of rank `mmrk`
+    /// in the `index`-th `(w,k)` scheme, if any. If the minimiser has been
+    /// flagged as high-frequency, its positions are reported according to `policy`.
+    pub fn query(
+        &self,
+        index: usize,
+        mmrk: TMmRank,
+        policy: HighFreqPolicy,
+    ) -> impl Iterator<Item = &usize> + '_ {
+        let occ = self.get_abs(index, mmrk).unwrap_or(&[]);
+        let (occ, step) = if !self.is_high_freq(index, mmrk) {
+            (occ, 1)
+        } else {
+            match policy {
+                HighFreqPolicy::Keep => (occ, 1),
+                HighFreqPolicy::Skip | HighFreqPolicy::Subsample(0) => (&occ[..0], 1),
+                HighFreqPolicy::Subsample(n) => (occ, std::cmp::max(1, occ.len().div_ceil(n))),
+            }
+        };
+        occ.iter().step_by(step)
+    }
+
+    /// Tells whether the minimiser of rank `mmrk` in the `index`-th scheme
+    /// has been flagged as high-frequency.
+    pub fn is_high_freq(&self, index: usize, mmrk: TMmRank) -> bool {
+        self.high_freq[index].contains(&mmrk)
+    }
+
+    /// Computes the occurrence count statistics of the `index`-th scheme.
+    pub fn occ_stats(&self, index: usize) -> OccStats {
+        let mut stats = OccStats::default();
+        for occ in self.tables[index].values() {
+            stats.distinct += 1;
+            stats.total += occ.len();
+            stats.max = std::cmp::max(stats.max, occ.len());
+            *stats.hist.entry(occ.len()).or_insert(0) += 1;
+        }
+        stats
+    }
+
+    /// Computes the statistics of the index.
+    pub fn stats(&self) -> IndexStats {
+        let schemes = (0..self.tables.len())
+            .map(|i| {
+                let nkmers = self
+                    .offs
+                    .windows(2)
+                    .map(|o| (o[1] - o[0] + 1).saturating_sub(self.k[i]))
+                    .sum();
+                let occ = self.occ_stats(i);
+                let density = if nkmers > 0 {
+                    occ.total as f64 / nkmers as f64
+                } else {
+                    0.0
+                };
+                SchemeStats {
+                    w: self.w[i],
+                    k: self.k[i],
+                    occ,
+                    nkmers,
+                    density,
+                    expected_density: 2.0 / (self.w[i] + 1) as f64,
+                    mem_bytes: self.mem_usage(i),
+                }
+            })
+            .collect();
+        IndexStats {
+            nseq: self.nseq,
+            total_len: self.total_len(),
+            schemes,
+        }
+    }
+
+    /// Approximate number of bytes used by the table of the `index`-th scheme,
+    /// including the list of flagged high-frequency minimisers.
+    pub fn mem_usage(&self, index: usize) -> usize {
+        let table = &self.tables[index];
+        let flagged = &self.high_freq[index];
+        table.capacity() * (size_of::<(TMmRank, Vec<usize>)>() + 1)
+            + table
+                .values()
+                .map(|occ| occ.capacity() * size_of::<usize>())
+                .sum::<usize>()
+            + flagged.capacity() * (size_of::<TMmRank>() + 1)
+    }
+
+    /// The `(w,k)` parameters of each scheme.
+    pub fn schemes(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.w.iter().copied().zip(self.k.iter().copied())
+    }
+
+    /// Name of the `seq`-th indexed sequence (empty if not set).
+    pub fn seq_name(&self, seq: usize) -> &str {
+        &self.names[seq]
+    }
+
+    /// Sets the name of the `seq`-th indexed sequence.
+    pub fn set_seq_name(&mut self, seq: usize, name: &str) {
+        self.names[seq] = String::from(name);
+    }
+
+    /// Number of indexed sequences.
+    pub fn nseq(&self) -> usize {
+        self.nseq
+    }
+
+    /// Total length of the indexed sequences.
+    pub fn total_len(&self) -> usize {
+        *self.offs.last().unwrap()
+    }
+
+    /// Start positions of the indexed sequences in their concatenation,
+    /// followed by the total length.
+    pub fn seq_offsets(&self) -> &[usize] {
+        &self.offs
+    }
+
+    /// Returns the index of the sequence holding the absolute position `pos`,
+    /// as returned by queries, and the offset of `pos` in it.
+    pub fn seq_pos(&self, pos: usize) -> (usize, usize) {
+        assert!(pos < self.total_len());
+        // the last of equal offsets is the start of the non-empty sequence
+        let seq = srchutil::pred(&self.offs, &pos).unwrap();
+        (seq, pos - self.offs[seq])
+    }
+
+    /// Drops or flags, in every scheme, the minimisers whose number of
+    /// occurrences exceeds the cutoff given by `thresh`.
+    /// Returns the cutoffs actually used, which are also recorded in the index
+    /// and applied, with the same `action`, to the minimisers of the sequences
+    /// indexed afterwards: a minimiser is dropped or flagged as soon as it
+    /// exceeds the cutoff, and dropped minimisers are no longer indexed.
+    /// Fails with `InvalidInput`, leaving the index unchanged, if `thresh`
+    /// is an invalid fraction.
+    pub fn filter_high_freq(
+        &mut self,
+        thresh: OccThreshold,
+        action: HighFreqAction,
+    ) -> Result<&[Option<usize>], io::Error> {
+        let cutoffs = (0..self.tables.len())
+            .map(|i| self.occ_stats(i).cutoff(thresh))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.hf_action = action;
+        for (i, &cutoff) in cutoffs.iter().enumerate() {
+            self.occ_cutoff[i] = cutoff;
+            self.apply_cutoff(i);
+        }
+        Ok(&self.occ_cutoff)
+    }
+
+    /// Drops or flags, with the recorded action, the minimisers of the
+    /// `index`-th scheme whose number of occurrences exceeds its recorded
+    /// cutoff, if any.
+    fn apply_cutoff(&mut self, index: usize) {
+        let cutoff = match self.occ_cutoff[index] {
+            Some(c) => c,
+            None => return,
+        };
+        let (dropped, high_freq) = (&mut self.dropped[index], &mut self.high_freq[index]);
+        match self.hf_action {
+            HighFreqAction::Drop => self.tables[index].retain(|&mmrk, occ| {
+                if occ.len() > cutoff {
+                    dropped.insert(mmrk);
+                }
+                occ.len() <= cutoff
+            }),
+            HighFreqAction::Flag => {
+                for (&mmrk, occ) in self.tables[index].iter() {
+                    if occ.len() > cutoff {
+                        high_freq.insert(mmrk);
+                    }
+                }
+            }
+        }
+    }
+
+    /// The occurrence count cutoffs used to filter high-frequency minimisers
+    /// of each scheme, if any.
+    pub fn occ_cutoffs(&self) -> &[Option<usize>] {
+        &self.occ_cutoff
+    }
+
+    /// What is done with the minimisers above the occurrence cutoffs.
+    pub fn high_freq_action(&self) -> HighFreqAction {
+        self.hf_action
+    }
+
+    /// Writes the index to `dst` in binary format.
+    /// The hashers are not saved, only a few probe kmers and their ranks
+    /// used to validate the hashers given to [`MmIndex::load`].
+    pub fn save<W: Write>(&self, dst: &mut W) -> Result<(), io::Error> {
+        dst.write_all(MMINDEX_MAGIC)?;
+        binio::write_usizes(dst, &self.w)?;
+        binio::write_usizes(dst, &self.k)?;
+        binio::write_u8(dst, (self.hf_action == HighFreqAction::Flag) as u8)?;
+        for i in 0..self.w.len() {
+            binio::write_opt_usize(dst, self.occ_cutoff[i])?;
+            binio::write_usize(dst, self.dropped[i].len())?;
+            for &mmrk in self.dropped[i].iter() {
+                binio::write_u64(dst, mmrk)?;
+            }
+            binio::write_usize(dst, self.samples[i].len())?;
+            for (kmer, rk) in self.samples[i].iter() {
+                binio::write_usize(dst, kmer.len())?;
+                for &c in kmer.iter() {
+                    binio::write_u32(dst, c.to_u32())?;
+                }
+                binio::write_u64(dst, *rk)?;
+            }
+            binio::write_usize(dst, self.tables[i].len())?;
+            for (&mmrk, occ) in self.tables[i].iter() {
+                binio::write_u64(dst, mmrk)?;
+                binio::write_u8(dst, self.high_freq[i].contains(&mmrk) as u8)?;
+                binio::write_usizes(dst, occ)?;
+            }
+        }
+        binio::write_usizes(dst, &self.offs)?;
+        for name in self.names.iter() {
+            binio::write_str(dst, name)?;
+        }
+        Ok(())
+    }
+
+    /// Reads an index saved with [`MmIndex::save`] from `src`.
+    /// Fails with `InvalidData` if the saved index was not built
+    /// with the given `(w,k)` schemes and hashers.
+    pub fn load<R: Read>(
+        src: &mut R,
+        w: &[usize],
+        k: &[usize],
+        hashers: &[&'a H],
+    ) -> Result<Self, io::Error> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let (saved_w, saved_k) = read_schemes(src)?;
+        if saved_w != w || saved_k != k {
+            return Err(invalid(&format!(
+                "Index (w,k) schemes w={:?} k={:?} do not match w={:?} k={:?}",
+                saved_w, saved_k, w, k
+            )));
+        }
+        let mut mmindex = Self::new(w, k, hashers);
+        if binio::read_u8(src)? != 0 {
+            mmindex.hf_action = HighFreqAction::Flag;
+        }
+        for i in 0..w.len() {
+            mmindex.occ_cutoff[i] = binio::read_opt_usize(src)?;
+            let ndropped = binio::read_usize(src)?;
+            for _ in 0..ndropped {
+                mmindex.dropped[i].insert(binio::read_u64(src)?);
+            }
+            mmindex.samples[i].clear();
+            let nsamples = binio::read_usize(src)?;
+            for _ in 0..nsamples {
+                let len = binio::read_usize(src)?;
+                let mut kmer = XString::new();
+                for _ in 0..len {
+                    let c = C::from_u32(binio::read_u32(src)?)
+                        .ok_or_else(|| invalid("Invalid character"))?;
+                    kmer.push(c);
+                }
+                let rk = binio::read_u64(src)?;
+                mmindex.samples[i].push((kmer, rk));
+            }
+            if !mmindex.hasher_matches(i, &mmindex.samples[i]) {
+                return Err(invalid(&format!(
+                    "Hasher of scheme {} does not match the one used to build the index",
+                    i
+                )));
+            }
+            let nmm = binio::read_usize(src)?;
+            for _ in 0..nmm {
+                let mmrk = binio::read_u64(src)?;
+                if binio::read_u8(src)? != 0 {
+                    mmindex.high_freq[i].insert(mmrk);
+                }
+                mmindex.tables[i].insert(mmrk, binio::read_usizes(src)?);
+            }
         }
+        mmindex.offs = binio::read_usizes(src)?;
+        if mmindex.offs.is_empty() {
+            return Err(invalid("Missing sequence offsets"));
+        }
+        mmindex.nseq = mmindex.offs.len() - 1;
+        for _ in 0..mmindex.nseq {
+            mmindex.names.push(binio::read_string(src)?);
+        }
+        Ok(mmindex)
     }
 
-    fn get_seq_lengths(&self) -> &[usize] {
-        &self.offs[1..]
+    /// Tells whether the hasher of the `index`-th scheme accepts the
+    /// probe kmers of another index and ranks them as its hasher did.
+    /// An index whose hasher does not tell its chars has no probes until it
+    /// indexes sequences, so any hasher matches it.
+    fn hasher_matches(&self, index: usize, samples: &[(XString<C>, TMmRank)]) -> bool {
+        let hasher = self.hasher[index];
+        let chars = hasher.chars();
+        if samples.is_empty() {
+            return chars.is_empty();
+        }
+        samples.iter().all(|(kmer, rk)| {
+            kmer.len() == self.k[index]
+                && (chars.is_empty() || kmer.iter().all(|c| chars.contains(c)))
+                && hasher.hash(kmer) == *rk
+        })
+    }
+
+    /// Appends the sequences indexed by `other` to this index, as if they
+    /// had been indexed after the sequences of this index.
+    /// Both indexes must have the same `(w,k)` schemes and hashers.
+    ///
+    /// The stricter of the recorded occurrence cutoffs of both indexes is kept
+    /// for each scheme, and the high-frequency action of `other` is adopted
+    /// if this index has no recorded cutoff. The flagged and dropped
+    /// minimisers of both indexes are unioned: the occurrences of dropped
+    /// minimisers are removed, then the cutoffs are applied to the merged
+    /// occurrence counts, as they are to sequences indexed afterwards.
+    pub fn merge(&mut self, other: &MmIndex<C, H>) -> Result<(), io::Error> {
+        if self.w != other.w || self.k != other.k {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Index (w,k) schemes w={:?} k={:?} do not match w={:?} k={:?}",
+                    other.w, other.k, self.w, self.k
+                ),
+            ));
+        }
+        for i in 0..self.w.len() {
+            if !self.hasher_matches(i, &other.samples[i]) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Hashers of scheme {} do not match", i),
+                ));
+            }
+        }
+        if self.occ_cutoff.iter().all(|c| c.is_none()) {
+            self.hf_action = other.hf_action;
+        }
+        let base = self.total_len();
+        for i in 0..self.w.len() {
+            self.occ_cutoff[i] = match (self.occ_cutoff[i], other.occ_cutoff[i]) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            };
+            self.high_freq[i].extend(other.high_freq[i].iter());
+            if !other.dropped[i].is_empty() {
+                self.dropped[i].extend(other.dropped[i].iter());
+                let dropped = &self.dropped[i];
+                self.tables[i].retain(|mmrk, _| !dropped.contains(mmrk));
+            }
+            for (&mmrk, occ) in other.tables[i].iter() {
+                if self.dropped[i].contains(&mmrk) {
+                    continue;
+                }
+                self.tables[i]
+                    .entry(mmrk)
+                    .or_default()
+                    .extend(occ.iter().map(|p| base + p));
+            }
+            self.apply_cutoff(i);
+            for sample in other.samples[i].iter() {
+                if self.samples[i].len() >= HASHER_SAMPLES {
+                    break;
+                }
+                self.samples[i].push(sample.clone());
+            }
+        }
+        self.offs.extend(other.offs[1..].iter().map(|o| base + o));
+        self.names.extend(other.names.iter().cloned());
+        self.nseq += other.nseq;
+        Ok(())
     }
 
     pub fn index_xstr(&mut self, s: &XString<C>) -> Result<(), io::Error> 
@@ -104,92 +644,32 @@ where
     where
         C: Character,
     {
-        let nidx = self.w.len();
         let offset = *self.offs.last().unwrap();
-        let max_win_len = self.max_wlen;
-        
-        let mut window: XString<C> = XString::new(); // text window containing all kmers
-        let mut win_rks: Vec<MQueue<(TMmRank, usize)>> = vec![MQueue::new_min(); nidx]; // window kmers ranks
-        let mut prev_mm_rk = vec![0 as TMmRank; nidx]; // rank of previous window minimiser
-        let mut prev_right_rk = vec![0 as TMmRank; nidx]; // rank of previous window rightmost kmer
-
-        let mut pos = 0;
-        while let Some(c) = s.get()? {
-            // perpare window
-            if pos >= max_win_len {
-                window.rotate_left(1);
-                window[max_win_len - 1] = c;
-            } else {
-                //read in first window
-                window.push(c);
-            }
-            pos += 1;
-            for i in 0..nidx {
-                if pos == self.k[i] {
-                    let kmer_rk = self.hasher[i].hash(&window[window.len() - self.k[i]..]);
-                    prev_right_rk[i] = kmer_rk;
-                    prev_mm_rk[i] = kmer_rk; 
-                    win_rks[i].push((kmer_rk, pos - self.k[i]));
-                    // initial end minimisers are all indexed
-                    self.insert(i, kmer_rk, offset + pos - self.k[i]);
-                } else if pos > self.k[i] {
-                    // get previous windows minimiser
-                    // let (last_mm_rk, _last_mm_pos) = win_rks[i].xtr().unwrap().clone();
-                    // compute new last kmer rank and add it to the new window
-                    let kmer_rk = self.hasher[i].roll_hash(
-                        &window[window.len() - self.k[i] - 1..window.len() - 1],
-                        prev_right_rk[i],
-                        c,
-                    );
-                    let kmer_pos = pos - self.k[i];
-                    prev_right_rk[i] = kmer_rk;
-                    // dequeue the first kmer of previous window if it is full
-                    if pos > self.w[i] + self.k[i] - 1 {
-                        win_rks[i].pop();
-                    }
-                    // and add new kmer
-                    win_rks[i].push((kmer_rk, kmer_pos));
-                    // then get current window miminiser
-                    let cur_mm_rk = win_rks[i].xtr().unwrap().0;
-                    if self.w[i] == 1 || prev_mm_rk[i] != cur_mm_rk {
-                        // new minimiser. add all its occurrences
-                        for &(rk, p) in win_rks[i].xtr_iter() {
-                            self.insert(i, rk, offset + p);
-                        }
-                        prev_mm_rk[i] = cur_mm_rk;
-                    } else if cur_mm_rk == kmer_rk {
-                        // last kmer is a new occ of same old mm
-                        self.insert(i, kmer_rk, offset + kmer_pos);
-                    }
-                }
+        let mut mms = MmIter::new(s, &self.w, &self.k, &self.hasher);
+        while let Some(mm) = mms.next() {
+            let mm = mm?;
+            self.insert(mm.scheme, mm.rank, offset + mm.pos);
+            if self.samples[mm.scheme].len() < HASHER_SAMPLES {
+                let kmer = mms.last_kmer(mm.scheme).unwrap();
+                let rk = self.hasher[mm.scheme].hash(kmer);
+                self.samples[mm.scheme].push((XString::from(kmer), rk));
             }
         }
-        // index end minimisers
-        let mut still_indexing = true;
-        while still_indexing {
-            still_indexing = false;
-            for i in 0..nidx {
-                if win_rks[i].len() > 1 {
-                    still_indexing = true;
-                    let (last_mm_rk, _last_mm_pos) = win_rks[i].xtr().unwrap().clone();
-                    win_rks[i].pop();
-                    let (cur_mm_rk, _cur_mm_pos) = win_rks[i].xtr().unwrap().clone();
-                    if last_mm_rk != cur_mm_rk {
-                        // new minimiser
-                        for &(rk, p) in win_rks[i].xtr_iter() {
-                            self.insert(i, rk, offset + p);
-                        }
-                    }
-                }
-            }
-        }
-        self.offs.push(pos);
+        self.offs.push(offset + mms.chars_read());
+        self.names.push(String::new());
         self.nseq += 1;
-        //println!("nseq={0} offs={1:?}", self.nseq, self.offs);
         Ok(())
     }
 }
 
+/// Reads the `(w,k)` schemes of an index saved with [`MmIndex::save`],
+/// which are needed to make its hashers before loading it.
+pub fn read_schemes<R: Read>(src: &mut R) -> Result<(Vec<usize>, Vec<usize>), io::Error> {
+    binio::check_magic(src, MMINDEX_MAGIC)?;
+    let w = binio::read_usizes(src)?;
+    let k = binio::read_usizes(src)?;
+    Ok((w, k))
+}
 
 #[cfg(test)]
 mod tests {
@@ -264,4 +744,358 @@ mod tests {
         let occ = mmindex.get_abs(1, ranker[1].hash("GTACGT".as_bytes()));
         println!("GTACGT = {0:?}", occ);
     }
+
+    #[test]
+    fn test_filter_high_freq() {
+        let w = vec![4];
+        let k = vec![3];
+        let ranker = KmerXStrLexHasher::new(Rc::new(DNAAlphabet::new()), k[0]);
+        let ranker_refs = [&ranker];
+        let src = XString::from("AAAAAAAAAAAAAAAAAAAACGTTGCATGCCA".as_bytes());
+        let aaa = ranker.hash("AAA".as_bytes());
+
+        let mut mmindex = MmIndex::new(&w, &k, &ranker_refs);
+        mmindex.index_xstr(&src).expect("Error indexing sequence");
+        let stats = mmindex.occ_stats(0);
+        assert_eq!(stats.max, 18);
+        assert_eq!(stats.hist.values().sum::<usize>(), stats.distinct);
+        assert_eq!(stats.cutoff(OccThreshold::Count(18)).unwrap(), None);
+        assert_eq!(stats.cutoff(OccThreshold::Count(2)).unwrap(), Some(2));
+        assert_eq!(stats.cutoff(OccThreshold::TopFraction(0.0)).unwrap(), None);
+        assert!(stats.cutoff(OccThreshold::TopFraction(1.5)).is_err());
+        assert!(stats.cutoff(OccThreshold::TopFraction(f64::NAN)).is_err());
+        assert!(mmindex
+            .filter_high_freq(OccThreshold::TopFraction(-0.1), HighFreqAction::Drop)
+            .is_err());
+        assert_eq!(mmindex.occ_cutoffs(), &[None]);
+
+        let cutoffs = mmindex
+            .filter_high_freq(OccThreshold::Count(10), HighFreqAction::Flag)
+            .unwrap();
+        assert_eq!(cutoffs, &[Some(10)]);
+        assert!(mmindex.is_high_freq(0, aaa));
+        assert_eq!(mmindex.get_abs(0, aaa).unwrap().len(), 18);
+        assert_eq!(mmindex.query(0, aaa, HighFreqPolicy::Keep).count(), 18);
+        assert_eq!(mmindex.query(0, aaa, HighFreqPolicy::Skip).count(), 0);
+        assert_eq!(
+            mmindex.query(0, aaa, HighFreqPolicy::Subsample(5)).count(),
+            5
+        );
+        assert_eq!(
+            mmindex.query(0, aaa, HighFreqPolicy::Subsample(0)).count(),
+            0
+        );
+        let acg = ranker.hash("ACG".as_bytes());
+        assert!(!mmindex.is_high_freq(0, acg));
+        assert_eq!(
+            mmindex
+                .query(0, acg, HighFreqPolicy::Skip)
+                .collect::<Vec<_>>(),
+            vec![&19]
+        );
+        let ggg = ranker.hash("GGG".as_bytes());
+        assert_eq!(mmindex.query(0, ggg, HighFreqPolicy::Keep).count(), 0);
+
+        let mut mmindex = MmIndex::new(&w, &k, &ranker_refs);
+        mmindex.index_xstr(&src).expect("Error indexing sequence");
+        let distinct = mmindex.occ_stats(0).distinct;
+        let cutoffs = mmindex
+            .filter_high_freq(OccThreshold::TopFraction(0.2), HighFreqAction::Drop)
+            .unwrap();
+        assert_eq!(cutoffs.len(), 1);
+        assert!(mmindex.get_abs(0, aaa).is_none());
+        assert!(!mmindex.is_high_freq(0, aaa));
+        assert_eq!(mmindex.occ_stats(0).distinct, distinct - 1);
+    }
+
+    #[test]
+    fn test_filter_high_freq_later_seqs() {
+        let w = vec![1];
+        let k = vec![3];
+        let ranker = KmerXStrLexHasher::new(Rc::new(DNAAlphabet::new()), k[0]);
+        let ranker_refs = [&ranker];
+        let acg = ranker.hash("ACG".as_bytes());
+        let ttt = ranker.hash("TTT".as_bytes());
+
+        for &action in [HighFreqAction::Drop, HighFreqAction::Flag].iter() {
+            let mut mmindex = MmIndex::new(&w, &k, &ranker_refs);
+            mmindex
+                .index_xstr(&XString::from("ACGTTTTT".as_bytes()))
+                .expect("Error indexing sequence");
+            mmindex
+                .filter_high_freq(OccThreshold::Count(2), action)
+                .unwrap();
+            // TTT is above the cutoff, ACG reaches it later
+            assert_eq!(mmindex.high_freq_action(), action);
+            mmindex
+                .index_xstr(&XString::from("ACGACGTTT".as_bytes()))
+                .expect("Error indexing sequence");
+            match action {
+                HighFreqAction::Drop => {
+                    assert!(mmindex.get_abs(0, ttt).is_none());
+                    assert!(mmindex.get_abs(0, acg).is_none());
+                }
+                HighFreqAction::Flag => {
+                    assert_eq!(mmindex.get_abs(0, ttt).unwrap().len(), 4);
+                    assert_eq!(mmindex.get_abs(0, acg).unwrap(), &[0, 8, 11]);
+                    assert!(mmindex.is_high_freq(0, ttt));
+                    assert!(mmindex.is_high_freq(0, acg));
+                }
+            }
+            let mut saved: Vec<u8> = vec![];
+            mmindex.save(&mut saved).expect("Error saving index");
+            let loaded =
+                MmIndex::load(&mut &saved[..], &w, &k, &ranker_refs).expect("Error loading index");
+            assert_eq!(loaded.high_freq_action(), action);
+            assert_eq!(loaded.dropped, mmindex.dropped);
+            assert_eq!(loaded.high_freq, mmindex.high_freq);
+        }
+    }
+
+    #[test]
+    fn test_stats() {
+        let w = vec![4, 1];
+        let k = vec![3, 2];
+        let ranker = [
+            KmerXStrLexHasher::new(Rc::new(DNAAlphabet::new()), k[0]),
+            KmerXStrLexHasher::new(Rc::new(DNAAlphabet::new()), k[1]),
+        ];
+        let ranker_refs = [&ranker[0], &ranker[1]];
+        let mut mmindex = MmIndex::new(&w, &k, &ranker_refs);
+        mmindex
+            .index_xstr(&XString::from("ACGTTGCATGCCA".as_bytes()))
+            .expect("Error indexing sequence");
+        mmindex
+            .index_xstr(&XString::from("TTGACCA".as_bytes()))
+            .expect("Error indexing sequence");
+        mmindex
+            .index_xstr(&XString::from("GATTACA".as_bytes()))
+            .expect("Error indexing sequence");
+        assert_eq!(mmindex.seq_offsets(), &[0, 13, 20, 27]);
+        assert_eq!(mmindex.seq_pos(0), (0, 0));
+        assert_eq!(mmindex.seq_pos(12), (0, 12));
+        assert_eq!(mmindex.seq_pos(13), (1, 0));
+        assert_eq!(mmindex.seq_pos(26), (2, 6));
+
+        let stats = mmindex.stats();
+        assert_eq!(stats.nseq, 3);
+        assert_eq!(stats.total_len, 27);
+        assert_eq!(stats.schemes.len(), 2);
+        assert_eq!(stats.schemes[0].nkmers, 11 + 5 + 5);
+        assert_eq!(stats.schemes[0].expected_density, 0.4);
+        // with w=1 every kmer is a minimiser
+        assert_eq!(stats.schemes[1].nkmers, 12 + 6 + 6);
+        assert_eq!(stats.schemes[1].occ.total, 24);
+        assert_eq!(stats.schemes[1].density, 1.0);
+        for s in stats.schemes.iter() {
+            assert_eq!(
+                s.occ.hist.iter().map(|(c, n)| c * n).sum::<usize>(),
+                s.occ.total
+            );
+            assert!(s.mem_bytes > 0);
+        }
+    }
+
+    #[test]
+    fn test_save_load_append() {
+        let w = vec![4, 3];
+        let k = vec![3, 5];
+        let ranker = [
+            KmerXStrLexHasher::new(Rc::new(DNAAlphabet::new()), k[0]),
+            KmerXStrLexHasher::new(Rc::new(DNAAlphabet::new()), k[1]),
+        ];
+        let ranker_refs = [&ranker[0], &ranker[1]];
+        let s1 = XString::from("ACGTTGCATGCCAAAAAAAAAAAA".as_bytes());
+        let s2 = XString::from("TTGACCAGATTACA".as_bytes());
+
+        let mut seq_index = MmIndex::new(&w, &k, &ranker_refs);
+        seq_index.index_xstr(&s1).expect("Error indexing sequence");
+        seq_index.set_seq_name(0, "s1");
+        seq_index
+            .filter_high_freq(OccThreshold::Count(5), HighFreqAction::Flag)
+            .unwrap();
+        seq_index.index_xstr(&s2).expect("Error indexing sequence");
+        seq_index.set_seq_name(1, "s2");
+
+        let mut mmindex = MmIndex::new(&w, &k, &ranker_refs);
+        mmindex.index_xstr(&s1).expect("Error indexing sequence");
+        mmindex.set_seq_name(0, "s1");
+        mmindex
+            .filter_high_freq(OccThreshold::Count(5), HighFreqAction::Flag)
+            .unwrap();
+        let mut saved: Vec<u8> = vec![];
+        mmindex.save(&mut saved).expect("Error saving index");
+
+        let mut loaded =
+            MmIndex::load(&mut &saved[..], &w, &k, &ranker_refs).expect("Error loading index");
+        assert_eq!(loaded.nseq(), 1);
+        assert_eq!(loaded.seq_name(0), "s1");
+        assert_eq!(loaded.tables, mmindex.tables);
+        loaded.index_xstr(&s2).expect("Error indexing sequence");
+        loaded.set_seq_name(1, "s2");
+
+        assert_eq!(loaded.tables, seq_index.tables);
+        assert_eq!(loaded.high_freq, seq_index.high_freq);
+        assert_eq!(loaded.occ_cutoffs(), seq_index.occ_cutoffs());
+        assert_eq!(loaded.seq_offsets(), seq_index.seq_offsets());
+        assert_eq!(loaded.names, seq_index.names);
+
+        // mismatched schemes
+        assert!(MmIndex::load(&mut &saved[..], &[4, 3], &[3, 6], &ranker_refs).is_err());
+        // mismatched hashers
+        let letters = [
+            DNAAlphabet::T,
+            DNAAlphabet::G,
+            DNAAlphabet::C,
+            DNAAlphabet::A,
+        ];
+        let other =
+            KmerXStrLexHasher::new(Rc::new(DNAAlphabet::new_with_permutation(&letters)), k[0]);
+        assert!(MmIndex::load(&mut &saved[..], &w, &k, &[&other, &ranker[1]]).is_err());
+        assert_eq!(
+            read_schemes(&mut &saved[..]).unwrap(),
+            (w.clone(), k.clone())
+        );
+
+        // the hashers of an empty index are checked too
+        saved.clear();
+        let empty = MmIndex::new(&w, &k, &ranker_refs);
+        empty.save(&mut saved).expect("Error saving index");
+        assert!(MmIndex::load(&mut &saved[..], &w, &k, &ranker_refs).is_ok());
+        assert!(MmIndex::load(&mut &saved[..], &w, &k, &[&other, &ranker[1]]).is_err());
+        let mut merged = MmIndex::new(&w, &k, &[&other, &ranker[1]]);
+        assert!(merged.merge(&empty).is_err());
+    }
+
+    #[test]
+    fn test_load_garbled() {
+        let w = vec![4, 3];
+        let k = vec![3, 5];
+        let ranker = [
+            KmerXStrLexHasher::new(Rc::new(DNAAlphabet::new()), k[0]),
+            KmerXStrLexHasher::new(Rc::new(DNAAlphabet::new()), k[1]),
+        ];
+        let ranker_refs = [&ranker[0], &ranker[1]];
+        let mut mmindex = MmIndex::new(&w, &k, &ranker_refs);
+        mmindex
+            .index_xstr(&XString::from("ACGTTGCATGCCAAAAAAAAAAAA".as_bytes()))
+            .expect("Error indexing sequence");
+        mmindex.set_seq_name(0, "s1");
+        let mut saved: Vec<u8> = vec![];
+        mmindex.save(&mut saved).expect("Error saving index");
+
+        for len in 0..saved.len() {
+            assert!(MmIndex::load(&mut &saved[..len], &w, &k, &ranker_refs).is_err());
+        }
+        // huge lengths of the schemes, of the last occurrence list and
+        // of the sequence name, which follows the 2 sequence offsets
+        let name_len = saved.len() - 8 - 2;
+        let last_occ = mmindex.tables[1].values().last().unwrap();
+        let occ_len = name_len - 8 * 3 - 8 * (1 + last_occ.len());
+        for &pos in [MMINDEX_MAGIC.len(), occ_len, name_len].iter() {
+            let mut garbled = saved.clone();
+            garbled[pos..pos + 8].copy_from_slice(&(u64::MAX - 1).to_le_bytes());
+            assert!(MmIndex::load(&mut &garbled[..], &w, &k, &ranker_refs).is_err());
+        }
+    }
+
+    #[test]
+    fn test_merge() {
+        let w = vec![5, 2];
+        let k = vec![4, 3];
+        let ranker = [
+            KmerXStrLexHasher::new(Rc::new(DNAAlphabet::new()), k[0]),
+            KmerXStrLexHasher::new(Rc::new(DNAAlphabet::new()), k[1]),
+        ];
+        let ranker_refs = [&ranker[0], &ranker[1]];
+        let seqs = [
+            XString::from("ACGTTGCATGCCAAAAAAAAAAAA".as_bytes()),
+            XString::from("TTGACCAGATTACA".as_bytes()),
+            XString::from("GGGCATTAGACATTTTAGGACCAT".as_bytes()),
+        ];
+
+        let mut seq_index = MmIndex::new(&w, &k, &ranker_refs);
+        for (i, s) in seqs.iter().enumerate() {
+            seq_index.index_xstr(s).expect("Error indexing sequence");
+            seq_index.set_seq_name(i, &format!("s{}", i));
+        }
+
+        let mut merged = MmIndex::new(&w, &k, &ranker_refs);
+        for (i, s) in seqs.iter().enumerate() {
+            let mut part = MmIndex::new(&w, &k, &ranker_refs);
+            part.index_xstr(s).expect("Error indexing sequence");
+            part.set_seq_name(0, &format!("s{}", i));
+            merged.merge(&part).expect("Error merging indexes");
+        }
+        assert_eq!(merged.tables, seq_index.tables);
+        assert_eq!(merged.seq_offsets(), seq_index.seq_offsets());
+        assert_eq!(merged.names, seq_index.names);
+        assert_eq!(merged.nseq(), 3);
+
+        // merging into an empty index adopts the recorded cutoffs, which
+        // are kept by merging an unfiltered index
+        let mut filtered = MmIndex::new(&w, &k, &ranker_refs);
+        filtered
+            .index_xstr(&seqs[0])
+            .expect("Error indexing sequence");
+        filtered
+            .filter_high_freq(OccThreshold::Count(2), HighFreqAction::Flag)
+            .unwrap();
+        let mut merged_filtered = MmIndex::new(&w, &k, &ranker_refs);
+        merged_filtered
+            .merge(&filtered)
+            .expect("Error merging indexes");
+        assert_eq!(merged_filtered.occ_cutoffs(), filtered.occ_cutoffs());
+        assert!(merged_filtered.occ_cutoffs().iter().any(|c| c.is_some()));
+        assert_eq!(merged_filtered.high_freq_action(), HighFreqAction::Flag);
+        assert_eq!(merged_filtered.high_freq, filtered.high_freq);
+        assert_eq!(merged_filtered.tables, filtered.tables);
+        merged_filtered
+            .merge(&merged)
+            .expect("Error merging indexes");
+        assert_eq!(merged_filtered.occ_cutoffs(), filtered.occ_cutoffs());
+
+        // filtered indexes merge as they are built sequentially, also when
+        // minimisers dropped from the first one occur in the next ones
+        let filter_seqs = [
+            seqs[0].clone(),
+            XString::from("CAAAAGTCAAAAGT".as_bytes()),
+            seqs[2].clone(),
+        ];
+        for &action in [HighFreqAction::Drop, HighFreqAction::Flag].iter() {
+            let mut seq_filtered = MmIndex::new(&w, &k, &ranker_refs);
+            let mut merged_filtered = MmIndex::new(&w, &k, &ranker_refs);
+            for (i, s) in filter_seqs.iter().enumerate() {
+                let mut part = MmIndex::new(&w, &k, &ranker_refs);
+                part.index_xstr(s).expect("Error indexing sequence");
+                seq_filtered.index_xstr(s).expect("Error indexing sequence");
+                if i == 0 {
+                    part.filter_high_freq(OccThreshold::Count(2), action)
+                        .unwrap();
+                    seq_filtered
+                        .filter_high_freq(OccThreshold::Count(2), action)
+                        .unwrap();
+                }
+                merged_filtered.merge(&part).expect("Error merging indexes");
+            }
+            assert_eq!(merged_filtered.occ_cutoffs(), seq_filtered.occ_cutoffs());
+            assert_eq!(merged_filtered.high_freq_action(), action);
+            assert_eq!(merged_filtered.tables, seq_filtered.tables);
+            assert_eq!(merged_filtered.dropped, seq_filtered.dropped);
+            assert_eq!(merged_filtered.high_freq, seq_filtered.high_freq);
+            let filtered = match action {
+                HighFreqAction::Drop => &seq_filtered.dropped,
+                HighFreqAction::Flag => &seq_filtered.high_freq,
+            };
+            assert!(filtered.iter().all(|f| !f.is_empty()));
+        }
+
+        let other_k = vec![4, 4];
+        let other_ranker = [
+            KmerXStrLexHasher::new(Rc::new(DNAAlphabet::new()), other_k[0]),
+            KmerXStrLexHasher::new(Rc::new(DNAAlphabet::new()), other_k[1]),
+        ];
+        let other = MmIndex::new(&w, &other_k, &[&other_ranker[0], &other_ranker[1]]);
+        assert!(merged.merge(&other).is_err());
+    }
 }
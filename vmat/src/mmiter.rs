@@ -0,0 +1,557 @@
+use std::collections::VecDeque;
+use std::io;
+use std::ops::Range;
+
+use crate::alphabet::Character;
+use crate::minimiser::TMmRank;
+use crate::mqueue::MQueue;
+use crate::xstream::XStream;
+use crate::xstring::XStrRollHasher;
+
+/// Number of chars read from the stream at a time
+const READ_CHUNK: usize = 1 << 12;
+
+/// Strand of a sampled minimiser.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Strand {
+    Forward,
+    Reverse,
+}
+
+/// An occurrence of a minimiser of a `(w,k)` scheme in a stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Minimiser {
+    /// Index of the `(w,k)` scheme
+    pub scheme: usize,
+    /// Rank (hash value) of the minimiser kmer
+    pub rank: TMmRank,
+    /// Start position of the kmer, relative to the start of the stream
+    pub pos: usize,
+    /// Strand of the kmer whose rank is the minimiser rank: `Reverse` if
+    /// it is the reverse complement of the kmer at `pos`
+    pub strand: Strand,
+}
+
+/**
+ * Streaming iterator over the `(w,k)`-minimisers of the chars read from
+ * an `XStream`, for several `(w,k)` schemes at once.
+ * This is the sampling used by `MmIndex`: every occurrence of every
+ * window minimiser is reported once, including the minimisers of the
+ * incomplete windows at both ends of the stream.
+ * Events of different schemes are interleaved, but the events of each
+ * scheme are reported in the order the indexer stores them.
+ * Minimisers are sampled from the forward strand only, unless canonical
+ * minimisers are requested with `set_canonical`.
+ * Chars that some hasher does not accept, such as `N` or soft-masked
+ * (lowercase) bases for DNA hashers, split the stream into segments that
+ * are sampled as if they were separate streams: no kmer overlaps them,
+ * but they still count in the positions.
+ *
+ * The stream is read in chunks of chars. Chars read ahead but not yet
+ * processed are pushed back into the stream by `into_inner`.
+ */
+pub struct MmIter<'a, S, C, H>
+where
+    S: XStream<CharType = C>,
+    C: Character,
+    H: XStrRollHasher<CharType = C>,
+{
+    src: S,
+    w: Vec<usize>,
+    k: Vec<usize>,
+    max_wlen: usize,
+    hasher: Vec<&'a H>,
+    ring: Vec<C>, // mirrored ring buffer of the last max_wlen chars
+    win_rks: Vec<MQueue<(TMmRank, usize, Strand)>>, // window kmers ranks
+    prev_mm_rk: Vec<TMmRank>, // rank of previous window minimiser
+    kmer_rks: Vec<Vec<TMmRank>>, // ranks of the kmers ending in the chunk
+    rk_shift: Vec<usize>, // chunk index of the kmer of kmer_rks[i][0]
+    ext: Vec<C>,  // chunk extended with the chars of the kmers preceding it
+    complement: Option<fn(C) -> C>, // complement of chars, for canonical kmers
+    rc_ext: Vec<C>, // reverse complement of ext
+    rc_rks: Vec<Vec<TMmRank>>, // ranks of the reverse complements of the kmers
+    pos: usize,
+    seg_start: usize, // position of the first char of the current segment
+    eos: bool,
+    pending: VecDeque<Minimiser>,
+    chunk: Vec<C>, // chars read from the stream
+    chunk_len: usize,
+    chunk_pos: usize,
+    valid_end: usize, // chunk index of the first char not accepted by the hashers
+}
+
+impl<'a, S, C, H> MmIter<'a, S, C, H>
+where
+    S: XStream<CharType = C>,
+    C: Character,
+    H: XStrRollHasher<CharType = C>,
+{
+    pub fn new(src: S, w: &[usize], k: &[usize], hashers: &[&'a H]) -> Self {
+        let l = w.len();
+        assert_eq!(l, k.len());
+        assert_eq!(l, hashers.len());
+        assert!(*w.iter().min_by(|x, y| x.cmp(y)).unwrap() > 0);
+        assert!(*k.iter().min_by(|x, y| x.cmp(y)).unwrap() > 0);
+        // maximum necessary window buffer length
+        // (!) all w,k are > 0 but, when w=1, the window has only one kmer.
+        //     because we need the previous last window kmer to
+        //     roll the hash value, we make sure that the window will
+        //     will always contain at least two kmers for any (w,k).
+        //     if w[i] == 1, w[i]+k[i] == k[i]+1, which holds two kmers
+        let max_wlen = w.iter().zip(k.iter()).map(|(a, b)| a + b).max().unwrap();
+        MmIter {
+            src,
+            w: Vec::from(w),
+            k: Vec::from(k),
+            max_wlen,
+            hasher: Vec::from(hashers),
+            ring: vec![C::default(); 2 * max_wlen],
+            win_rks: vec![MQueue::new_min(); l],
+            prev_mm_rk: vec![0; l],
+            kmer_rks: vec![Vec::new(); l],
+            rk_shift: vec![0; l],
+            ext: Vec::new(),
+            complement: None,
+            rc_ext: Vec::new(),
+            rc_rks: vec![Vec::new(); l],
+            pos: 0,
+            seg_start: 0,
+            eos: false,
+            pending: VecDeque::new(),
+            chunk: vec![C::default(); READ_CHUNK],
+            chunk_len: 0,
+            chunk_pos: 0,
+            valid_end: 0,
+        }
+    }
+
+    /// Samples canonical minimisers: each kmer is ranked as the smaller of
+    /// its rank and the rank of its reverse complement, where chars are
+    /// complemented by `complement`, and the minimisers are reported on the
+    /// strand of the smaller one. Must be set before the first minimiser is read.
+    pub fn set_canonical(&mut self, complement: fn(C) -> C) {
+        assert_eq!(self.pos, 0);
+        self.complement = Some(complement);
+    }
+
+    /// Number of chars read from the stream so far.
+    pub fn chars_read(&self) -> usize {
+        self.pos
+    }
+
+    /// The last kmer read for the `scheme`-th scheme, if any.
+    pub fn last_kmer(&self, scheme: usize) -> Option<&[C]> {
+        if self.pos - self.seg_start >= self.k[scheme] {
+            Some(self.last_chars(self.k[scheme]))
+        } else {
+            None
+        }
+    }
+
+    /// The last `len` chars read, with `len <= min(pos - seg_start, max_wlen)`.
+    /// Each char is stored twice in the ring, at `i` and `i + max_wlen`,
+    /// so that the last chars are always contiguous.
+    #[inline]
+    fn last_chars(&self, len: usize) -> &[C] {
+        &self.ring[self.last_range(len)]
+    }
+
+    #[inline]
+    fn last_range(&self, len: usize) -> Range<usize> {
+        let end = (self.pos - 1) % self.max_wlen + self.max_wlen + 1;
+        end - len..end
+    }
+
+    /// Whether every hasher accepts the char `c`.
+    #[inline]
+    fn accepts(&self, c: &C) -> bool {
+        self.hasher.iter().all(|h| h.accepts(c))
+    }
+
+    /// Computes the ranks of all the kmers ending in the chunk from
+    /// `chunk_pos` up to the first char not accepted by the hashers,
+    /// at once for every scheme.
+    fn rank_chunk(&mut self) {
+        let start = self.chunk_pos;
+        self.valid_end = (start..self.chunk_len)
+            .find(|&j| !self.accepts(&self.chunk[j]))
+            .unwrap_or(self.chunk_len);
+        for i in 0..self.k.len() {
+            let k = self.k[i];
+            // chars of the kmers starting before, in the same segment
+            let prev = (self.pos - self.seg_start).min(k - 1);
+            self.ext.clear();
+            if prev > 0 {
+                let range = self.last_range(prev);
+                self.ext.extend_from_slice(&self.ring[range]);
+            }
+            self.ext
+                .extend_from_slice(&self.chunk[start..self.valid_end]);
+            self.hasher[i].kmer_hashes(&self.ext, k, &mut self.kmer_rks[i]);
+            if let Some(complement) = self.complement {
+                self.rc_ext.clear();
+                self.rc_ext
+                    .extend(self.ext.iter().rev().map(|&c| complement(c)));
+                self.hasher[i].kmer_hashes(&self.rc_ext, k, &mut self.rc_rks[i]);
+            }
+            self.rk_shift[i] = start + k - 1 - prev;
+        }
+    }
+
+    /// Skips the char at `chunk_pos`, which the hashers do not accept,
+    /// once the windows of the segment before it are exhausted,
+    /// and starts a new segment after it.
+    fn skip_char(&mut self) {
+        for queue in self.win_rks.iter_mut() {
+            *queue = MQueue::new_min();
+        }
+        self.pos += 1;
+        self.seg_start = self.pos;
+        self.chunk_pos += 1;
+        self.rank_chunk();
+    }
+
+    /// Consumes the iterator and returns the stream itself,
+    /// positioned after the last char processed.
+    pub fn into_inner(mut self) -> S {
+        for &c in self.chunk[self.chunk_pos..self.chunk_len].iter().rev() {
+            self.src.unget(c);
+        }
+        self.src
+    }
+
+    #[inline]
+    fn emit(&mut self, scheme: usize, rank: TMmRank, pos: usize, strand: Strand) {
+        self.pending.push_back(Minimiser {
+            scheme,
+            rank,
+            pos,
+            strand,
+        });
+    }
+
+    /// Rank and strand of the `t`-th kmer ranked in the chunk of
+    /// the `scheme`-th scheme.
+    #[inline]
+    fn kmer_rank(&self, scheme: usize, t: usize) -> (TMmRank, Strand) {
+        let rk = self.kmer_rks[scheme][t];
+        if self.complement.is_none() {
+            return (rk, Strand::Forward);
+        }
+        // the reverse complements are ranked in reverse order
+        let rc_rk = self.rc_rks[scheme][self.rc_rks[scheme].len() - 1 - t];
+        if rc_rk < rk {
+            (rc_rk, Strand::Reverse)
+        } else {
+            (rk, Strand::Forward)
+        }
+    }
+
+    /// Reports all the occurrences of the current window minimiser.
+    fn emit_window_mms(&mut self, scheme: usize) {
+        let pending = &mut self.pending;
+        for &(rank, pos, strand) in self.win_rks[scheme].xtr_iter() {
+            pending.push_back(Minimiser {
+                scheme,
+                rank,
+                pos,
+                strand,
+            });
+        }
+    }
+
+    /// Processes the char at index `j` of the chunk.
+    fn push_char(&mut self, j: usize) {
+        let c = self.chunk[j];
+        let slot = self.pos % self.max_wlen;
+        self.ring[slot] = c;
+        self.ring[slot + self.max_wlen] = c;
+        self.pos += 1;
+        let pos = self.pos;
+        // number of chars of the segment read
+        let len = pos - self.seg_start;
+        for i in 0..self.w.len() {
+            let k = self.k[i];
+            if len < k {
+                continue;
+            }
+            let (kmer_rk, strand) = self.kmer_rank(i, j - self.rk_shift[i]);
+            if len == k {
+                self.prev_mm_rk[i] = kmer_rk;
+                self.win_rks[i].push((kmer_rk, pos - k, strand));
+                // initial end minimisers are all reported
+                self.emit(i, kmer_rk, pos - k, strand);
+            } else {
+                // add the new last kmer to the new window
+                let kmer_pos = pos - k;
+                // dequeue the first kmer of previous window if it is full
+                if len > self.w[i] + k - 1 {
+                    self.win_rks[i].pop();
+                }
+                // and add new kmer
+                self.win_rks[i].push((kmer_rk, kmer_pos, strand));
+                // then get current window miminiser
+                let cur_mm_rk = self.win_rks[i].xtr().unwrap().0;
+                if self.w[i] == 1 || self.prev_mm_rk[i] != cur_mm_rk {
+                    // new minimiser. report all its occurrences
+                    self.emit_window_mms(i);
+                    self.prev_mm_rk[i] = cur_mm_rk;
+                } else if cur_mm_rk == kmer_rk {
+                    // last kmer is a new occ of same old mm
+                    self.emit(i, kmer_rk, kmer_pos, strand);
+                }
+            }
+        }
+    }
+
+    /// Shrinks the final windows by one kmer, reporting the minimisers
+    /// of the end windows. Returns false when all windows are exhausted.
+    fn shrink_end_windows(&mut self) -> bool {
+        let mut still_sampling = false;
+        for i in 0..self.win_rks.len() {
+            if self.win_rks[i].len() > 1 {
+                still_sampling = true;
+                let (last_mm_rk, _last_mm_pos, _) = *self.win_rks[i].xtr().unwrap();
+                self.win_rks[i].pop();
+                let (cur_mm_rk, _cur_mm_pos, _) = *self.win_rks[i].xtr().unwrap();
+                if last_mm_rk != cur_mm_rk {
+                    // new minimiser
+                    self.emit_window_mms(i);
+                }
+            }
+        }
+        still_sampling
+    }
+}
+
+impl<'a, S, C, H> Iterator for MmIter<'a, S, C, H>
+where
+    S: XStream<CharType = C>,
+    C: Character,
+    H: XStrRollHasher<CharType = C>,
+{
+    type Item = Result<Minimiser, io::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(mm) = self.pending.pop_front() {
+                return Some(Ok(mm));
+            }
+            if self.chunk_pos < self.valid_end {
+                self.chunk_pos += 1;
+                self.push_char(self.chunk_pos - 1);
+            } else if self.chunk_pos < self.chunk_len {
+                // a char not accepted by the hashers ends the segment
+                if !self.shrink_end_windows() {
+                    self.skip_char();
+                }
+            } else if !self.eos {
+                match self.src.read(&mut self.chunk) {
+                    Ok(0) => self.eos = true,
+                    Ok(n) => {
+                        self.chunk_len = n;
+                        self.chunk_pos = 0;
+                        self.rank_chunk();
+                    }
+                    Err(e) => return Some(Err(e)),
+                }
+            } else if !self.shrink_end_windows() {
+                return None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dna::{complement, DNAAlphabet, DNAHasher};
+    use crate::xstream::XStrStream;
+    use crate::xstring::{KmerXStrLexHasher, XStrHasher, XString};
+    use std::rc::Rc;
+
+    #[test]
+    fn test_mmiter() {
+        let ranker = KmerXStrLexHasher::new(Rc::new(DNAAlphabet::new()), 2);
+        let src = XString::from("GTACCA".as_bytes());
+        // kmers GT TA AC CC CA with ranks 11 12 1 5 4
+        let mms: Vec<(TMmRank, usize)> =
+            MmIter::new(XStrStream::open(&src), &[3], &[2], &[&ranker])
+                .map(|m| m.unwrap())
+                .map(|m| (m.rank, m.pos))
+                .collect();
+        assert_eq!(mms, vec![(11, 0), (1, 2), (4, 4)]);
+    }
+
+    #[test]
+    fn test_mmiter_slice() {
+        let ranker = KmerXStrLexHasher::new(Rc::new(DNAAlphabet::new()), 3);
+        let src = "AAAAAA".as_bytes();
+        let mut it = MmIter::new(
+            XStrStream::open_slice(src),
+            &[1, 2],
+            &[3, 3],
+            &[&ranker, &ranker],
+        );
+        let mut count = [0; 2];
+        for m in &mut it {
+            let m = m.unwrap();
+            assert_eq!(m.rank, 0);
+            assert_eq!(m.strand, Strand::Forward);
+            count[m.scheme] += 1;
+        }
+        assert_eq!(count, [4, 4]);
+        assert_eq!(it.chars_read(), 6);
+        assert_eq!(it.last_kmer(0), Some("AAA".as_bytes()));
+    }
+
+    #[test]
+    fn test_mmiter_into_inner() {
+        let ranker = KmerXStrLexHasher::new(Rc::new(DNAAlphabet::new()), 4);
+        let src: Vec<u8> = b"ACGTTGCA".iter().cycle().take(10000).copied().collect();
+        let mut it = MmIter::new(XStrStream::open_slice(&src), &[5], &[4], &[&ranker]);
+        for _ in 0..100 {
+            it.next().unwrap().unwrap();
+        }
+        let pos = it.chars_read();
+        assert!(pos < READ_CHUNK);
+        let mut rest = vec![0u8; src.len()];
+        let n = it.into_inner().read(&mut rest).unwrap();
+        assert_eq!(&rest[..n], &src[pos..]);
+    }
+
+    #[test]
+    fn test_mmiter_ring() {
+        // the window ring wraps around many times
+        let rankers: Vec<KmerXStrLexHasher<u8, DNAAlphabet>> = [1, 7, 12]
+            .iter()
+            .map(|&k| KmerXStrLexHasher::new(Rc::new(DNAAlphabet::new()), k))
+            .collect();
+        let src: Vec<u8> = (0..5000u32)
+            .map(|i| b"ACGT"[((i * i + i / 7) % 4) as usize])
+            .collect();
+        let (w, k) = ([3, 1, 9], [1, 7, 12]);
+        let hashers: Vec<&KmerXStrLexHasher<u8, DNAAlphabet>> = rankers.iter().collect();
+        let mut it = MmIter::new(XStrStream::open_slice(&src), &w, &k, &hashers);
+        let mut count = 0;
+        while let Some(m) = it.next() {
+            let m = m.unwrap();
+            let kmer = &src[m.pos..m.pos + k[m.scheme]];
+            assert_eq!(m.rank, hashers[m.scheme].hash(kmer));
+            if let Some(last) = it.last_kmer(m.scheme) {
+                let end = it.chars_read();
+                assert_eq!(last, &src[end - k[m.scheme]..end]);
+            }
+            count += 1;
+        }
+        assert!(count > src.len());
+    }
+
+    #[test]
+    fn test_mmiter_canonical() {
+        let k = [5, 2];
+        let rankers: Vec<KmerXStrLexHasher<u8, DNAAlphabet>> = k
+            .iter()
+            .map(|&k| KmerXStrLexHasher::new(Rc::new(DNAAlphabet::new()), k))
+            .collect();
+        let hashers: Vec<&KmerXStrLexHasher<u8, DNAAlphabet>> = rankers.iter().collect();
+        let src: Vec<u8> = (0..2 * READ_CHUNK as u32 + 5)
+            .map(|i| b"ACGT"[((i * i + i / 3) % 4) as usize])
+            .collect();
+        let mut it = MmIter::new(XStrStream::open_slice(&src), &[4, 3], &k, &hashers);
+        it.set_canonical(complement);
+        let mut strands = [0; 2];
+        for m in it {
+            let m = m.unwrap();
+            let kmer = &src[m.pos..m.pos + k[m.scheme]];
+            let rc: Vec<u8> = kmer.iter().rev().map(|&c| complement(c)).collect();
+            let (rk, rc_rk) = (hashers[m.scheme].hash(kmer), hashers[m.scheme].hash(&rc));
+            assert_eq!(m.rank, rk.min(rc_rk));
+            let strand = if rc_rk < rk {
+                Strand::Reverse
+            } else {
+                Strand::Forward
+            };
+            assert_eq!(m.strand, strand);
+            strands[(m.strand == Strand::Reverse) as usize] += 1;
+        }
+        assert!(strands[0] > 0 && strands[1] > 0);
+    }
+
+    #[test]
+    fn test_mmiter_dna_hasher() {
+        // the kmers of several chunks are ranked in bulk
+        let src: Vec<u8> = (0..3 * READ_CHUNK as u32 + 17)
+            .map(|i| b"ACGT"[((i * i + i / 5) % 4) as usize])
+            .collect();
+        let (w, k) = ([5, 1, 20], [32, 3, 15]);
+        let lex: Vec<KmerXStrLexHasher<u8, DNAAlphabet>> = k
+            .iter()
+            .map(|&k| KmerXStrLexHasher::new(Rc::new(DNAAlphabet::new()), k))
+            .collect();
+        let dna: Vec<DNAHasher> = k
+            .iter()
+            .map(|_| DNAHasher::new(Rc::new(DNAAlphabet::new())))
+            .collect();
+        let lex_refs: Vec<&KmerXStrLexHasher<u8, DNAAlphabet>> = lex.iter().collect();
+        let dna_refs: Vec<&DNAHasher> = dna.iter().collect();
+        let expected: Vec<Minimiser> = MmIter::new(XStrStream::open_slice(&src), &w, &k, &lex_refs)
+            .map(|m| m.unwrap())
+            .collect();
+        let mms: Vec<Minimiser> = MmIter::new(XStrStream::open_slice(&src), &w, &k, &dna_refs)
+            .map(|m| m.unwrap())
+            .collect();
+        assert!(expected.len() > src.len());
+        assert_eq!(mms, expected);
+    }
+
+    #[test]
+    fn test_mmiter_invalid_chars() {
+        // runs of invalid chars, at both ends, across chunks,
+        // and segments shorter than the kmers
+        let mut src: Vec<u8> = (0..2 * READ_CHUNK as u32 + 100)
+            .map(|i| b"ACGT"[((i * i + i / 3) % 4) as usize])
+            .collect();
+        for &(from, to) in [
+            (0, 2),
+            (50, 51),
+            (60, 70),
+            (73, 74),
+            (READ_CHUNK - 3, READ_CHUNK + 40),
+        ]
+        .iter()
+        {
+            for c in src[from..to].iter_mut() {
+                *c = b'N';
+            }
+        }
+        src[200] = b'a';
+        src[201] = b'c';
+        let last = src.len() - 1;
+        src[last] = b'N';
+        let (w, k) = ([4, 1], [5, 9]);
+        let dna = DNAHasher::new(Rc::new(DNAAlphabet::new()));
+        let hashers = [&dna, &dna];
+        for &canonical in [false, true].iter() {
+            let mms = |s: &[u8]| -> Vec<Minimiser> {
+                let mut it = MmIter::new(XStrStream::open_slice(s), &w, &k, &hashers);
+                if canonical {
+                    it.set_canonical(complement);
+                }
+                it.map(|m| m.unwrap()).collect()
+            };
+            // the segments indexed separately
+            let mut expected = vec![];
+            let mut start = 0;
+            for seg in src.split(|c| !dna.accepts(c)) {
+                expected.extend(mms(seg).into_iter().map(|mut m| {
+                    m.pos += start;
+                    m
+                }));
+                start += seg.len() + 1;
+            }
+            assert!(!expected.is_empty());
+            assert_eq!(mms(&src), expected);
+        }
+    }
+}
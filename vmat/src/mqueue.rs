@@ -21,12 +21,11 @@ where
                     == self.src.queue[self.src.minmax[self.index] - self.src.popped])
         {
             self.index += 1;
-            return self
-                .src
+            self.src
                 .queue
-                .get(self.src.minmax[self.index - 1] - self.src.popped);
+                .get(self.src.minmax[self.index - 1] - self.src.popped)
         } else {
-            return None;
+            None
         }
     }
 }
@@ -74,7 +73,7 @@ where
 
     pub fn push(&mut self, item: T) {
         //let l = self.len();
-        while self.minmax.len() > 0
+        while !self.minmax.is_empty()
             && item.cmp(&self.queue[self.minmax.back().unwrap() - self.popped]) == self.crit
         {
             self.minmax.pop_back();
@@ -96,9 +95,9 @@ where
 
     pub fn xtr(&self) -> Option<&T> {
         if self.is_empty() {
-            return None;
+            None
         } else {
-            return self.queue.get(self.minmax.front().unwrap() - self.popped);
+            self.queue.get(self.minmax.front().unwrap() - self.popped)
         }
     }
 
@@ -118,15 +117,15 @@ mod tests {
     fn test_push_pop_min() {
         let mut queue: MQueue<u32> = MQueue::new_min();
         queue.push(30);
-        assert_eq!(*queue.xtr().unwrap(), 30 as u32);
+        assert_eq!(*queue.xtr().unwrap(), 30);
         queue.push(20);
-        assert_eq!(*queue.xtr().unwrap(), 20 as u32);
+        assert_eq!(*queue.xtr().unwrap(), 20);
         queue.push(40);
-        assert_eq!(*queue.xtr().unwrap(), 20 as u32);
+        assert_eq!(*queue.xtr().unwrap(), 20);
         queue.pop();
-        assert_eq!(*queue.xtr().unwrap(), 20 as u32);
+        assert_eq!(*queue.xtr().unwrap(), 20);
         queue.pop();
-        assert_eq!(*queue.xtr().unwrap(), 40 as u32);
+        assert_eq!(*queue.xtr().unwrap(), 40);
         queue.pop();
         assert!(queue.pop().is_none());
     }
@@ -135,19 +134,19 @@ mod tests {
     fn test_push_pop_max() {
         let mut queue: MQueue<u32> = MQueue::new_max();
         queue.push(30);
-        assert_eq!(*queue.xtr().unwrap(), 30 as u32);
+        assert_eq!(*queue.xtr().unwrap(), 30);
         queue.push(20);
-        assert_eq!(*queue.xtr().unwrap(), 30 as u32);
+        assert_eq!(*queue.xtr().unwrap(), 30);
         queue.push(40);
-        assert_eq!(*queue.xtr().unwrap(), 40 as u32);
+        assert_eq!(*queue.xtr().unwrap(), 40);
         queue.pop();
-        assert_eq!(*queue.xtr().unwrap(), 40 as u32);
+        assert_eq!(*queue.xtr().unwrap(), 40);
         queue.pop();
-        assert_eq!(*queue.xtr().unwrap(), 40 as u32);
+        assert_eq!(*queue.xtr().unwrap(), 40);
         queue.push(15);
-        assert_eq!(*queue.xtr().unwrap(), 40 as u32);
+        assert_eq!(*queue.xtr().unwrap(), 40);
         queue.push(55);
-        assert_eq!(*queue.xtr().unwrap(), 55 as u32);
+        assert_eq!(*queue.xtr().unwrap(), 55);
     }
 
     #[test]
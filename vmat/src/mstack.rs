@@ -27,12 +27,12 @@ where
             } else {
                 self.index = self.src.stack[self.src.len() - 1].1;
             }
-            return Some(&self.src.stack[self.index].0);
+            Some(&self.src.stack[self.index].0)
         } else if self.src.stack[self.index].1 != self.index {
             self.index = self.src.stack[self.index].1;
-            return Some(&self.src.stack[self.index].0);
+            Some(&self.src.stack[self.index].0)
         } else {
-            return None;
+            None
         }
     }
 }
@@ -91,7 +91,7 @@ where
     }
 
     pub fn xtr(&self) -> Option<&T> {
-        if self.len() > 0 {
+        if !self.is_empty() {
             Some(&self.stack[self.stack[self.stack.len() - 1].1].0)
         } else {
             None
@@ -100,7 +100,7 @@ where
 
     pub fn xtr_iter<'a>(&'a self) -> MStackXtrIter<'a, T> {
         MStackXtrIter {
-            src: &self,
+            src: self,
             index: self.len(),
         }
     }
@@ -115,19 +115,19 @@ mod tests {
     fn test_push_pop_min() {
         let mut stack: MStack<u32> = MStack::new_min();
         stack.push(20);
-        assert_eq!(*stack.xtr().unwrap(), 20 as u32);
+        assert_eq!(*stack.xtr().unwrap(), 20);
         stack.push(10);
-        assert_eq!(*stack.xtr().unwrap(), 10 as u32);
+        assert_eq!(*stack.xtr().unwrap(), 10);
         stack.push(30);
-        assert_eq!(*stack.xtr().unwrap(), 10 as u32);
+        assert_eq!(*stack.xtr().unwrap(), 10);
         stack.push(5);
-        assert_eq!(*stack.xtr().unwrap(), 5 as u32);
+        assert_eq!(*stack.xtr().unwrap(), 5);
         stack.pop();
-        assert_eq!(*stack.xtr().unwrap(), 10 as u32);
+        assert_eq!(*stack.xtr().unwrap(), 10);
         stack.pop();
-        assert_eq!(*stack.xtr().unwrap(), 10 as u32);
+        assert_eq!(*stack.xtr().unwrap(), 10);
         stack.pop();
-        assert_eq!(*stack.xtr().unwrap(), 20 as u32);
+        assert_eq!(*stack.xtr().unwrap(), 20);
         stack.pop();
         assert_eq!(stack.len(), 0);
     }
@@ -136,19 +136,19 @@ mod tests {
     fn test_push_pop_max() {
         let mut stack: MStack<u32> = MStack::new_max();
         stack.push(10);
-        assert_eq!(*stack.xtr().unwrap(), 10 as u32);
+        assert_eq!(*stack.xtr().unwrap(), 10);
         stack.push(20);
-        assert_eq!(*stack.xtr().unwrap(), 20 as u32);
+        assert_eq!(*stack.xtr().unwrap(), 20);
         stack.push(30);
-        assert_eq!(*stack.xtr().unwrap(), 30 as u32);
+        assert_eq!(*stack.xtr().unwrap(), 30);
         stack.push(15);
-        assert_eq!(*stack.xtr().unwrap(), 30 as u32);
+        assert_eq!(*stack.xtr().unwrap(), 30);
         stack.pop();
-        assert_eq!(*stack.xtr().unwrap(), 30 as u32);
+        assert_eq!(*stack.xtr().unwrap(), 30);
         stack.pop();
-        assert_eq!(*stack.xtr().unwrap(), 20 as u32);
+        assert_eq!(*stack.xtr().unwrap(), 20);
         stack.pop();
-        assert_eq!(*stack.xtr().unwrap(), 10 as u32);
+        assert_eq!(*stack.xtr().unwrap(), 10);
         stack.pop();
         assert_eq!(stack.len(), 0);
     }
@@ -156,9 +156,7 @@ mod tests {
     #[test]
     fn test_all_xstr() {
         let mut stack: MStack<u32> = MStack::new_min();
-        for m in stack.xtr_iter() {
-            panic!("min stack is empty");
-        }
+        assert!(stack.xtr_iter().next().is_none());
         stack.push(6);
         stack.push(5);
         stack.push(7);
@@ -169,7 +167,7 @@ mod tests {
         stack.push(8);
         let mut count = 0;
         for m in stack.xtr_iter() {
-            assert_eq!(*m, 5 as u32);
+            assert_eq!(*m, 5);
             count += 1;
         }
         assert_eq!(count, 3);
@@ -178,7 +176,7 @@ mod tests {
         stack.pop();
         count = 0;
         for m in stack.xtr_iter() {
-            assert_eq!(*m, 5 as u32);
+            assert_eq!(*m, 5);
             count += 1;
         }
         assert_eq!(count, 2);
@@ -0,0 +1,478 @@
+//! Multithreaded FASTA/FASTQ parsing.
+//!
+//! The input is split into large byte chunks at record boundaries by a
+//! reader thread, the chunks are parsed by worker threads, and the parsed
+//! record batches are delivered over a channel in the original order.
+
+use std::collections::BTreeMap;
+use std::io::{BufReader, Error, Read};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::binio;
+use crate::compress::Decoder;
+use crate::fasta::FastaReader;
+use crate::fastq::FastqReader;
+use crate::seqio::{self, SeqFormat, SeqRecord};
+
+/// Default chunk size in bytes
+pub const DEFAULT_CHUNK_SIZE: usize = 4 << 20;
+
+/// A batch of consecutive records, parsed from one chunk
+pub type SeqBatch = Vec<SeqRecord>;
+
+type Job = (usize, u64, Result<Vec<u8>, Error>);
+type Parsed = (usize, Result<SeqBatch, Error>);
+
+/**
+ * Reader of FASTA or FASTQ records which parses the input in worker threads.
+ * The format is detected by `seqio::detect_format`, as in `SeqReader`.
+ *
+ * Record batches are received in the order of the input, either with
+ * `next_batch`, by iterating over the reader, or directly from the
+ * channel returned by `into_receiver`.
+ *
+ * The threads are joined at the end of the input, or when the reader is
+ * dropped. A panic of any of them is reported as an error after the
+ * batches parsed before it.
+ */
+pub struct ParallelSeqReader {
+    format: SeqFormat,
+    rx: Option<Receiver<Result<SeqBatch, Error>>>,
+    handle: Option<JoinHandle<()>>, // thread delivering the batches
+}
+
+impl ParallelSeqReader {
+    /// Opens a FASTA or FASTQ file, which may be gzip, BGZF or zstd compressed,
+    /// to be parsed by `nthreads` worker threads.
+    pub fn new_from_path<P: AsRef<Path>>(path: P, nthreads: usize) -> Result<Self, Error> {
+        Self::new(Decoder::new_from_path(path)?, nthreads, DEFAULT_CHUNK_SIZE)
+    }
+
+    /// Parses `src` in chunks of about `chunk_size` bytes with `nthreads`
+    /// worker threads. Chunks are extended as needed to hold at least
+    /// one complete record.
+    pub fn new<R>(src: R, nthreads: usize, chunk_size: usize) -> Result<Self, Error>
+    where
+        R: Read + Send + 'static,
+    {
+        assert!(nthreads > 0);
+        assert!(chunk_size > 0);
+        let mut reader = BufReader::new(src);
+        let format = seqio::detect_format(&mut reader)?;
+
+        let (job_tx, job_rx) = mpsc::sync_channel::<Job>(2 * nthreads);
+        let (parsed_tx, parsed_rx) = mpsc::sync_channel::<Parsed>(2 * nthreads);
+        let (batch_tx, batch_rx) = mpsc::sync_channel(nthreads);
+
+        let mut threads = vec![thread::spawn(move || {
+            split_chunks(reader, format, chunk_size, job_tx)
+        })];
+        let job_rx = Arc::new(Mutex::new(job_rx));
+        for _ in 0..nthreads {
+            let job_rx = Arc::clone(&job_rx);
+            let parsed_tx = parsed_tx.clone();
+            threads.push(thread::spawn(move || {
+                parse_chunks(format, job_rx, parsed_tx)
+            }));
+        }
+        drop(parsed_tx);
+        let handle = thread::spawn(move || {
+            reorder_batches(parsed_rx, &batch_tx);
+            // the other threads are done or stop at their next send
+            let panicked = threads.into_iter().any(|t| t.join().is_err());
+            if panicked {
+                let _ = batch_tx.send(Err(panicked_error()));
+            }
+        });
+
+        Ok(ParallelSeqReader {
+            format,
+            rx: Some(batch_rx),
+            handle: Some(handle),
+        })
+    }
+
+    /// The detected format of the source.
+    pub fn format(&self) -> SeqFormat {
+        self.format
+    }
+
+    /// Receives the next batch of records, if any.
+    pub fn next_batch(&mut self) -> Result<Option<SeqBatch>, Error> {
+        match self.next() {
+            Some(batch) => batch.map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// The channel over which the record batches are delivered.
+    /// The threads are no longer joined by the reader, but the end of the
+    /// channel still means that they are all done.
+    pub fn into_receiver(mut self) -> Receiver<Result<SeqBatch, Error>> {
+        self.handle = None;
+        self.rx.take().unwrap()
+    }
+
+    /// Joins the thread delivering the batches, which has joined the others.
+    fn join(&mut self) -> Result<(), Error> {
+        match self.handle.take() {
+            Some(handle) => handle.join().map_err(|_| panicked_error()),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Iterator for ParallelSeqReader {
+    type Item = Result<SeqBatch, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.rx.as_ref()?.recv() {
+            Ok(batch) => Some(batch),
+            // all the batches were delivered
+            Err(_) => self.join().err().map(Err),
+        }
+    }
+}
+
+impl Drop for ParallelSeqReader {
+    fn drop(&mut self) {
+        // the threads stop as soon as they find the channel closed
+        self.rx = None;
+        let _ = self.join();
+    }
+}
+
+fn panicked_error() -> Error {
+    Error::other("A parsing thread panicked")
+}
+
+/// Returns the line starting at `pos`, without EOL chars, and the
+/// start of the next line, if the line is complete.
+fn next_line(buf: &[u8], pos: usize) -> Option<(&[u8], usize)> {
+    let len = buf[pos..].iter().position(|&c| c == b'\n')?;
+    let mut line = &buf[pos..pos + len];
+    if line.last() == Some(&b'\r') {
+        line = &line[..line.len() - 1];
+    }
+    Some((line, pos + len + 1))
+}
+
+/// End of the last complete FASTQ record in `buf`. If `buf` is malformed,
+/// the whole buffer is taken so that the parser reports the error.
+fn fastq_boundary(buf: &[u8]) -> usize {
+    let mut end = 0;
+    loop {
+        let (mut header, mut pos) = match next_line(buf, end) {
+            Some(line) => line,
+            None => return end,
+        };
+        // blank lines between records
+        while header.iter().all(u8::is_ascii_whitespace) {
+            match next_line(buf, pos) {
+                Some((line, next)) => {
+                    header = line;
+                    pos = next;
+                }
+                None => return end,
+            }
+        }
+        if header.first() != Some(&b'@') {
+            return buf.len();
+        }
+        let mut seq_len = 0;
+        loop {
+            match next_line(buf, pos) {
+                Some((line, next)) => {
+                    pos = next;
+                    if line.first() == Some(&b'+') {
+                        break;
+                    }
+                    seq_len += line.len();
+                }
+                None => return end,
+            }
+        }
+        let mut qual_len = 0;
+        loop {
+            match next_line(buf, pos) {
+                Some((line, next)) => {
+                    pos = next;
+                    qual_len += line.len();
+                    if qual_len >= seq_len {
+                        break;
+                    }
+                }
+                None => return end,
+            }
+        }
+        end = pos;
+    }
+}
+
+/// End of the last complete FASTA record in `buf`, i.e. the start of the
+/// last description line, searched from `from` onwards.
+fn fasta_boundary(buf: &[u8], from: usize) -> usize {
+    buf[from..]
+        .windows(2)
+        .rposition(|w| w == b"\n>")
+        .map_or(0, |i| from + i + 1)
+}
+
+/// Reader thread: splits the source into chunks at record boundaries.
+fn split_chunks<R: Read>(
+    mut src: BufReader<R>,
+    format: SeqFormat,
+    chunk_size: usize,
+    job_tx: SyncSender<Job>,
+) {
+    let mut chunk: Vec<u8> = Vec::new();
+    let mut offset = 0u64;
+    let mut index = 0;
+    loop {
+        // fill the chunk up to chunk_size more bytes
+        let len = chunk.len();
+        chunk.resize(len + chunk_size, 0);
+        let n = match binio::read_up_to(&mut src, &mut chunk[len..]) {
+            Ok(n) => n,
+            Err(e) => {
+                let _ = job_tx.send((index, offset, Err(e)));
+                return;
+            }
+        };
+        chunk.truncate(len + n);
+        let eof = n < chunk_size;
+        let cut = if eof {
+            chunk.len()
+        } else {
+            match format {
+                // the carried bytes hold no boundary
+                SeqFormat::Fasta => fasta_boundary(&chunk, len.saturating_sub(1)),
+                SeqFormat::Fastq => fastq_boundary(&chunk),
+            }
+        };
+        if cut > 0 {
+            let rest = chunk.split_off(cut);
+            let size = chunk.len() as u64;
+            if job_tx.send((index, offset, Ok(chunk))).is_err() {
+                // the receiver is gone
+                return;
+            }
+            chunk = rest;
+            offset += size;
+            index += 1;
+        }
+        if eof {
+            return;
+        }
+    }
+}
+
+fn parse_chunk(format: SeqFormat, chunk: &[u8]) -> Result<SeqBatch, Error> {
+    let mut batch = SeqBatch::new();
+    match format {
+        SeqFormat::Fasta => {
+            let mut reader = FastaReader::new(chunk)?;
+            while let Some(rec) = reader.next_ref()? {
+                let (desc, seq) = rec.into_owned();
+                batch.push(SeqRecord {
+                    desc,
+                    seq,
+                    qual: None,
+                });
+            }
+        }
+        SeqFormat::Fastq => {
+            let mut reader = FastqReader::new(chunk)?;
+            while let Some(rec) = reader.next_ref()? {
+                let (desc, seq, qual) = rec.into_owned();
+                batch.push(SeqRecord {
+                    desc,
+                    seq,
+                    qual: Some(qual),
+                });
+            }
+        }
+    }
+    Ok(batch)
+}
+
+/// Worker thread: parses chunks until there are no more.
+fn parse_chunks(
+    format: SeqFormat,
+    job_rx: Arc<Mutex<Receiver<Job>>>,
+    parsed_tx: SyncSender<Parsed>,
+) {
+    loop {
+        let job = job_rx.lock().unwrap().recv();
+        let (index, offset, chunk) = match job {
+            Ok(job) => job,
+            Err(_) => return,
+        };
+        let batch = chunk.and_then(|chunk| {
+            parse_chunk(format, &chunk).map_err(|e| {
+                Error::new(
+                    e.kind(),
+                    format!("{} (in chunk at byte offset {})", e, offset),
+                )
+            })
+        });
+        if parsed_tx.send((index, batch)).is_err() {
+            return;
+        }
+    }
+}
+
+/// Reorders the parsed batches by chunk index.
+fn reorder_batches(parsed_rx: Receiver<Parsed>, batch_tx: &SyncSender<Result<SeqBatch, Error>>) {
+    let mut pending: BTreeMap<usize, Result<SeqBatch, Error>> = BTreeMap::new();
+    let mut next = 0;
+    for (index, batch) in parsed_rx {
+        pending.insert(index, batch);
+        while let Some(batch) = pending.remove(&next) {
+            let failed = batch.is_err();
+            if batch_tx.send(batch).is_err() || failed {
+                return;
+            }
+            next += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::seqio::SeqReader;
+
+    fn fasta_src(nrec: usize) -> Vec<u8> {
+        let mut src = vec![];
+        for i in 0..nrec {
+            src.extend(format!(">seq{} desc\n", i).bytes());
+            for j in 0..(i % 7) {
+                src.extend(b"ACGT".iter().cycle().skip(i + j).take(1 + i % 13));
+                src.push(b'\n');
+            }
+        }
+        src
+    }
+
+    fn fastq_src(nrec: usize) -> Vec<u8> {
+        let mut src = vec![];
+        for i in 0..nrec {
+            // qualities starting with '@' and '+'
+            src.extend(format!("@read{}\nACGTA\nC\n+\n@+III\nI\n", i).bytes());
+        }
+        src
+    }
+
+    fn read_all(src: Vec<u8>, nthreads: usize, chunk_size: usize) -> Vec<SeqRecord> {
+        let reader = ParallelSeqReader::new(std::io::Cursor::new(src), nthreads, chunk_size)
+            .expect("Unable to open source");
+        reader.flat_map(|batch| batch.unwrap()).collect()
+    }
+
+    fn read_seq(src: &[u8]) -> Vec<SeqRecord> {
+        let mut reader = SeqReader::new(src).unwrap();
+        let mut recs = vec![];
+        while let Some(rec) = reader.next_record().unwrap() {
+            recs.push(rec);
+        }
+        recs
+    }
+
+    #[test]
+    fn test_parallel_fasta() {
+        let src = fasta_src(500);
+        let expected = read_seq(&src);
+        assert_eq!(expected.len(), 500);
+        for &(nthreads, chunk_size) in [(1, 1 << 20), (4, 1), (3, 50), (8, 1000)].iter() {
+            assert_eq!(read_all(src.clone(), nthreads, chunk_size), expected);
+        }
+    }
+
+    #[test]
+    fn test_parallel_fastq() {
+        let src = fastq_src(300);
+        let expected = read_seq(&src);
+        assert_eq!(expected.len(), 300);
+        for &(nthreads, chunk_size) in [(1, 1 << 20), (4, 1), (3, 17), (8, 1000)].iter() {
+            let reader =
+                ParallelSeqReader::new(std::io::Cursor::new(src.clone()), nthreads, chunk_size)
+                    .unwrap();
+            assert_eq!(reader.format(), SeqFormat::Fastq);
+            let recs: Vec<SeqRecord> = reader.flat_map(|batch| batch.unwrap()).collect();
+            assert_eq!(recs, expected);
+        }
+        // blank lines between records and at the end
+        let mut blank = String::from_utf8(src)
+            .unwrap()
+            .replace("\n@read", "\n\n@read");
+        blank.push_str("\n\n");
+        for &(nthreads, chunk_size) in [(1, 1 << 20), (4, 1), (3, 17)].iter() {
+            assert_eq!(
+                read_all(blank.clone().into_bytes(), nthreads, chunk_size),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_parallel_error() {
+        let mut src = fastq_src(100);
+        src.extend(b"@bad\nACGT\n+\nII\n");
+        src.extend(fastq_src(100));
+        let mut reader = ParallelSeqReader::new(std::io::Cursor::new(src), 4, 64).unwrap();
+        let mut nrec = 0;
+        loop {
+            match reader.next_batch() {
+                Ok(Some(batch)) => nrec += batch.len(),
+                Ok(None) => panic!("error not reported"),
+                Err(_) => break,
+            }
+        }
+        assert!(nrec < 101);
+        assert!(reader.next_batch().unwrap().is_none());
+    }
+
+    /// Source which panics after the given number of bytes.
+    struct PanickingReader {
+        src: std::io::Cursor<Vec<u8>>,
+        limit: u64,
+    }
+
+    impl Read for PanickingReader {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+            if self.src.position() >= self.limit {
+                panic!("read failure");
+            }
+            let len = buf.len().min((self.limit - self.src.position()) as usize);
+            self.src.read(&mut buf[..len])
+        }
+    }
+
+    #[test]
+    fn test_parallel_panic() {
+        let src = PanickingReader {
+            src: std::io::Cursor::new(fasta_src(500)),
+            limit: 5000,
+        };
+        let mut reader = ParallelSeqReader::new(src, 3, 100).unwrap();
+        let mut nrec = 0;
+        loop {
+            match reader.next_batch() {
+                Ok(Some(batch)) => nrec += batch.len(),
+                Ok(None) => panic!("panic not reported"),
+                Err(e) => {
+                    assert_eq!(e.kind(), std::io::ErrorKind::Other);
+                    break;
+                }
+            }
+        }
+        assert!(nrec > 0 && nrec < 500);
+        assert!(reader.next_batch().unwrap().is_none());
+        // dropped before the end of the input
+        drop(ParallelSeqReader::new(std::io::Cursor::new(fasta_src(500)), 2, 10).unwrap());
+    }
+}
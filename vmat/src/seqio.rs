@@ -0,0 +1,209 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Stdin};
+use std::path::Path;
+
+use crate::compress::Decoder;
+use crate::fasta::FastaReader;
+use crate::fastq::FastqReader;
+use crate::xstring::XString;
+
+/// Sequence file formats.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SeqFormat {
+    Fasta,
+    Fastq,
+}
+
+/// A sequence record read from a FASTA or FASTQ file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SeqRecord {
+    /// Description line, without the starting `>` or `@`
+    pub desc: String,
+    /// The sequence
+    pub seq: XString<u8>,
+    /// Quality string (FASTQ only)
+    pub qual: Option<XString<u8>>,
+}
+
+/// A sequence record borrowed from the buffers of a `SeqReader`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SeqRecordRef<'a> {
+    /// Description line, without the starting `>` or `@`
+    pub desc: &'a [u8],
+    /// The sequence
+    pub seq: &'a [u8],
+    /// Quality string (FASTQ only)
+    pub qual: Option<&'a [u8]>,
+}
+
+impl<'a> SeqRecordRef<'a> {
+    /// Copies the record into an owned `SeqRecord`.
+    pub fn into_owned(self) -> SeqRecord {
+        SeqRecord {
+            desc: String::from_utf8_lossy(self.desc).into_owned(),
+            seq: XString::from(self.seq),
+            qual: self.qual.map(XString::from),
+        }
+    }
+}
+
+/// Detects the format of a FASTA or FASTQ source from its first char
+/// other than whitespace: `@` for FASTQ, anything else for FASTA.
+/// The leading whitespace of FASTQ sources is consumed, as their first
+/// record must start at the first line, that of FASTA sources is left
+/// to the FASTA parser unless it fills the whole buffer of `reader`.
+pub fn detect_format<B: BufRead>(reader: &mut B) -> Result<SeqFormat, io::Error> {
+    loop {
+        let buf = reader.fill_buf()?;
+        match buf.iter().position(|c| !c.is_ascii_whitespace()) {
+            Some(i) if buf[i] == b'@' => {
+                reader.consume(i);
+                return Ok(SeqFormat::Fastq);
+            }
+            Some(_) => return Ok(SeqFormat::Fasta),
+            None if buf.is_empty() => return Ok(SeqFormat::Fasta),
+            None => {
+                let n = buf.len();
+                reader.consume(n);
+            }
+        }
+    }
+}
+
+enum Inner<R>
+where
+    R: Read,
+{
+    Fasta(FastaReader<R>),
+    Fastq(FastqReader<R>),
+}
+
+/// Reader of FASTA or FASTQ records.
+/// The format is detected by `detect_format`.
+pub struct SeqReader<R>
+where
+    R: Read,
+{
+    format: SeqFormat,
+    inner: Inner<R>,
+}
+
+impl SeqReader<Decoder<File>> {
+    /// Opens a FASTA or FASTQ file, which may be gzip, BGZF or zstd compressed.
+    pub fn new_from_path<P: AsRef<Path>>(path: P) -> Result<Self, std::io::Error> {
+        Self::new(Decoder::new_from_path(path)?)
+    }
+}
+
+impl SeqReader<Decoder<Stdin>> {
+    /// Reads FASTA or FASTQ records from the standard input,
+    /// which may be gzip, BGZF or zstd compressed.
+    pub fn new_from_stdin() -> Result<Self, std::io::Error> {
+        Self::new(Decoder::new(std::io::stdin())?)
+    }
+}
+
+impl<R> SeqReader<R>
+where
+    R: Read,
+{
+    pub fn new(src: R) -> Result<Self, std::io::Error> {
+        let mut reader = BufReader::new(src);
+        let format = detect_format(&mut reader)?;
+        let inner = match format {
+            SeqFormat::Fasta => Inner::Fasta(FastaReader::from_bufreader(reader)),
+            SeqFormat::Fastq => Inner::Fastq(FastqReader::from_bufreader(reader)),
+        };
+        Ok(SeqReader { format, inner })
+    }
+
+    /// The detected format of the source.
+    pub fn format(&self) -> SeqFormat {
+        self.format
+    }
+
+    /// Enables or disables strict parsing of FASTA sources
+    /// (see `FastaReader`).
+    pub fn set_strict(&mut self, strict: bool) {
+        if let Inner::Fasta(reader) = &mut self.inner {
+            reader.set_strict(strict);
+        }
+    }
+
+    /// Reads the next record into the reusable buffers of the reader
+    /// and returns a view of it, valid until the next call.
+    pub fn next_ref(&mut self) -> Result<Option<SeqRecordRef<'_>>, std::io::Error> {
+        Ok(match &mut self.inner {
+            Inner::Fasta(reader) => reader.next_ref()?.map(|rec| SeqRecordRef {
+                desc: rec.desc,
+                seq: rec.seq,
+                qual: None,
+            }),
+            Inner::Fastq(reader) => reader.next_ref()?.map(|rec| SeqRecordRef {
+                desc: rec.desc,
+                seq: rec.seq,
+                qual: Some(rec.qual),
+            }),
+        })
+    }
+
+    /// Reads the next record, if any.
+    pub fn next_record(&mut self) -> Result<Option<SeqRecord>, std::io::Error> {
+        Ok(self.next_ref()?.map(SeqRecordRef::into_owned))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_format() {
+        let fasta: &[u8] = b">id1\nACGT\nAC\n>id2\nTTTT\n";
+        let mut reader = SeqReader::new(fasta).unwrap();
+        assert_eq!(reader.format(), SeqFormat::Fasta);
+        let rec = reader.next_record().unwrap().unwrap();
+        assert_eq!(rec.desc, "id1");
+        assert_eq!(&rec.seq[..], b"ACGTAC");
+        assert_eq!(rec.qual, None);
+        assert_eq!(&reader.next_record().unwrap().unwrap().seq[..], b"TTTT");
+        assert!(reader.next_record().unwrap().is_none());
+
+        let fastq: &[u8] = b"@id1\nACGT\n+\nIIII\n";
+        let mut reader = SeqReader::new(fastq).unwrap();
+        assert_eq!(reader.format(), SeqFormat::Fastq);
+        let rec = reader.next_record().unwrap().unwrap();
+        assert_eq!(rec.desc, "id1");
+        assert_eq!(&rec.seq[..], b"ACGT");
+        assert_eq!(&rec.qual.unwrap()[..], b"IIII");
+        assert!(reader.next_record().unwrap().is_none());
+
+        let mut reader = SeqReader::new(fastq).unwrap();
+        let rec = reader.next_ref().unwrap().unwrap();
+        assert_eq!(rec.qual, Some(&b"IIII"[..]));
+        assert!(reader.next_ref().unwrap().is_none());
+    }
+
+    #[test]
+    fn detect_format_whitespace() {
+        let fastq: &[u8] = b"\n  \r\n\t@id1\nACGT\n+\nIIII\n";
+        let mut reader = SeqReader::new(fastq).unwrap();
+        assert_eq!(reader.format(), SeqFormat::Fastq);
+        assert_eq!(reader.next_record().unwrap().unwrap().desc, "id1");
+        let fasta: &[u8] = b" \n>id1\nACGT\n";
+        let mut src = fasta;
+        assert_eq!(super::detect_format(&mut src).unwrap(), SeqFormat::Fasta);
+        assert_eq!(src, fasta);
+        let mut reader = SeqReader::new(fasta).unwrap();
+        assert_eq!(reader.format(), SeqFormat::Fasta);
+        assert_eq!(reader.next_record().unwrap().unwrap().desc, "id1");
+        // whitespace filling the buffer
+        let mut src = BufReader::with_capacity(4, &b"      @id1\n"[..]);
+        assert_eq!(super::detect_format(&mut src).unwrap(), SeqFormat::Fastq);
+        assert_eq!(src.fill_buf().unwrap()[0], b'@');
+        assert_eq!(
+            super::detect_format(&mut &b" \n"[..]).unwrap(),
+            SeqFormat::Fasta
+        );
+    }
+}
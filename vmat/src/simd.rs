@@ -0,0 +1,328 @@
+//! Vectorised kernels for 2-bit encoded DNA: conversion of ASCII bases
+//! to 2-bit codes, and hashing of all the k-mers of a block of codes.
+//!
+//! SSE2 and AVX2 implementations are chosen at runtime from the features
+//! of the CPU, with a scalar fallback on other CPUs and architectures.
+//! All the implementations produce the same results.
+
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+/// Instruction set used by the kernels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SimdLevel {
+    Scalar,
+    Sse2,
+    Avx2,
+}
+
+impl SimdLevel {
+    /// The best instruction set supported by the running CPU.
+    pub fn detect() -> Self {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("avx2") {
+                return SimdLevel::Avx2;
+            }
+            if is_x86_feature_detected!("sse2") {
+                return SimdLevel::Sse2;
+            }
+        }
+        SimdLevel::Scalar
+    }
+
+    /// Whether the running CPU supports the instruction set.
+    pub fn is_supported(self) -> bool {
+        self <= Self::detect()
+    }
+}
+
+/// Encodes the bases of `seq` into `codes` as their ranks,
+/// i.e. their indices in `letters`.
+/// Returns the position of the first char of `seq` which is not in
+/// `letters`, if any, in which case the codes from it onwards are unspecified.
+///
+/// Panics if `codes` is shorter than `seq`.
+pub fn encode_2bit(letters: &[u8; 4], seq: &[u8], codes: &mut [u8]) -> Option<usize> {
+    encode_2bit_with(SimdLevel::detect(), letters, seq, codes)
+}
+
+/// Same as `encode_2bit` with the given instruction set,
+/// or the scalar fallback if it is not supported.
+pub fn encode_2bit_with(
+    level: SimdLevel,
+    letters: &[u8; 4],
+    seq: &[u8],
+    codes: &mut [u8],
+) -> Option<usize> {
+    assert!(codes.len() >= seq.len());
+    match level {
+        #[cfg(target_arch = "x86_64")]
+        SimdLevel::Avx2 if level.is_supported() => unsafe { encode_avx2(letters, seq, codes) },
+        #[cfg(target_arch = "x86_64")]
+        SimdLevel::Sse2 if level.is_supported() => unsafe { encode_sse2(letters, seq, codes) },
+        _ => encode_scalar(letters, seq, codes, 0),
+    }
+}
+
+/// Encodes `seq[from..]`.
+fn encode_scalar(letters: &[u8; 4], seq: &[u8], codes: &mut [u8], from: usize) -> Option<usize> {
+    const NULL_RK: u8 = 4;
+    let mut ranks = [NULL_RK; 256];
+    for (r, &l) in letters.iter().enumerate() {
+        ranks[l as usize] = r as u8;
+    }
+    for i in from..seq.len() {
+        match ranks[seq[i] as usize] {
+            NULL_RK => return Some(i),
+            r => codes[i] = r,
+        }
+    }
+    None
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse2")]
+unsafe fn encode_sse2(letters: &[u8; 4], seq: &[u8], codes: &mut [u8]) -> Option<usize> {
+    let l0 = _mm_set1_epi8(letters[0] as i8);
+    let l1 = _mm_set1_epi8(letters[1] as i8);
+    let l2 = _mm_set1_epi8(letters[2] as i8);
+    let l3 = _mm_set1_epi8(letters[3] as i8);
+    let (one, two, three) = (_mm_set1_epi8(1), _mm_set1_epi8(2), _mm_set1_epi8(3));
+    let mut i = 0;
+    while i + 16 <= seq.len() {
+        let v = _mm_loadu_si128(seq.as_ptr().add(i) as *const __m128i);
+        let m0 = _mm_cmpeq_epi8(v, l0);
+        let m1 = _mm_cmpeq_epi8(v, l1);
+        let m2 = _mm_cmpeq_epi8(v, l2);
+        let m3 = _mm_cmpeq_epi8(v, l3);
+        let valid = _mm_or_si128(_mm_or_si128(m0, m1), _mm_or_si128(m2, m3));
+        if _mm_movemask_epi8(valid) != 0xffff {
+            // locate the invalid base
+            return encode_scalar(letters, seq, codes, i);
+        }
+        let code = _mm_or_si128(
+            _mm_or_si128(_mm_and_si128(m1, one), _mm_and_si128(m2, two)),
+            _mm_and_si128(m3, three),
+        );
+        _mm_storeu_si128(codes.as_mut_ptr().add(i) as *mut __m128i, code);
+        i += 16;
+    }
+    encode_scalar(letters, seq, codes, i)
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn encode_avx2(letters: &[u8; 4], seq: &[u8], codes: &mut [u8]) -> Option<usize> {
+    let l0 = _mm256_set1_epi8(letters[0] as i8);
+    let l1 = _mm256_set1_epi8(letters[1] as i8);
+    let l2 = _mm256_set1_epi8(letters[2] as i8);
+    let l3 = _mm256_set1_epi8(letters[3] as i8);
+    let (one, two, three) = (
+        _mm256_set1_epi8(1),
+        _mm256_set1_epi8(2),
+        _mm256_set1_epi8(3),
+    );
+    let mut i = 0;
+    while i + 32 <= seq.len() {
+        let v = _mm256_loadu_si256(seq.as_ptr().add(i) as *const __m256i);
+        let m0 = _mm256_cmpeq_epi8(v, l0);
+        let m1 = _mm256_cmpeq_epi8(v, l1);
+        let m2 = _mm256_cmpeq_epi8(v, l2);
+        let m3 = _mm256_cmpeq_epi8(v, l3);
+        let valid = _mm256_or_si256(_mm256_or_si256(m0, m1), _mm256_or_si256(m2, m3));
+        if _mm256_movemask_epi8(valid) != -1 {
+            // locate the invalid base
+            return encode_scalar(letters, seq, codes, i);
+        }
+        let code = _mm256_or_si256(
+            _mm256_or_si256(_mm256_and_si256(m1, one), _mm256_and_si256(m2, two)),
+            _mm256_and_si256(m3, three),
+        );
+        _mm256_storeu_si256(codes.as_mut_ptr().add(i) as *mut __m256i, code);
+        i += 32;
+    }
+    encode_scalar(letters, seq, codes, i)
+}
+
+/// Mask of the `2k` low bits of a k-mer hash.
+fn kmer_mask(k: usize) -> u64 {
+    if k == 32 {
+        u64::MAX
+    } else {
+        (1 << (2 * k)) - 1
+    }
+}
+
+/// Computes the hashes of all the `k`-mers of `codes`, for `0 < k <= 32`,
+/// into `hashes`, in order. The hash of a k-mer is the number whose base-4
+/// digits are its 2-bit codes, as computed by `KmerXStrLexHasher`.
+pub fn hash_kmers_2bit(codes: &[u8], k: usize, hashes: &mut Vec<u64>) {
+    hash_kmers_2bit_with(SimdLevel::detect(), codes, k, hashes)
+}
+
+/// Same as `hash_kmers_2bit` with the given instruction set,
+/// or the scalar fallback if it is not supported.
+pub fn hash_kmers_2bit_with(level: SimdLevel, codes: &[u8], k: usize, hashes: &mut Vec<u64>) {
+    assert!(k > 0 && k <= 32);
+    hashes.clear();
+    if codes.len() < k {
+        return;
+    }
+    hashes.resize(codes.len() - k + 1, 0);
+    // the vector kernels extend the first hashes
+    let lanes = match level {
+        SimdLevel::Avx2 if level.is_supported() => 4,
+        SimdLevel::Sse2 if level.is_supported() => 2,
+        _ => 0,
+    };
+    let head = if lanes > 0 && hashes.len() >= 2 * lanes {
+        lanes
+    } else {
+        hashes.len()
+    };
+    let mask = kmer_mask(k);
+    let mut h = 0;
+    for &c in codes[..k - 1].iter() {
+        h = h << 2 | c as u64;
+    }
+    for i in 0..head {
+        h = (h << 2 | codes[i + k - 1] as u64) & mask;
+        hashes[i] = h;
+    }
+    if head == hashes.len() {
+        return;
+    }
+    match lanes {
+        #[cfg(target_arch = "x86_64")]
+        4 => unsafe { hash_kmers_avx2(codes, k, hashes) },
+        #[cfg(target_arch = "x86_64")]
+        2 => unsafe { hash_kmers_sse2(codes, k, hashes) },
+        _ => unreachable!(),
+    }
+}
+
+/// Extends `hashes[i] = (hashes[i - 1] << 2 | codes[i + k - 1]) & mask`
+/// from `from` to the end.
+fn hash_kmers_tail(codes: &[u8], k: usize, hashes: &mut [u64], from: usize) {
+    let mask = kmer_mask(k);
+    for i in from..hashes.len() {
+        hashes[i] = (hashes[i - 1] << 2 | codes[i + k - 1] as u64) & mask;
+    }
+}
+
+/// Computes `hashes[2..]` from `hashes[0..2]`, two lanes at a time.
+/// Each lane appends two codes at a time:
+/// `hashes[i] = (hashes[i - 2] << 4 | c[i + k - 2] << 2 | c[i + k - 1]) & mask`.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse2")]
+unsafe fn hash_kmers_sse2(codes: &[u8], k: usize, hashes: &mut [u64]) {
+    let n = hashes.len();
+    let mask = _mm_set1_epi64x(kmer_mask(k) as i64);
+    let pair = |p: usize| ((codes[p] as u64) << 2 | codes[p + 1] as u64) as i64;
+    let mut h = _mm_loadu_si128(hashes.as_ptr() as *const __m128i);
+    let mut i = 2;
+    while i + 2 <= n {
+        let p = i + k - 2;
+        let v = _mm_set_epi64x(pair(p + 1), pair(p));
+        h = _mm_and_si128(_mm_or_si128(_mm_slli_epi64(h, 4), v), mask);
+        _mm_storeu_si128(hashes.as_mut_ptr().add(i) as *mut __m128i, h);
+        i += 2;
+    }
+    hash_kmers_tail(codes, k, hashes, i);
+}
+
+/// Computes `hashes[4..]` from `hashes[0..4]`, four lanes at a time.
+/// Each lane appends four codes at a time, packed in a byte.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn hash_kmers_avx2(codes: &[u8], k: usize, hashes: &mut [u64]) {
+    let n = hashes.len();
+    // quads[p] packs codes[p..p + 4]
+    let quads: Vec<u8> = codes
+        .windows(4)
+        .map(|c| c[0] << 6 | c[1] << 4 | c[2] << 2 | c[3])
+        .collect();
+    let mask = _mm256_set1_epi64x(kmer_mask(k) as i64);
+    let mut h = _mm256_loadu_si256(hashes.as_ptr() as *const __m256i);
+    let mut i = 4;
+    while i + 4 <= n {
+        // quads of the lanes are contiguous
+        let q = (quads.as_ptr().add(i + k - 4) as *const i32).read_unaligned();
+        let v = _mm256_cvtepu8_epi64(_mm_cvtsi32_si128(q));
+        h = _mm256_and_si256(_mm256_or_si256(_mm256_slli_epi64(h, 8), v), mask);
+        _mm256_storeu_si256(hashes.as_mut_ptr().add(i) as *mut __m256i, h);
+        i += 4;
+    }
+    hash_kmers_tail(codes, k, hashes, i);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEVELS: [SimdLevel; 3] = [SimdLevel::Scalar, SimdLevel::Sse2, SimdLevel::Avx2];
+    const ACGT: [u8; 4] = [b'A', b'C', b'G', b'T'];
+
+    fn random_dna(len: usize, seed: u64) -> Vec<u8> {
+        let mut x = seed;
+        (0..len)
+            .map(|_| {
+                x = x
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                ACGT[(x >> 62) as usize]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_encode_2bit() {
+        let seq = random_dna(1000, 1);
+        let letters = [b'G', b'T', b'A', b'C'];
+        for &level in LEVELS.iter() {
+            for &len in [0, 1, 15, 16, 33, 64, 1000].iter() {
+                let mut codes = vec![9; len];
+                assert_eq!(
+                    encode_2bit_with(level, &letters, &seq[..len], &mut codes),
+                    None
+                );
+                for (&c, &code) in seq[..len].iter().zip(codes.iter()) {
+                    assert_eq!(letters[code as usize], c);
+                }
+            }
+            // invalid bases are reported at their position
+            for &pos in [0, 5, 31, 32, 47, 999].iter() {
+                let mut seq = seq.clone();
+                seq[pos] = b'N';
+                let last = seq.len() - 1;
+                if pos < last {
+                    seq[last] = b'a';
+                }
+                let mut codes = vec![0; seq.len()];
+                let invalid = encode_2bit_with(level, &ACGT, &seq, &mut codes);
+                assert_eq!(invalid, Some(pos));
+            }
+        }
+    }
+
+    #[test]
+    fn test_hash_kmers_2bit() {
+        let seq = random_dna(300, 2);
+        let mut codes = vec![0; seq.len()];
+        encode_2bit(&ACGT, &seq, &mut codes);
+        let mut hashes = vec![];
+        for &k in [1, 2, 3, 4, 5, 15, 31, 32].iter() {
+            let expected: Vec<u64> = codes
+                .windows(k)
+                .map(|w| w.iter().fold(0, |h, &c| h << 2 | c as u64))
+                .collect();
+            for &level in LEVELS.iter() {
+                for &len in [0, k - 1, k, k + 3, k + 8, 100, 300].iter() {
+                    hash_kmers_2bit_with(level, &codes[..len], k, &mut hashes);
+                    assert_eq!(&hashes[..], &expected[..(len + 1).saturating_sub(k)]);
+                }
+            }
+        }
+    }
+}
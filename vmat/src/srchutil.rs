@@ -1,21 +1,204 @@
-fn pred(v: &[usize], x: usize) -> Option<usize> {
-    if v.len() == 0 || v[0] > x {
-        return None;
-    }
-    let mut r = v.len();
-    if v[r] <= x {
-        return Some(r);
-    }
-    let mut l = 0;
-    let mut h: usize;
-    // invariant: pred is in [l,r)
-    while r - l > 1 {
-        h = (l + r) / 2;
-        if v[h] <= x {
-            l = h;
+//! Searches in sorted slices.
+//!
+//! All the functions take slices sorted in non-decreasing order, which may
+//! hold repeated values, and return indices in them.
+
+/// Index of the first value `>= x`, or `v.len()` if there is none.
+pub fn lower_bound<T: Ord>(v: &[T], x: &T) -> usize {
+    // invariant: v[..l] < x <= v[r..]
+    let (mut l, mut r) = (0, v.len());
+    while l < r {
+        let h = l + (r - l) / 2;
+        if v[h] < *x {
+            l = h + 1;
         } else {
             r = h;
         }
     }
-    Some(l)
+    l
+}
+
+/// Index of the first value `> x`, or `v.len()` if there is none.
+pub fn upper_bound<T: Ord>(v: &[T], x: &T) -> usize {
+    // invariant: v[..l] <= x < v[r..]
+    let (mut l, mut r) = (0, v.len());
+    while l < r {
+        let h = l + (r - l) / 2;
+        if v[h] <= *x {
+            l = h + 1;
+        } else {
+            r = h;
+        }
+    }
+    l
+}
+
+/// Index of the last value `<= x`, if any.
+pub fn pred<T: Ord>(v: &[T], x: &T) -> Option<usize> {
+    match upper_bound(v, x) {
+        0 => None,
+        i => Some(i - 1),
+    }
+}
+
+/// Index of the first value `>= x`, if any.
+pub fn succ<T: Ord>(v: &[T], x: &T) -> Option<usize> {
+    let i = lower_bound(v, x);
+    if i < v.len() {
+        Some(i)
+    } else {
+        None
+    }
+}
+
+/// Number of values in `[lo,hi)`.
+pub fn range_count<T: Ord>(v: &[T], lo: &T, hi: &T) -> usize {
+    if hi <= lo {
+        return 0;
+    }
+    lower_bound(v, hi) - lower_bound(v, lo)
+}
+
+/**
+ * Index of the first value `>= x` from `start` on, or `v.len()` if there
+ * is none, found by galloping: the search range is doubled from `start`
+ * until it holds the result, which is then searched by bisection.
+ *
+ * This takes `O(log d)` steps where `d` is the distance of the result from
+ * `start`, so that looking up increasing values, each from the result
+ * of the previous one, takes less time than independent binary searches.
+ */
+pub fn gallop<T: Ord>(v: &[T], x: &T, start: usize) -> usize {
+    let n = v.len();
+    let (mut lo, mut hi) = (start, start);
+    let mut step = 1;
+    // invariant: v[start..lo] < x
+    while hi < n && v[hi] < *x {
+        lo = hi + 1;
+        hi = start + step;
+        step *= 2;
+    }
+    let hi = hi.min(n);
+    let lo = lo.min(hi);
+    lo + lower_bound(&v[lo..hi], x)
+}
+
+/// Integer keys, which can be interpolated.
+pub trait IntKey: Ord + Copy {
+    fn to_u64(self) -> u64;
+}
+
+macro_rules! impl_int_key {
+    ($($t:ty),*) => {
+        $(impl IntKey for $t {
+            #[inline]
+            fn to_u64(self) -> u64 {
+                self as u64
+            }
+        })*
+    };
+}
+
+impl_int_key!(u8, u16, u32, u64, usize);
+
+/**
+ * Index of the first value `>= x`, or `v.len()` if there is none, found
+ * by interpolation search: each probe is placed where `x` would be if the
+ * values were evenly spread between the bounds of the search range.
+ *
+ * This takes `O(log log n)` probes on average for uniformly spread values,
+ * but up to `O(n)` for skewed ones.
+ */
+pub fn interpolation_search<T: IntKey>(v: &[T], x: T) -> usize {
+    let x = x.to_u64();
+    // invariant: v[..l] < x <= v[r..]
+    let (mut l, mut r) = (0, v.len());
+    while l < r {
+        let (a, b) = (v[l].to_u64(), v[r - 1].to_u64());
+        if x <= a {
+            return l;
+        }
+        if x > b {
+            return r;
+        }
+        // a < x <= b, the probe is in [l, r-1]
+        let span = (r - 1 - l) as u128;
+        let h = l + ((x - a) as u128 * span / (b - a) as u128) as usize;
+        if v[h].to_u64() < x {
+            l = h + 1;
+        } else {
+            r = h;
+        }
+    }
+    l
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted_values(len: usize, max: u64, seed: u64) -> Vec<u64> {
+        let mut x = seed;
+        let mut v: Vec<u64> = (0..len)
+            .map(|_| {
+                x = x
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                (x >> 33) % max
+            })
+            .collect();
+        v.sort_unstable();
+        v
+    }
+
+    #[test]
+    fn test_bounds() {
+        for &(len, max) in [
+            (0, 1),
+            (1, 3),
+            (2, 2),
+            (50, 10),
+            (300, 1000),
+            (1000, 1 << 30),
+        ]
+        .iter()
+        {
+            let v = sorted_values(len, max, len as u64);
+            for x in (0..max.min(2000) + 2).chain(v.iter().copied()) {
+                let lb = v.iter().filter(|&&y| y < x).count();
+                let ub = v.iter().filter(|&&y| y <= x).count();
+                assert_eq!(lower_bound(&v, &x), lb);
+                assert_eq!(upper_bound(&v, &x), ub);
+                assert_eq!(pred(&v, &x), ub.checked_sub(1));
+                assert_eq!(succ(&v, &x), if lb < len { Some(lb) } else { None });
+                assert_eq!(
+                    range_count(&v, &x, &(x + 3)),
+                    v.iter().filter(|&&y| y >= x && y < x + 3).count()
+                );
+                assert_eq!(range_count(&v, &x, &x), 0);
+                assert_eq!(interpolation_search(&v, x), lb);
+                for start in [0, lb / 2, lb, len, len + 2].iter().copied() {
+                    let expected = start.max(lb).min(len);
+                    assert_eq!(gallop(&v, &x, start), expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_search_ord() {
+        let v = ["ac", "b", "b", "ba", "c"];
+        assert_eq!(pred(&v, &"a"), None);
+        assert_eq!(pred(&v, &"b"), Some(2));
+        assert_eq!(succ(&v, &"b"), Some(1));
+        assert_eq!(succ(&v, &"bb"), Some(4));
+        assert_eq!(succ(&v, &"d"), None);
+        assert_eq!(range_count(&v, &"b", &"c"), 3);
+        // streaming lookups
+        let mut i = 0;
+        for (x, expected) in [("a", 0), ("b", 1), ("ba", 3), ("bz", 4), ("z", 5)].iter() {
+            i = gallop(&v, x, i);
+            assert_eq!(i, *expected);
+        }
+    }
 }
@@ -0,0 +1,416 @@
+//! Suffix arrays of one or more sequences, for exact-match seeding.
+//!
+//! The sequences are concatenated, each followed by its own separator,
+//! so that suffixes never match across sequence boundaries. The separator
+//! of the `i`-th sequence is smaller than every alphabet char and than the
+//! separators of the following sequences.
+//!
+//! The suffix array is built with the SA-IS algorithm in linear time.
+
+use std::cmp::min;
+use std::io::{Error, ErrorKind};
+use std::ops::Range;
+use std::rc::Rc;
+
+use crate::alphabet::Alphabet;
+use crate::srchutil;
+
+/// Marks empty suffix array slots during construction
+const EMPTY: usize = usize::MAX;
+
+/**
+ * Suffix array of the concatenation of sequences over an alphabet.
+ *
+ * Positions are *absolute*, i.e. offsets in the concatenation of the
+ * sequences, where each sequence is followed by one separator position.
+ * The suffixes starting at the separators are included in the array,
+ * and sort before all the others.
+ */
+pub struct SuffixArray<A>
+where
+    A: Alphabet,
+{
+    ab: Rc<A>,
+    /// Concatenated sequences, as separator indices or as shifted char ranks
+    text: Vec<usize>,
+    sa: Vec<usize>,
+    /// Start positions of the sequences, and the total length at the end
+    starts: Vec<usize>,
+}
+
+impl<A> SuffixArray<A>
+where
+    A: Alphabet,
+{
+    /// Builds the suffix array of `seqs`, whose chars must all be in `ab`.
+    pub fn new(ab: Rc<A>, seqs: &[&[A::CharType]]) -> Result<Self, Error> {
+        let nseq = seqs.len();
+        let total = seqs.iter().map(|s| s.len() + 1).sum::<usize>();
+        let mut text = Vec::with_capacity(total + 1);
+        let mut starts = Vec::with_capacity(nseq + 1);
+        for (i, seq) in seqs.iter().enumerate() {
+            starts.push(text.len());
+            for (j, c) in seq.iter().enumerate() {
+                match ab.ord(c) {
+                    Some(r) => text.push(r + nseq + 1),
+                    None => {
+                        return Err(Error::new(
+                            ErrorKind::InvalidInput,
+                            format!("Char {} of sequence {} not in alphabet", j, i),
+                        ))
+                    }
+                }
+            }
+            text.push(i + 1);
+        }
+        starts.push(text.len());
+        // the sentinel is only needed for the construction
+        text.push(0);
+        let mut sa = vec![EMPTY; text.len()];
+        sais(&text, ab.len() + nseq + 1, &mut sa);
+        text.pop();
+        sa.remove(0);
+        Ok(SuffixArray {
+            ab,
+            text,
+            sa,
+            starts,
+        })
+    }
+
+    /// Number of suffixes, i.e. the total length of the sequences
+    /// plus one separator per sequence.
+    pub fn len(&self) -> usize {
+        self.sa.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sa.is_empty()
+    }
+
+    /// Number of indexed sequences.
+    pub fn nseq(&self) -> usize {
+        self.starts.len() - 1
+    }
+
+    /// The start positions of the suffixes in lexicographic order.
+    pub fn sa(&self) -> &[usize] {
+        &self.sa
+    }
+
+    /// Absolute start position of the `seq`-th sequence.
+    pub fn seq_start(&self, seq: usize) -> usize {
+        self.starts[seq]
+    }
+
+    /// Returns the index of the sequence holding the absolute position `pos`,
+    /// and the offset of `pos` in it. The separator which follows a sequence
+    /// is at offset the length of the sequence.
+    pub fn seq_pos(&self, pos: usize) -> (usize, usize) {
+        assert!(pos < self.len());
+        let seq = srchutil::pred(&self.starts, &pos).unwrap();
+        (seq, pos - self.starts[seq])
+    }
+
+    pub(crate) fn alphabet(&self) -> &Rc<A> {
+        &self.ab
+    }
+
+    /// The concatenated sequences, as separator indices `1..=nseq`
+    /// or as char ranks shifted by `nseq + 1`.
+    pub(crate) fn symbols(&self) -> &[usize] {
+        &self.text
+    }
+
+    pub(crate) fn starts(&self) -> &[usize] {
+        &self.starts
+    }
+
+    /// Whether the absolute position `pos` holds a separator.
+    #[inline]
+    fn is_separator(&self, pos: usize) -> bool {
+        self.text[pos] <= self.nseq()
+    }
+
+    /**
+     * Computes the LCP array with Kasai's algorithm: `lcp[i]` is the length
+     * of the longest common prefix of the suffixes `sa[i-1]` and `sa[i]`,
+     * and `lcp[0]` is 0. Common prefixes do not include separators.
+     */
+    pub fn lcp(&self) -> Vec<usize> {
+        let n = self.len();
+        let mut rank = vec![0; n];
+        for (i, &p) in self.sa.iter().enumerate() {
+            rank[p] = i;
+        }
+        let mut lcp = vec![0; n];
+        let mut h = 0;
+        for p in 0..n {
+            if rank[p] == 0 {
+                h = 0;
+                continue;
+            }
+            let q = self.sa[rank[p] - 1];
+            while p + h < n
+                && q + h < n
+                && self.text[p + h] == self.text[q + h]
+                && !self.is_separator(p + h)
+            {
+                h += 1;
+            }
+            lcp[rank[p]] = h;
+            h = h.saturating_sub(1);
+        }
+        lcp
+    }
+
+    /// Returns the interval of the suffix array holding the suffixes which
+    /// start with `pattern`. The interval is empty if there is none, in which
+    /// case it starts where such suffixes would be.
+    pub fn find(&self, pattern: &[A::CharType]) -> Range<usize> {
+        let nseq = self.nseq();
+        let mut pat = Vec::with_capacity(pattern.len());
+        for c in pattern {
+            match self.ab.ord(c) {
+                Some(r) => pat.push(r + nseq + 1),
+                None => return 0..0,
+            }
+        }
+        let n = self.text.len();
+        let prefix = |p: usize| &self.text[p..min(p + pat.len(), n)];
+        let lo = self.sa.partition_point(|&p| prefix(p) < &pat[..]);
+        let hi = lo + self.sa[lo..].partition_point(|&p| prefix(p) <= &pat[..]);
+        lo..hi
+    }
+
+    /// Returns the absolute positions of the occurrences of `pattern`,
+    /// in suffix array order.
+    pub fn locate(&self, pattern: &[A::CharType]) -> &[usize] {
+        &self.sa[self.find(pattern)]
+    }
+}
+
+/// Sets the start of each bucket of chars in `heads`, from the char counts.
+fn bucket_heads(counts: &[usize], heads: &mut [usize]) {
+    let mut sum = 0;
+    for (h, &c) in heads.iter_mut().zip(counts) {
+        *h = sum;
+        sum += c;
+    }
+}
+
+/// Sets the end of each bucket of chars in `tails`, from the char counts.
+fn bucket_tails(counts: &[usize], tails: &mut [usize]) {
+    let mut sum = 0;
+    for (t, &c) in tails.iter_mut().zip(counts) {
+        sum += c;
+        *t = sum;
+    }
+}
+
+/// Induces the order of the L-type and then of the S-type suffixes
+/// from the LMS suffixes already in `sa`.
+fn induce(s: &[usize], stype: &[bool], counts: &[usize], sa: &mut [usize]) {
+    let mut bkt = vec![0; counts.len()];
+    bucket_heads(counts, &mut bkt);
+    for i in 0..sa.len() {
+        let j = sa[i];
+        if j != EMPTY && j > 0 && !stype[j - 1] {
+            sa[bkt[s[j - 1]]] = j - 1;
+            bkt[s[j - 1]] += 1;
+        }
+    }
+    bucket_tails(counts, &mut bkt);
+    for i in (0..sa.len()).rev() {
+        let j = sa[i];
+        if j != EMPTY && j > 0 && stype[j - 1] {
+            bkt[s[j - 1]] -= 1;
+            sa[bkt[s[j - 1]]] = j - 1;
+        }
+    }
+}
+
+/**
+ * Sorts the suffixes of `s` into `sa` with the SA-IS algorithm.
+ * The chars of `s` must be smaller than `k`, and `s` must end with a
+ * sentinel 0 which occurs nowhere else.
+ */
+fn sais(s: &[usize], k: usize, sa: &mut [usize]) {
+    let n = s.len();
+    if n == 1 {
+        sa[0] = 0;
+        return;
+    }
+    // S-type (true) or L-type (false) suffixes
+    let mut stype = vec![false; n];
+    stype[n - 1] = true;
+    for i in (0..n - 1).rev() {
+        stype[i] = s[i] < s[i + 1] || (s[i] == s[i + 1] && stype[i + 1]);
+    }
+    let is_lms = |i: usize| i > 0 && stype[i] && !stype[i - 1];
+    let mut counts = vec![0; k];
+    for &c in s {
+        counts[c] += 1;
+    }
+
+    // sort the LMS substrings by inducing from the unsorted LMS suffixes
+    let mut bkt = vec![0; k];
+    bucket_tails(&counts, &mut bkt);
+    sa.iter_mut().for_each(|p| *p = EMPTY);
+    for i in 1..n {
+        if is_lms(i) {
+            bkt[s[i]] -= 1;
+            sa[bkt[s[i]]] = i;
+        }
+    }
+    induce(s, &stype, &counts, sa);
+
+    // name the LMS substrings by their rank
+    let lms_eq = |a: usize, b: usize| {
+        for d in 0.. {
+            if s[a + d] != s[b + d] || stype[a + d] != stype[b + d] {
+                return false;
+            }
+            if d > 0 && (is_lms(a + d) || is_lms(b + d)) {
+                return is_lms(a + d) && is_lms(b + d);
+            }
+        }
+        unreachable!()
+    };
+    let sorted: Vec<usize> = sa.iter().copied().filter(|&p| is_lms(p)).collect();
+    let mut names = vec![EMPTY; n];
+    let mut nnames = 0;
+    for (i, &p) in sorted.iter().enumerate() {
+        if i == 0 || !lms_eq(sorted[i - 1], p) {
+            nnames += 1;
+        }
+        names[p] = nnames - 1;
+    }
+
+    // sort the LMS suffixes, recursively if their substrings are not unique
+    let lms: Vec<usize> = (1..n).filter(|&i| is_lms(i)).collect();
+    let m = lms.len();
+    let mut sa1 = vec![EMPTY; m];
+    if nnames < m {
+        let s1: Vec<usize> = lms.iter().map(|&p| names[p]).collect();
+        sais(&s1, nnames, &mut sa1);
+    } else {
+        for (i, &p) in lms.iter().enumerate() {
+            sa1[names[p]] = i;
+        }
+    }
+
+    // induce the suffix order from the sorted LMS suffixes
+    bucket_tails(&counts, &mut bkt);
+    sa.iter_mut().for_each(|p| *p = EMPTY);
+    for &i in sa1.iter().rev() {
+        let p = lms[i];
+        bkt[s[p]] -= 1;
+        sa[bkt[s[p]]] = p;
+    }
+    induce(s, &stype, &counts, sa);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alphabet::HashAlphabet;
+    use crate::dna::DNAAlphabet;
+
+    fn random_seq(len: usize, seed: u64) -> Vec<u8> {
+        let mut x = seed;
+        (0..len)
+            .map(|_| {
+                x = x
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                // skewed to get long repeats
+                b"AAACGT"[(x >> 33) as usize % 6]
+            })
+            .collect()
+    }
+
+    /// Suffixes of the separated concatenation, as (seq, char) keys
+    fn naive_sa(seqs: &[&[u8]]) -> Vec<usize> {
+        let mut text: Vec<(usize, u8)> = vec![];
+        for (i, s) in seqs.iter().enumerate() {
+            text.extend(s.iter().map(|&c| (seqs.len(), c)));
+            text.push((i, 0));
+        }
+        let mut sa: Vec<usize> = (0..text.len()).collect();
+        sa.sort_by(|&a, &b| text[a..].cmp(&text[b..]));
+        sa
+    }
+
+    #[test]
+    fn test_suffix_array() {
+        let ab = Rc::new(DNAAlphabet::new());
+        let (a, b, c) = (random_seq(1000, 1), random_seq(37, 2), b"AAAAAAAA".to_vec());
+        let cases: Vec<Vec<&[u8]>> = vec![
+            vec![b"GATTACA"],
+            vec![b""],
+            vec![&c],
+            vec![&a],
+            vec![&a, &b, b"", &c, &a[100..300], &c],
+        ];
+        for seqs in cases.iter() {
+            let sa = SuffixArray::new(Rc::clone(&ab), seqs).unwrap();
+            assert_eq!(sa.nseq(), seqs.len());
+            assert_eq!(sa.sa(), &naive_sa(seqs)[..]);
+        }
+        assert!(SuffixArray::new(ab, &[b"ACGNT"]).is_err());
+    }
+
+    #[test]
+    fn test_lcp() {
+        let ab = Rc::new(DNAAlphabet::new());
+        let (a, b) = (random_seq(500, 3), random_seq(300, 4));
+        let seqs: Vec<&[u8]> = vec![&a, &b, &a[50..400], b"ACGT", b"ACGT"];
+        let sa = SuffixArray::new(ab, &seqs).unwrap();
+        let lcp = sa.lcp();
+        assert_eq!(lcp[0], 0);
+        let seq_end = |p: usize| {
+            let (s, _) = sa.seq_pos(p);
+            sa.seq_start(s) + seqs[s].len()
+        };
+        for (w, &h) in sa.sa().windows(2).zip(&lcp[1..]) {
+            let (p, q) = (w[0], w[1]);
+            let (x, y) = (seq_end(p) - p, seq_end(q) - q);
+            let expected = (0..min(x, y))
+                .take_while(|&d| sa.text[p + d] == sa.text[q + d])
+                .count();
+            assert_eq!(h, expected);
+        }
+    }
+
+    #[test]
+    fn test_find() {
+        let ab = Rc::new(HashAlphabet::new("ab€".chars().collect()));
+        let seqs: Vec<Vec<char>> = ["abba€ab", "bab", "", "€€a"]
+            .iter()
+            .map(|s| s.chars().collect())
+            .collect();
+        let refs: Vec<&[char]> = seqs.iter().map(|s| &s[..]).collect();
+        let sa = SuffixArray::new(ab, &refs).unwrap();
+        let occs = |pat: &str| {
+            let pat: Vec<char> = pat.chars().collect();
+            let mut occs: Vec<(usize, usize)> =
+                sa.locate(&pat).iter().map(|&p| sa.seq_pos(p)).collect();
+            occs.sort_unstable();
+            occs
+        };
+        assert_eq!(occs("ab"), vec![(0, 0), (0, 5), (1, 1)]);
+        assert_eq!(occs("ba"), vec![(0, 2), (1, 0)]);
+        assert_eq!(occs("€"), vec![(0, 4), (3, 0), (3, 1)]);
+        assert_eq!(occs("€€a"), vec![(3, 0)]);
+        // no match across sequences
+        assert!(occs("bb").len() == 1 && occs("bba").len() == 1);
+        assert!(occs("abb€").is_empty());
+        assert!(occs("bab€").is_empty());
+        assert!(occs("x").is_empty());
+        assert_eq!(sa.find(&[]), 0..sa.len());
+        let r = sa.find(&['b', 'b', 'b']);
+        assert!(r.is_empty() && r.start > 0);
+        assert_eq!(sa.seq_pos(sa.seq_start(1) + 3), (1, 3));
+    }
+}
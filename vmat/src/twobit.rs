@@ -0,0 +1,574 @@
+//! Reader of UCSC `.2bit` sequence files.
+//!
+//! Bases are stored with 2 bits each (`T=0, C=1, A=2, G=3`, most significant
+//! bits first), together with the lists of N blocks and soft-mask
+//! (lowercase) blocks of every sequence.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Error, ErrorKind, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::binio;
+use crate::xstream::{end_read_until, take_pushback, DelimiterMode, ReadStatus, XStream};
+use crate::xstring::XString;
+
+const TWOBIT_SIGNATURE: u32 = 0x1A41_2743;
+
+/// Decoded bases of the 4 base pairs packed in each byte value
+const BASES: [u8; 4] = [b'T', b'C', b'A', b'G'];
+
+/// Number of bases decoded at a time by `TwoBitStream`
+const STREAM_CHUNK: usize = 1 << 16;
+
+/// A sequence of a `.2bit` file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TwoBitSeq {
+    pub name: String,
+    /// Sequence length
+    pub len: usize,
+    /// `(start, size)` of the blocks of Ns
+    pub n_blocks: Vec<(usize, usize)>,
+    /// `(start, size)` of the soft-masked (lowercase) blocks
+    pub mask_blocks: Vec<(usize, usize)>,
+    /// Byte offset of the packed bases
+    dna_offset: u64,
+}
+
+/// Calls `f(from, to)` for each intersection `[from,to)` of the `blocks`,
+/// sorted by start, with `[start,end)`.
+fn for_overlaps<F: FnMut(usize, usize)>(
+    blocks: &[(usize, usize)],
+    start: usize,
+    end: usize,
+    mut f: F,
+) {
+    let first = blocks.partition_point(|&(s, l)| s + l <= start);
+    for &(s, l) in blocks[first..].iter().take_while(|&&(s, _)| s < end) {
+        f(s.max(start), (s + l).min(end));
+    }
+}
+
+/// Tells whether the `blocks` are sorted by start, do not overlap
+/// and are within a sequence of length `len`.
+fn valid_blocks(blocks: &[(usize, usize)], len: usize) -> bool {
+    let mut end = 0;
+    for &(s, l) in blocks {
+        if s < end || s + l > len {
+            return false;
+        }
+        end = s + l;
+    }
+    true
+}
+
+pub struct TwoBitReader<R>
+where
+    R: Read + Seek,
+{
+    src: R,
+    big_endian: bool,
+    soft_mask: bool,
+    seqs: Vec<TwoBitSeq>,
+    by_name: HashMap<String, usize>,
+}
+
+impl TwoBitReader<BufReader<File>> {
+    pub fn new_from_path<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R> TwoBitReader<R>
+where
+    R: Read + Seek,
+{
+    /// Reads the header, the index and the block lists of all sequences.
+    /// Fails with `InvalidData` if the packed bases of a sequence would
+    /// extend past the end of the file, or if its N or mask blocks are not
+    /// sorted, overlap or extend past its end.
+    pub fn new(mut src: R) -> Result<Self, Error> {
+        let file_len = src.seek(SeekFrom::End(0))?;
+        src.seek(SeekFrom::Start(0))?;
+        let mut reader = TwoBitReader {
+            src,
+            big_endian: false,
+            soft_mask: true,
+            seqs: vec![],
+            by_name: HashMap::new(),
+        };
+        let signature = reader.read_u32()?;
+        if signature != TWOBIT_SIGNATURE {
+            if signature.swap_bytes() != TWOBIT_SIGNATURE {
+                return Err(Error::new(ErrorKind::InvalidData, "Not a .2bit file"));
+            }
+            reader.big_endian = true;
+        }
+        let version = reader.read_u32()?;
+        if version > 1 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Unsupported .2bit version {}", version),
+            ));
+        }
+        let nseq = reader.read_u32()? as usize;
+        let _reserved = reader.read_u32()?;
+        let mut index = binio::with_capacity(nseq);
+        for _ in 0..nseq {
+            let mut len = [0u8; 1];
+            reader.src.read_exact(&mut len)?;
+            let mut name = vec![0u8; len[0] as usize];
+            reader.src.read_exact(&mut name)?;
+            // version 1 has 64-bit offsets
+            let offset = if version == 1 {
+                reader.read_u64()?
+            } else {
+                reader.read_u32()? as u64
+            };
+            index.push((String::from_utf8_lossy(&name).into_owned(), offset));
+        }
+        for (name, offset) in index {
+            reader.src.seek(SeekFrom::Start(offset))?;
+            let len = reader.read_u32()? as usize;
+            let n_blocks = reader.read_blocks()?;
+            let mask_blocks = reader.read_blocks()?;
+            let _reserved = reader.read_u32()?;
+            if !valid_blocks(&n_blocks, len) || !valid_blocks(&mask_blocks, len) {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Invalid N or mask blocks in .2bit sequence {}", name),
+                ));
+            }
+            let dna_offset = reader.src.stream_position()?;
+            if dna_offset + (len as u64).div_ceil(4) > file_len {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Truncated .2bit file: sequence {} is incomplete", name),
+                ));
+            }
+            reader.by_name.insert(name.clone(), reader.seqs.len());
+            reader.seqs.push(TwoBitSeq {
+                name,
+                len,
+                n_blocks,
+                mask_blocks,
+                dna_offset,
+            });
+        }
+        Ok(reader)
+    }
+
+    fn read_u32(&mut self) -> Result<u32, Error> {
+        let mut buf = [0u8; 4];
+        self.src.read_exact(&mut buf)?;
+        Ok(if self.big_endian {
+            u32::from_be_bytes(buf)
+        } else {
+            u32::from_le_bytes(buf)
+        })
+    }
+
+    fn read_u64(&mut self) -> Result<u64, Error> {
+        let mut buf = [0u8; 8];
+        self.src.read_exact(&mut buf)?;
+        Ok(if self.big_endian {
+            u64::from_be_bytes(buf)
+        } else {
+            u64::from_le_bytes(buf)
+        })
+    }
+
+    /// Reads a block count, followed by the block starts and the block sizes.
+    fn read_blocks(&mut self) -> Result<Vec<(usize, usize)>, Error> {
+        let n = self.read_u32()? as usize;
+        let mut starts = binio::with_capacity(n);
+        for _ in 0..n {
+            starts.push(self.read_u32()? as usize);
+        }
+        let mut blocks = binio::with_capacity(n);
+        for start in starts {
+            blocks.push((start, self.read_u32()? as usize));
+        }
+        Ok(blocks)
+    }
+
+    /// Enables or disables lowercase output of the soft-masked blocks
+    /// (enabled by default).
+    pub fn set_soft_mask(&mut self, soft_mask: bool) {
+        self.soft_mask = soft_mask;
+    }
+
+    pub fn seqs(&self) -> &[TwoBitSeq] {
+        &self.seqs
+    }
+
+    pub fn nseq(&self) -> usize {
+        self.seqs.len()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&TwoBitSeq> {
+        self.by_name.get(name).map(|&i| &self.seqs[i])
+    }
+
+    fn seq_index(&self, name: &str) -> Result<usize, Error> {
+        self.by_name.get(name).copied().ok_or_else(|| {
+            Error::new(
+                ErrorKind::NotFound,
+                format!("Sequence {} not found in .2bit file", name),
+            )
+        })
+    }
+
+    /// Decodes the bases `[start,end)` of the `i`-th sequence into `dst`.
+    fn fetch_into(
+        &mut self,
+        i: usize,
+        start: usize,
+        end: usize,
+        dst: &mut Vec<u8>,
+    ) -> Result<(), Error> {
+        let seq = &self.seqs[i];
+        let first_byte = start / 4;
+        let last_byte = end.div_ceil(4);
+        let mut packed = vec![0u8; last_byte - first_byte];
+        self.src
+            .seek(SeekFrom::Start(seq.dna_offset + first_byte as u64))?;
+        self.src.read_exact(&mut packed)?;
+        let from = dst.len();
+        dst.extend((start..end).map(|p| {
+            let byte = packed[p / 4 - first_byte];
+            BASES[((byte >> (6 - 2 * (p % 4))) & 3) as usize]
+        }));
+        let seq = &self.seqs[i];
+        let region = &mut dst[from..];
+        for_overlaps(&seq.n_blocks, start, end, |s, e| {
+            region[s - start..e - start]
+                .iter_mut()
+                .for_each(|c| *c = b'N');
+        });
+        if self.soft_mask {
+            for_overlaps(&seq.mask_blocks, start, end, |s, e| {
+                region[s - start..e - start].make_ascii_lowercase();
+            });
+        }
+        Ok(())
+    }
+
+    /// Fetches the bases in the (0-based) positions `[start,end)` of the
+    /// sequence `name`. `end` is clipped to the sequence length.
+    pub fn fetch(&mut self, name: &str, start: usize, end: usize) -> Result<XString<u8>, Error> {
+        let i = self.seq_index(name)?;
+        let end = end.min(self.seqs[i].len);
+        if start > end {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid region {}:{}-{}", name, start, end),
+            ));
+        }
+        let mut seq = Vec::with_capacity(end - start);
+        self.fetch_into(i, start, end, &mut seq)?;
+        Ok(XString::from(seq))
+    }
+
+    /// Reads the whole sequence `name`.
+    pub fn read_seq(&mut self, name: &str) -> Result<XString<u8>, Error> {
+        self.fetch(name, 0, usize::MAX)
+    }
+
+    /// Opens a stream over the bases of the sequence `name`,
+    /// decoded in chunks so that memory use is constant.
+    pub fn stream(&mut self, name: &str) -> Result<TwoBitStream<'_, R>, Error> {
+        let i = self.seq_index(name)?;
+        let end = self.seqs[i].len;
+        Ok(TwoBitStream {
+            reader: self,
+            seq: i,
+            pos: 0,
+            end,
+            buf: Vec::with_capacity(STREAM_CHUNK.min(end)),
+            buf_pos: 0,
+            pushback: Vec::new(),
+        })
+    }
+}
+
+/// Stream over the bases of a sequence of a `.2bit` file.
+pub struct TwoBitStream<'a, R>
+where
+    R: Read + Seek,
+{
+    reader: &'a mut TwoBitReader<R>,
+    seq: usize,
+    pos: usize, // next position to decode
+    end: usize,
+    buf: Vec<u8>,
+    buf_pos: usize,
+    pushback: Vec<u8>,
+}
+
+impl<'a, R> TwoBitStream<'a, R>
+where
+    R: Read + Seek,
+{
+    /// Makes sure there are buffered bases, unless at the end of the sequence.
+    /// Returns the buffered bases.
+    fn fill_buf(&mut self) -> Result<&[u8], Error> {
+        if self.buf_pos == self.buf.len() && self.pos < self.end {
+            let to = self.end.min(self.pos + STREAM_CHUNK);
+            self.buf.clear();
+            self.buf_pos = 0;
+            self.reader
+                .fetch_into(self.seq, self.pos, to, &mut self.buf)?;
+            self.pos = to;
+        }
+        Ok(&self.buf[self.buf_pos..])
+    }
+}
+
+impl<'a, R> XStream for TwoBitStream<'a, R>
+where
+    R: Read + Seek,
+{
+    type CharType = u8;
+
+    fn get(&mut self) -> Result<Option<u8>, Error> {
+        if let Some(c) = self.pushback.pop() {
+            return Ok(Some(c));
+        }
+        let c = self.fill_buf()?.first().copied();
+        if c.is_some() {
+            self.buf_pos += 1;
+        }
+        Ok(c)
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let (mut n, _) = take_pushback(&mut self.pushback, buf, None);
+        while n < buf.len() {
+            let avail = self.fill_buf()?;
+            if avail.is_empty() {
+                break;
+            }
+            let m = avail.len().min(buf.len() - n);
+            buf[n..n + m].copy_from_slice(&avail[..m]);
+            self.buf_pos += m;
+            n += m;
+        }
+        Ok(n)
+    }
+
+    fn read_until(
+        &mut self,
+        buf: &mut [u8],
+        delimiter: u8,
+        mode: DelimiterMode,
+    ) -> Result<(usize, ReadStatus), Error> {
+        let (mut n, found) = take_pushback(&mut self.pushback, buf, Some(delimiter));
+        while !found && n < buf.len() {
+            let avail = self.fill_buf()?;
+            if avail.is_empty() {
+                break;
+            }
+            let m = avail.len().min(buf.len() - n);
+            let (m, found) = match avail[..m].iter().position(|&c| c == delimiter) {
+                Some(d) => (d, true),
+                None => (m, false),
+            };
+            buf[n..n + m].copy_from_slice(&avail[..m]);
+            self.buf_pos += m;
+            n += m;
+            if found {
+                break;
+            }
+        }
+        end_read_until(self, buf, n, delimiter, mode)
+    }
+
+    fn unget(&mut self, c: u8) {
+        self.pushback.push(c);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dna::DNAAlphabet;
+    use crate::minimiser::MmIndex;
+    use crate::xstring::KmerXStrLexHasher;
+    use std::io::Cursor;
+    use std::rc::Rc;
+
+    /// Encodes `seqs` as a .2bit file. Ns and lowercase chars are stored
+    /// as N and mask blocks.
+    fn twobit(seqs: &[(&str, &[u8])], big_endian: bool) -> Vec<u8> {
+        let u32b = |v: usize| {
+            if big_endian {
+                (v as u32).to_be_bytes()
+            } else {
+                (v as u32).to_le_bytes()
+            }
+        };
+        let blocks = |s: &[u8], pred: &dyn Fn(u8) -> bool| {
+            let mut blocks: Vec<(usize, usize)> = vec![];
+            for (i, &c) in s.iter().enumerate() {
+                if pred(c) {
+                    match blocks.last_mut() {
+                        Some((start, len)) if *start + *len == i => *len += 1,
+                        _ => blocks.push((i, 1)),
+                    }
+                }
+            }
+            blocks
+        };
+        let mut records: Vec<Vec<u8>> = vec![];
+        for (_, s) in seqs.iter() {
+            let mut rec = vec![];
+            rec.extend(&u32b(s.len()));
+            for bl in [
+                blocks(s, &|c| c == b'N' || c == b'n'),
+                blocks(s, &|c| c.is_ascii_lowercase()),
+            ]
+            .iter()
+            {
+                rec.extend(&u32b(bl.len()));
+                bl.iter().for_each(|b| rec.extend(&u32b(b.0)));
+                bl.iter().for_each(|b| rec.extend(&u32b(b.1)));
+            }
+            rec.extend(&u32b(0));
+            let mut packed = vec![0u8; s.len().div_ceil(4)];
+            for (i, c) in s.iter().enumerate() {
+                let code = match c.to_ascii_uppercase() {
+                    b'C' => 1,
+                    b'A' => 2,
+                    b'G' => 3,
+                    _ => 0,
+                };
+                packed[i / 4] |= code << (6 - 2 * (i % 4));
+            }
+            rec.extend(packed);
+            records.push(rec);
+        }
+        let mut out = vec![];
+        out.extend(&u32b(TWOBIT_SIGNATURE as usize));
+        out.extend(&u32b(0));
+        out.extend(&u32b(seqs.len()));
+        out.extend(&u32b(0));
+        let index_len: usize = seqs.iter().map(|(n, _)| 1 + n.len() + 4).sum();
+        let mut offset = 16 + index_len;
+        for ((name, _), rec) in seqs.iter().zip(records.iter()) {
+            out.push(name.len() as u8);
+            out.extend(name.bytes());
+            out.extend(&u32b(offset));
+            offset += rec.len();
+        }
+        records.iter().for_each(|rec| out.extend(rec));
+        out
+    }
+
+    const SEQ1: &[u8] = b"ACGTNNNNacgtaACCGGTTnnTGCA";
+    const SEQ2: &[u8] = b"GATTACA";
+
+    #[test]
+    fn test_read_twobit() {
+        for &big_endian in [false, true].iter() {
+            let src = twobit(&[("chr1", SEQ1), ("chr2", SEQ2)], big_endian);
+            let mut reader = TwoBitReader::new(Cursor::new(src)).unwrap();
+            assert_eq!(reader.nseq(), 2);
+            assert_eq!(reader.get("chr1").unwrap().n_blocks, vec![(4, 4), (20, 2)]);
+            assert_eq!(
+                reader.get("chr1").unwrap().mask_blocks,
+                vec![(8, 5), (20, 2)]
+            );
+            assert_eq!(&reader.read_seq("chr1").unwrap()[..], SEQ1);
+            assert_eq!(&reader.read_seq("chr2").unwrap()[..], SEQ2);
+            assert_eq!(&reader.fetch("chr1", 6, 10).unwrap()[..], b"NNac");
+            assert_eq!(&reader.fetch("chr1", 3, 3).unwrap()[..], b"");
+            assert_eq!(&reader.fetch("chr2", 5, 100).unwrap()[..], b"CA");
+            assert!(reader.fetch("chr1", 8, 6).is_err());
+            assert!(reader.fetch("chr3", 0, 1).is_err());
+            reader.set_soft_mask(false);
+            assert_eq!(&reader.fetch("chr1", 6, 14).unwrap()[..], b"NNACGTAA");
+        }
+        assert!(TwoBitReader::new(Cursor::new(b"ACGTACGTACGTACGT".to_vec())).is_err());
+    }
+
+    #[test]
+    fn test_twobit_truncated() {
+        let src = twobit(&[("chr1", SEQ1), ("chr2", SEQ2)], false);
+        for len in 0..src.len() {
+            assert!(TwoBitReader::new(Cursor::new(src[..len].to_vec())).is_err());
+        }
+        // garbled sequence count and sequence length
+        let mut garbled = src.clone();
+        garbled[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(TwoBitReader::new(Cursor::new(garbled)).is_err());
+        let reader = TwoBitReader::new(Cursor::new(src.clone())).unwrap();
+        let len_offset = reader.get("chr2").unwrap().dna_offset as usize - 4 * 4;
+        let mut garbled = src;
+        garbled[len_offset..len_offset + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        let e = TwoBitReader::new(Cursor::new(garbled)).err().unwrap();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_twobit_invalid_blocks() {
+        let src = twobit(&[("chr1", SEQ1)], false);
+        let reader = TwoBitReader::new(Cursor::new(src.clone())).unwrap();
+        // chr1 has N blocks (4,4) and (20,2), stored as length,
+        // count, starts and sizes, and as many mask blocks
+        let rec = reader.get("chr1").unwrap().dna_offset as usize - 4 * 12;
+        let n_start = |i: usize| rec + 8 + 4 * i;
+        let n_size = |i: usize| rec + 16 + 4 * i;
+        // unsorted, overlapping and past the end of the sequence
+        for &(offset, value) in [(n_start(0), 21u32), (n_start(1), 6), (n_size(1), 7)].iter() {
+            let mut garbled = src.clone();
+            garbled[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+            let e = TwoBitReader::new(Cursor::new(garbled)).err().unwrap();
+            assert_eq!(e.kind(), ErrorKind::InvalidData);
+        }
+        // adjacent blocks are valid
+        let mut adjacent = src;
+        adjacent[n_start(1)..n_start(1) + 4].copy_from_slice(&8u32.to_le_bytes());
+        let mut reader = TwoBitReader::new(Cursor::new(adjacent)).unwrap();
+        assert_eq!(&reader.fetch("chr1", 2, 12).unwrap()[..], b"GTNNNNnngt");
+    }
+
+    #[test]
+    fn test_twobit_stream() {
+        let seq: Vec<u8> = b"ACGTTGCAAT"
+            .iter()
+            .cycle()
+            .take(3 * STREAM_CHUNK + 5)
+            .copied()
+            .collect();
+        let src = twobit(&[("s", &seq)], false);
+        let mut reader = TwoBitReader::new(Cursor::new(src)).unwrap();
+        let mut stream = reader.stream("s").unwrap();
+        assert_eq!(stream.get().unwrap(), Some(b'A'));
+        stream.unget(b'A');
+        assert_eq!(stream.skip_while(|c| c == b'A').unwrap(), 1);
+        let mut buf = vec![0u8; STREAM_CHUNK + 3];
+        assert_eq!(stream.read(&mut buf).unwrap(), buf.len());
+        assert_eq!(&buf[..], &seq[1..buf.len() + 1]);
+        let mut pos = buf.len() + 1;
+        // stops after the delimiter
+        let dist = seq[pos..].iter().position(|&c| c == b'G').unwrap();
+        let r = stream.read_until(&mut buf, b'G', DelimiterMode::Inclusive);
+        assert_eq!(r.unwrap(), (dist + 1, ReadStatus::Found));
+        assert_eq!(buf[dist], b'G');
+        pos += dist + 1;
+        let mut rest = vec![];
+        while let Some(c) = stream.get().unwrap() {
+            rest.push(c);
+        }
+        assert_eq!(&rest[..], &seq[pos..]);
+
+        // index directly from the stream
+        let ranker = KmerXStrLexHasher::new(Rc::new(DNAAlphabet::new()), 5);
+        let mut from_2bit = MmIndex::new(&[4], &[5], &[&ranker]);
+        from_2bit.index(&mut reader.stream("s").unwrap()).unwrap();
+        let mut from_xstr = MmIndex::new(&[4], &[5], &[&ranker]);
+        from_xstr.index_xstr(&XString::from(seq)).unwrap();
+        assert_eq!(from_2bit.stats(), from_xstr.stats());
+    }
+}
@@ -1,33 +1,255 @@
-use crate::alphabet::Character;
+use crate::alphabet::{CharEncoding, Character, Decoded};
+use crate::compress::{Compression, Decoder};
 use crate::xstring::XString;
+use memmap2::Mmap;
 use std::fs::File;
-use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Seek, SeekFrom};
+use std::io::{BufRead, BufReader, Error, ErrorKind, Seek, SeekFrom};
 use std::path::Path;
-use std::slice;
+
+/// Whether `XStream::read_until` stores the delimiter in the buffer.
+/// The delimiter is consumed in both cases.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DelimiterMode {
+    /// The delimiter is stored after the chars preceding it
+    Inclusive,
+    /// The delimiter is discarded
+    Exclusive,
+}
+
+/// Why `XStream::read_until` stopped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReadStatus {
+    /// The delimiter was found and consumed
+    Found,
+    /// The buffer is full. The delimiter, if next, is left in the stream
+    /// unless it is discarded (`DelimiterMode::Exclusive`)
+    BufferFull,
+    /// The end of the stream was reached before any delimiter
+    Eof,
+}
 
 pub trait XStream {
-    type CharType;
+    type CharType: Copy + PartialEq;
 
     /// Reads from stream into the given buffer.
     /// Returns the number of items (chars) read.
     fn read(&mut self, buf: &mut [Self::CharType]) -> Result<usize, std::io::Error>;
 
+    /// Reads chars into `buf` up to the first `delimiter`, which is
+    /// consumed, and stored in `buf` if `mode` is inclusive.
+    /// Returns the number of chars stored and why the read stopped.
     fn read_until(
         &mut self,
         buf: &mut [Self::CharType],
         delimiter: Self::CharType,
-    ) -> Result<usize, std::io::Error>;
+        mode: DelimiterMode,
+    ) -> Result<(usize, ReadStatus), std::io::Error>;
+
+    /// Appends the chars up to the first `delimiter` to `xstr`, which grows
+    /// as needed, as in `read_until`.
+    /// Returns `ReadStatus::Found`, or `ReadStatus::Eof` if there is no
+    /// delimiter up to the end of the stream.
+    fn read_until_xstring(
+        &mut self,
+        xstr: &mut XString<Self::CharType>,
+        delimiter: Self::CharType,
+        mode: DelimiterMode,
+    ) -> Result<ReadStatus, std::io::Error>
+    where
+        Self::CharType: Character,
+    {
+        let mut chunk = [Self::CharType::default(); 1024];
+        loop {
+            let (n, status) = self.read_until(&mut chunk, delimiter, mode)?;
+            xstr.append_from_slice(&chunk[..n]);
+            if status != ReadStatus::BufferFull {
+                return Ok(status);
+            }
+        }
+    }
 
     /// Reads the next char from stream, if any.
     fn get(&mut self) -> Result<Option<Self::CharType>, std::io::Error>;
+
+    /// Pushes `c` back into the stream, to be read again before any other char.
+    /// Several chars may be pushed back: they are read in the reverse order,
+    /// and need not be the chars read last.
+    fn unget(&mut self, c: Self::CharType);
+
+    /// Returns the next char without consuming it, if any.
+    fn peek(&mut self) -> Result<Option<Self::CharType>, std::io::Error> {
+        let c = self.get()?;
+        if let Some(c) = c {
+            self.unget(c);
+        }
+        Ok(c)
+    }
+
+    /// Copies the next `buf.len()` chars into `buf` without consuming them.
+    /// Returns the number of chars copied, which is smaller than `buf.len()`
+    /// only at the end of the stream.
+    fn peek_n(&mut self, buf: &mut [Self::CharType]) -> Result<usize, std::io::Error> {
+        let mut n = 0;
+        while n < buf.len() {
+            match self.get()? {
+                Some(c) => buf[n] = c,
+                None => break,
+            }
+            n += 1;
+        }
+        for &c in buf[..n].iter().rev() {
+            self.unget(c);
+        }
+        Ok(n)
+    }
+
+    /// Skips the chars satisfying `pred`, up to the first one which does not.
+    /// Returns the number of chars skipped.
+    fn skip_while<P>(&mut self, mut pred: P) -> Result<usize, std::io::Error>
+    where
+        Self: Sized,
+        P: FnMut(Self::CharType) -> bool,
+    {
+        let mut n = 0;
+        while let Some(c) = self.get()? {
+            if !pred(c) {
+                self.unget(c);
+                break;
+            }
+            n += 1;
+        }
+        Ok(n)
+    }
+
+    /// Reads the chars satisfying `pred` into `buf`, up to the first one
+    /// which does not or until `buf` is full.
+    /// Returns the number of chars read.
+    fn read_while<P>(
+        &mut self,
+        buf: &mut [Self::CharType],
+        mut pred: P,
+    ) -> Result<usize, std::io::Error>
+    where
+        Self: Sized,
+        P: FnMut(Self::CharType) -> bool,
+    {
+        let mut n = 0;
+        while n < buf.len() {
+            match self.get()? {
+                Some(c) if pred(c) => buf[n] = c,
+                Some(c) => {
+                    self.unget(c);
+                    break;
+                }
+                None => break,
+            }
+            n += 1;
+        }
+        Ok(n)
+    }
+}
+
+/// Completes a `read_until` on `stream` which has read `n` chars into `buf`
+/// and stopped before the delimiter, at the end of the stream or
+/// because `buf` is full.
+pub(crate) fn end_read_until<S>(
+    stream: &mut S,
+    buf: &mut [S::CharType],
+    mut n: usize,
+    delimiter: S::CharType,
+    mode: DelimiterMode,
+) -> Result<(usize, ReadStatus), std::io::Error>
+where
+    S: XStream + ?Sized,
+{
+    let status = match stream.peek()? {
+        None => ReadStatus::Eof,
+        Some(c) if c != delimiter => ReadStatus::BufferFull,
+        Some(_) if mode == DelimiterMode::Inclusive => {
+            if n == buf.len() {
+                ReadStatus::BufferFull
+            } else {
+                stream.get()?;
+                buf[n] = delimiter;
+                n += 1;
+                ReadStatus::Found
+            }
+        }
+        Some(_) => {
+            stream.get()?;
+            ReadStatus::Found
+        }
+    };
+    Ok((n, status))
+}
+
+/// Moves the chars pushed back into a stream to the start of `buf`, in the
+/// order they are to be read, stopping before `delimiter` if any.
+/// Returns the number of chars moved and whether `delimiter` was found.
+pub(crate) fn take_pushback<C>(
+    pushback: &mut Vec<C>,
+    buf: &mut [C],
+    delimiter: Option<C>,
+) -> (usize, bool)
+where
+    C: Copy + PartialEq,
+{
+    let mut n = 0;
+    while n < buf.len() {
+        match pushback.last() {
+            Some(&c) if Some(c) == delimiter => return (n, true),
+            Some(&c) => buf[n] = c,
+            None => break,
+        }
+        pushback.pop();
+        n += 1;
+    }
+    (n, false)
+}
+
+impl<S> XStream for &mut S
+where
+    S: XStream + ?Sized,
+{
+    type CharType = S::CharType;
+
+    fn read(&mut self, buf: &mut [Self::CharType]) -> Result<usize, std::io::Error> {
+        (**self).read(buf)
+    }
+
+    fn read_until(
+        &mut self,
+        buf: &mut [Self::CharType],
+        delimiter: Self::CharType,
+        mode: DelimiterMode,
+    ) -> Result<(usize, ReadStatus), std::io::Error> {
+        (**self).read_until(buf, delimiter, mode)
+    }
+
+    fn get(&mut self) -> Result<Option<Self::CharType>, std::io::Error> {
+        (**self).get()
+    }
+
+    fn unget(&mut self, c: Self::CharType) {
+        (**self).unget(c)
+    }
+
+    fn peek(&mut self) -> Result<Option<Self::CharType>, std::io::Error> {
+        (**self).peek()
+    }
+
+    fn peek_n(&mut self, buf: &mut [Self::CharType]) -> Result<usize, std::io::Error> {
+        (**self).peek_n(buf)
+    }
 }
 
 pub struct XStrStream<'a, C>
 where
     C: Character,
 {
-    xstr: &'a XString<C>,
+    xstr: &'a [C],
     cur: usize,
+    pushback: Vec<C>,
 }
 
 impl<'a, C> XStrStream<'a, C>
@@ -35,7 +257,15 @@ where
     C: Character,
 {
     pub fn open(xstr: &'a XString<C>) -> Self {
-        XStrStream { xstr, cur: 0 }
+        Self::open_slice(xstr)
+    }
+
+    pub fn open_slice(xstr: &'a [C]) -> Self {
+        XStrStream {
+            xstr,
+            cur: 0,
+            pushback: Vec::new(),
+        }
     }
 
     pub fn close(self) {}
@@ -48,6 +278,9 @@ where
     type CharType = C;
 
     fn get(&mut self) -> Result<Option<C>, std::io::Error> {
+        if let Some(c) = self.pushback.pop() {
+            return Ok(Some(c));
+        }
         if self.cur < self.xstr.len() {
             self.cur += 1;
             Ok(Some(self.xstr[self.cur - 1]))
@@ -57,41 +290,83 @@ where
     }
 
     fn read(&mut self, buf: &mut [C]) -> Result<usize, std::io::Error> {
-        let nitems = std::cmp::min(buf.len(), self.xstr.len() - self.cur);
-        buf[..nitems].copy_from_slice(&self.xstr[self.cur..self.cur + nitems]);
+        let (n, _) = take_pushback(&mut self.pushback, buf, None);
+        let nitems = std::cmp::min(buf.len() - n, self.xstr.len() - self.cur);
+        buf[n..n + nitems].copy_from_slice(&self.xstr[self.cur..self.cur + nitems]);
         self.cur += nitems;
-        Ok(nitems)
+        Ok(n + nitems)
     }
 
-    fn read_until(&mut self, buf: &mut [C], delimiter: C) -> Result<usize, std::io::Error> {
-        let nitems = self.xstr[self.cur..]
-            .iter()
-            .position(|&x| x == delimiter)
-            .unwrap_or_else(|| self.xstr.len() - self.cur);
-        buf[..nitems].copy_from_slice(&self.xstr[self.cur..self.cur + nitems]);
-        self.cur += nitems;
-        Ok(nitems)
+    fn read_until(
+        &mut self,
+        buf: &mut [C],
+        delimiter: C,
+        mode: DelimiterMode,
+    ) -> Result<(usize, ReadStatus), std::io::Error> {
+        let (mut n, found) = take_pushback(&mut self.pushback, buf, Some(delimiter));
+        if !found {
+            let avail = &self.xstr[self.cur..];
+            let avail = &avail[..avail.len().min(buf.len() - n)];
+            let nitems = avail
+                .iter()
+                .position(|&x| x == delimiter)
+                .unwrap_or(avail.len());
+            buf[n..n + nitems].copy_from_slice(&avail[..nitems]);
+            self.cur += nitems;
+            n += nitems;
+        }
+        end_read_until(self, buf, n, delimiter, mode)
+    }
+
+    fn unget(&mut self, c: C) {
+        if self.pushback.is_empty() && self.cur > 0 && self.xstr[self.cur - 1] == c {
+            self.cur -= 1;
+        } else {
+            self.pushback.push(c);
+        }
+    }
+
+    fn peek(&mut self) -> Result<Option<C>, std::io::Error> {
+        Ok(self
+            .pushback
+            .last()
+            .or_else(|| self.xstr.get(self.cur))
+            .copied())
     }
 }
 
 use std::marker::PhantomData;
-use std::mem::size_of;
 
+/// Stream over the chars of a file, decoded from their byte encoding.
 pub struct XStrFileReader<C> {
     any_char: PhantomData<C>,
-    char_bytes: usize,
-    freader: BufReader<File>,
+    encoding: CharEncoding,
+    freader: BufReader<Decoder<File>>,
+    /// Leading bytes of a char split across buffer refills
+    partial: Vec<u8>,
+    /// Chars pushed back into the stream, with the number of bytes
+    /// of their encoding if they were looked ahead in the file, else 0
+    pushback: Vec<(C, usize)>,
+    /// Byte offset of the next undecoded char
+    offset: u64,
 }
 
 impl<C> XStrFileReader<C>
 where
     C: Character,
 {
+    /// Opens a stream over the chars of `src`, which may be
+    /// gzip, BGZF or zstd compressed.
+    /// The chars are decoded with the default encoding of `C`.
+    /// Compressed streams are not seekable.
     pub fn new_from_file(src: File) -> Result<Self, std::io::Error> {
         Ok(XStrFileReader {
             any_char: PhantomData,
-            char_bytes: size_of::<C>(),
-            freader: BufReader::new(src),
+            encoding: C::ENCODING,
+            freader: BufReader::new(Decoder::new(src)?),
+            partial: Vec::new(),
+            pushback: Vec::new(),
+            offset: 0,
         })
     }
 
@@ -102,16 +377,82 @@ where
         let file = File::open(path)?;
         Self::new_from_file(file)
     }
-}
 
-/// Convert a slice of T (where T is plain old data) to its mutable binary
-/// representation.
-///
-/// This function is wildly unsafe because it permits arbitrary modification of
-/// the binary representation of any `Copy` type. Use with care.
-unsafe fn slice_to_u8_mut<T: Copy>(slice: &mut [T]) -> &mut [u8] {
-    let len = size_of::<T>() * slice.len();
-    slice::from_raw_parts_mut(slice.as_mut_ptr() as *mut u8, len)
+    /// Sets the byte encoding of the chars of the file.
+    pub fn set_encoding(&mut self, encoding: CharEncoding) {
+        self.encoding = encoding;
+    }
+
+    pub fn encoding(&self) -> CharEncoding {
+        self.encoding
+    }
+
+    fn invalid_char(&self) -> Error {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "Invalid {:?} encoded char at byte offset {}",
+                self.encoding, self.offset
+            ),
+        )
+    }
+
+    /// Decodes the next char of the file, if any.
+    fn decode_next(&mut self) -> Result<Option<(C, usize)>, std::io::Error> {
+        loop {
+            let avail = self.freader.fill_buf()?;
+            let decoded = if self.partial.is_empty() {
+                if avail.is_empty() {
+                    return Ok(None);
+                }
+                match C::decode(avail, self.encoding) {
+                    Decoded::Char(c, len) => {
+                        self.freader.consume(len);
+                        Decoded::Char(c, len)
+                    }
+                    Decoded::Incomplete => {
+                        // the rest of the char is beyond the buffered bytes
+                        let len = avail.len();
+                        self.partial.extend_from_slice(avail);
+                        self.freader.consume(len);
+                        continue;
+                    }
+                    Decoded::Invalid => Decoded::Invalid,
+                }
+            } else {
+                // complete the split char one byte at a time
+                if avail.is_empty() {
+                    return Err(Error::new(
+                        ErrorKind::UnexpectedEof,
+                        format!("Truncated char at byte offset {}", self.offset),
+                    ));
+                }
+                self.partial.push(avail[0]);
+                self.freader.consume(1);
+                C::decode(&self.partial, self.encoding)
+            };
+            match decoded {
+                Decoded::Char(c, len) => {
+                    self.partial.clear();
+                    self.offset += len as u64;
+                    return Ok(Some((c, len)));
+                }
+                Decoded::Incomplete => continue,
+                Decoded::Invalid => return Err(self.invalid_char()),
+            }
+        }
+    }
+
+    /// Returns the next char without consuming it.
+    fn peek_char(&mut self) -> Result<Option<C>, std::io::Error> {
+        if self.pushback.is_empty() {
+            match self.decode_next()? {
+                Some(next) => self.pushback.push(next),
+                None => return Ok(None),
+            }
+        }
+        Ok(self.pushback.last().map(|&(c, _)| c))
+    }
 }
 
 impl<C> XStream for XStrFileReader<C>
@@ -120,59 +461,292 @@ where
 {
     type CharType = C;
     fn get(&mut self) -> Result<Option<C>, std::io::Error> {
-        let mut c: [C; 1] = [Default::default(); 1];
-        if self.read(&mut c)? == 1 {
-            Ok(Some(c[0]))
-        } else {
-            Ok(None)
+        match self.pushback.pop() {
+            Some((c, _)) => Ok(Some(c)),
+            None => Ok(self.decode_next()?.map(|(c, _)| c)),
         }
     }
 
     fn read(&mut self, buf: &mut [C]) -> Result<usize, std::io::Error> {
-        let m;
-        unsafe {
-            let rawbuf =
-                slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, buf.len() * self.char_bytes);
-            m = self.freader.read(rawbuf)?;
-        }
-        if m % self.char_bytes != 0 {
-            return Result::Err(Error::new(
-                ErrorKind::InvalidData,
-                "Invalid number or bytes",
+        let mut n = 0;
+        while n < buf.len() {
+            match self.get()? {
+                Some(c) => buf[n] = c,
+                None => break,
+            }
+            n += 1;
+        }
+        Ok(n)
+    }
+
+    fn read_until(
+        &mut self,
+        buf: &mut [C],
+        delimiter: C,
+        mode: DelimiterMode,
+    ) -> Result<(usize, ReadStatus), std::io::Error> {
+        let mut n = 0;
+        while n < buf.len() {
+            match self.peek_char()? {
+                Some(c) if c != delimiter => buf[n] = c,
+                _ => break,
+            }
+            self.pushback.pop();
+            n += 1;
+        }
+        end_read_until(self, buf, n, delimiter, mode)
+    }
+
+    fn unget(&mut self, c: C) {
+        self.pushback.push((c, 0));
+    }
+
+    fn peek(&mut self) -> Result<Option<C>, std::io::Error> {
+        self.peek_char()
+    }
+}
+
+/// Seeks to a byte offset of the (uncompressed) file,
+/// which should be the start of a char.
+/// Positions are those of the chars decoded from the file: chars pushed
+/// back with `unget` are not accounted for, and are discarded on seek.
+impl<C> Seek for XStrFileReader<C>
+where
+    C: Character,
+{
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, std::io::Error> {
+        let pos = match pos {
+            SeekFrom::Current(delta) => {
+                // position of the next char to be returned
+                let ahead: usize = self.pushback.iter().map(|&(_, len)| len).sum();
+                let cur = self.offset - ahead as u64;
+                match (cur as i64).checked_add(delta) {
+                    Some(pos) if pos >= 0 => SeekFrom::Start(pos as u64),
+                    _ => {
+                        return Err(Error::new(
+                            ErrorKind::InvalidInput,
+                            "Invalid seek to a negative or overflowing position",
+                        ))
+                    }
+                }
+            }
+            pos => pos,
+        };
+        let offset = self.freader.seek(pos)?;
+        self.partial.clear();
+        self.pushback.clear();
+        self.offset = offset;
+        Ok(offset)
+    }
+}
+
+/// Stream over the chars of a memory-mapped, uncompressed, file.
+///
+/// Chars are decoded from the mapping as in `XStrFileReader`, and chars
+/// which can be viewed in place (bytes) are handed out as slices of the
+/// mapping itself, without copying.
+/// As in `XStrFileReader`, positions are byte offsets in the file.
+pub struct XStrMmapReader<C> {
+    encoding: CharEncoding,
+    mmap: Mmap,
+    /// Byte offset of the next char
+    pos: usize,
+    pushback: Vec<C>,
+    /// Chars decoded by `next_chunk` when they cannot be viewed in place
+    chunk: Vec<C>,
+}
+
+impl<C> XStrMmapReader<C>
+where
+    C: Character,
+{
+    /// Maps `src` in memory. The chars are decoded with the default
+    /// encoding of `C`.
+    /// The file must not be modified while it is mapped.
+    pub fn new_from_file(src: &File) -> Result<Self, std::io::Error> {
+        // safety: the mapping is read-only, and the file is assumed not
+        // to be modified by other processes while mapped
+        let mmap = unsafe { Mmap::map(src)? };
+        if Compression::detect(&mmap) != Compression::None {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Compressed files cannot be memory mapped",
             ));
         }
-        Ok(m / self.char_bytes)
+        Ok(XStrMmapReader {
+            encoding: C::ENCODING,
+            mmap,
+            pos: 0,
+            pushback: Vec::new(),
+            chunk: Vec::new(),
+        })
+    }
+
+    pub fn new<P>(path: P) -> Result<Self, std::io::Error>
+    where
+        P: AsRef<Path>,
+    {
+        Self::new_from_file(&File::open(path)?)
+    }
+
+    /// Sets the byte encoding of the chars of the file.
+    pub fn set_encoding(&mut self, encoding: CharEncoding) {
+        self.encoding = encoding;
+    }
+
+    pub fn encoding(&self) -> CharEncoding {
+        self.encoding
+    }
+
+    /// The mapped bytes.
+    pub fn bytes(&self) -> &[u8] {
+        &self.mmap
+    }
+
+    /// The chars of the whole file, for random access,
+    /// if they can be viewed in place.
+    pub fn as_slice(&self) -> Option<&[C]> {
+        C::view_bytes(&self.mmap, self.encoding)
+    }
+
+    /// Reads the next `max` chars at most, which are returned as a slice
+    /// of the mapping if possible. An empty slice means the end of the file.
+    pub fn next_chunk(&mut self, max: usize) -> Result<&[C], std::io::Error> {
+        self.chunk.clear();
+        if !self.pushback.is_empty() {
+            while self.chunk.len() < max {
+                match self.pushback.pop() {
+                    Some(c) => self.chunk.push(c),
+                    None => break,
+                }
+            }
+            return Ok(&self.chunk);
+        }
+        let start = self.pos.min(self.mmap.len());
+        if let Some(view) = C::view_bytes(&self.mmap[start..], self.encoding) {
+            let n = view.len().min(max);
+            self.pos = start + n * C::WIDTH;
+            return Ok(&view[..n]);
+        }
+        while self.chunk.len() < max {
+            match self.decode_at(self.pos)? {
+                Some((c, len)) => {
+                    self.chunk.push(c);
+                    self.pos += len;
+                }
+                None => break,
+            }
+        }
+        Ok(&self.chunk)
     }
 
-    fn read_until(&mut self, buf: &mut [C], delimiter: C) -> Result<usize, std::io::Error> {
-        let mut m;
-        unsafe {
-            let rawbuf =
-                slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, buf.len() * self.char_bytes);
-            m = self.freader.read(rawbuf)?;
+    /// Decodes the char at byte offset `pos`, if any.
+    fn decode_at(&self, pos: usize) -> Result<Option<(C, usize)>, std::io::Error> {
+        let bytes = &self.mmap[pos.min(self.mmap.len())..];
+        if bytes.is_empty() {
+            return Ok(None);
         }
-        if m % self.char_bytes != 0 {
-            return Result::Err(Error::new(
+        match C::decode(bytes, self.encoding) {
+            Decoded::Char(c, len) => Ok(Some((c, len))),
+            Decoded::Incomplete => Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                format!("Truncated char at byte offset {}", pos),
+            )),
+            Decoded::Invalid => Err(Error::new(
                 ErrorKind::InvalidData,
-                "Invalid number or bytes",
-            ));
+                format!(
+                    "Invalid {:?} encoded char at byte offset {}",
+                    self.encoding, pos
+                ),
+            )),
         }
-        m /= self.char_bytes;
-        let del_pos = buf[..m]
-            .iter()
-            .position(|&x| x == delimiter)
-            .unwrap_or_else(|| m);
-        self.seek(SeekFrom::Current(del_pos as i64 - m as i64))?;
-        Ok(del_pos)
     }
 }
 
-impl<C> Seek for XStrFileReader<C>
+impl<C> XStream for XStrMmapReader<C>
+where
+    C: Character,
+{
+    type CharType = C;
+
+    fn get(&mut self) -> Result<Option<C>, std::io::Error> {
+        if let Some(c) = self.pushback.pop() {
+            return Ok(Some(c));
+        }
+        Ok(self.decode_at(self.pos)?.map(|(c, len)| {
+            self.pos += len;
+            c
+        }))
+    }
+
+    fn read(&mut self, buf: &mut [C]) -> Result<usize, std::io::Error> {
+        let (mut n, _) = take_pushback(&mut self.pushback, buf, None);
+        while n < buf.len() {
+            let chunk = self.next_chunk(buf.len() - n)?;
+            if chunk.is_empty() {
+                break;
+            }
+            buf[n..n + chunk.len()].copy_from_slice(chunk);
+            n += chunk.len();
+        }
+        Ok(n)
+    }
+
+    fn read_until(
+        &mut self,
+        buf: &mut [C],
+        delimiter: C,
+        mode: DelimiterMode,
+    ) -> Result<(usize, ReadStatus), std::io::Error> {
+        let (mut n, found) = take_pushback(&mut self.pushback, buf, Some(delimiter));
+        while !found && n < buf.len() {
+            match self.decode_at(self.pos)? {
+                Some((c, len)) if c != delimiter => {
+                    buf[n] = c;
+                    n += 1;
+                    self.pos += len;
+                }
+                _ => break,
+            }
+        }
+        end_read_until(self, buf, n, delimiter, mode)
+    }
+
+    fn unget(&mut self, c: C) {
+        self.pushback.push(c);
+    }
+
+    fn peek(&mut self) -> Result<Option<C>, std::io::Error> {
+        match self.pushback.last() {
+            Some(&c) => Ok(Some(c)),
+            None => Ok(self.decode_at(self.pos)?.map(|(c, _)| c)),
+        }
+    }
+}
+
+/// Seeks to a byte offset of the file, which should be the start of a char.
+/// Chars pushed back with `unget` are discarded.
+impl<C> Seek for XStrMmapReader<C>
 where
     C: Character,
 {
     fn seek(&mut self, pos: SeekFrom) -> Result<u64, std::io::Error> {
-        return self.freader.seek(pos);
+        let (base, delta) = match pos {
+            SeekFrom::Start(offset) => (offset as i64, 0),
+            SeekFrom::End(delta) => (self.mmap.len() as i64, delta),
+            SeekFrom::Current(delta) => (self.pos as i64, delta),
+        };
+        match base.checked_add(delta) {
+            Some(offset) if offset >= 0 => {
+                self.pos = offset as usize;
+                self.pushback.clear();
+                Ok(self.pos as u64)
+            }
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Invalid seek to a negative or overflowing position",
+            )),
+        }
     }
 }
 
@@ -180,8 +754,12 @@ where
 mod tests {
 
     use super::*;
+    use crate::compress::Encoder;
+    use crate::dna::DNAAlphabet;
+    use crate::minimiser::MmIndex;
     use crate::xstring::*;
     use std::io::{BufWriter, Write};
+    use std::rc::Rc;
 
     #[test]
     fn test_xstrstream() {
@@ -215,21 +793,72 @@ mod tests {
         let xstr: XString<u8> = XString::from("abcdefghijklmnopqrstuvwxyz".as_bytes());
         let mut stream = XStrStream::open(&xstr);
         let mut buf = [0; 12];
-        let n = stream.read_until(&mut buf, 'f' as u8).unwrap();
-        assert_eq!(n, 5);
-        let n = stream.read_until(&mut buf, 'r' as u8).unwrap();
-        assert_eq!(n, 12);
-        let n = stream.read_until(&mut buf, 'a' as u8).unwrap();
-        assert_eq!(n, 9);
+        let r = stream.read_until(&mut buf, b'f', DelimiterMode::Exclusive);
+        assert_eq!(r.unwrap(), (5, ReadStatus::Found));
+        let r = stream.read_until(&mut buf, b'r', DelimiterMode::Inclusive);
+        assert_eq!(r.unwrap(), (12, ReadStatus::Found));
+        assert_eq!(&buf, b"ghijklmnopqr");
+        let r = stream.read_until(&mut buf, b'a', DelimiterMode::Exclusive);
+        assert_eq!(r.unwrap(), (8, ReadStatus::Eof));
         stream.close();
+
+        // the delimiter is consumed, and the buffer is never overrun
+        let xstr: XString<u8> = XString::from("a,bcdef,,g,".as_bytes());
+        let mut stream = XStrStream::open(&xstr);
+        let mut buf = [0; 3];
+        let mut reads = vec![];
+        loop {
+            let (n, status) = stream
+                .read_until(&mut buf, b',', DelimiterMode::Inclusive)
+                .unwrap();
+            reads.push((String::from_utf8(buf[..n].to_vec()).unwrap(), status));
+            if status == ReadStatus::Eof {
+                break;
+            }
+        }
+        let found = |s: &str| (String::from(s), ReadStatus::Found);
+        let full = |s: &str| (String::from(s), ReadStatus::BufferFull);
+        assert_eq!(
+            reads,
+            vec![
+                found("a,"),
+                full("bcd"),
+                found("ef,"),
+                found(","),
+                found("g,"),
+                (String::new(), ReadStatus::Eof)
+            ]
+        );
+        // a full buffer followed by the delimiter
+        let mut stream = XStrStream::open(&xstr);
+        stream.get().unwrap();
+        let r = stream.read_until(&mut buf[..1], b',', DelimiterMode::Exclusive);
+        assert_eq!(r.unwrap(), (0, ReadStatus::Found));
+        let r = stream.read_until(&mut buf, b',', DelimiterMode::Exclusive);
+        assert_eq!(r.unwrap(), (3, ReadStatus::BufferFull));
+        let r = stream.read_until(&mut buf[..2], b',', DelimiterMode::Exclusive);
+        assert_eq!(r.unwrap(), (2, ReadStatus::Found));
+
+        // growable destination
+        let mut stream = XStrStream::open(&xstr);
+        let mut xs = XString::new();
+        let r = stream.read_until_xstring(&mut xs, b'g', DelimiterMode::Exclusive);
+        assert_eq!(r.unwrap(), ReadStatus::Found);
+        assert_eq!(&xs[..], b"a,bcdef,,");
+        let long = XString::repeat(5000, b'x');
+        let mut stream = XStrStream::open(&long);
+        let mut xs = XString::new();
+        let r = stream.read_until_xstring(&mut xs, b',', DelimiterMode::Inclusive);
+        assert_eq!(r.unwrap(), ReadStatus::Eof);
+        assert_eq!(xs.len(), 5000);
     }
 
-    const DNA_FILE: &'static [u8] = b"AAAAAAAAAACCCCCCCCCCGGGGGGGGGGTTTTTTTTTT";
+    const DNA_FILE: &[u8] = b"AAAAAAAAAACCCCCCCCCCGGGGGGGGGGTTTTTTTTTT";
 
     fn file_setup(filename: &str) {
         let f = File::create(filename).expect("Unabe to create the file");
         let mut writer = BufWriter::new(f);
-        writer.write(&DNA_FILE);
+        writer.write_all(DNA_FILE).unwrap();
     }
 
     fn file_teardown(filename: &str) {
@@ -274,40 +903,249 @@ mod tests {
             XStrFileReader::new("test_xstrfilestream_read_until.txt").expect("Cannot open stream");
         let mut buf: [u8; 50] = [0; 50];
         let mut cur = 0;
-        let mut m = stream
-            .read_until(&mut buf, 'A' as u8)
-            .expect("cannot read from stream");
-        assert_eq!(m, 0);
-        cur += m;
-        println!("cur={0} buf={1:?}", cur, &buf[..m]);
-        m = stream
-            .read_until(&mut buf, 'C' as u8)
-            .expect("cannot read from stream");
-        assert_eq!(m, 10);
-        cur += m;
-        println!("cur={0} buf={1:?}", cur, &buf[..m]);
-        m = stream
-            .read_until(&mut buf, 'T' as u8)
-            .expect("cannot read from stream");
-        assert_eq!(m, 20);
-        cur += m;
-        println!("cur={0} buf={1:?}", cur, &buf[..m]);
-        m = stream
-            .read_until(&mut buf, 'T' as u8)
-            .expect("cannot read from stream");
-        assert_eq!(m, 0);
-        cur += m;
-        println!("cur={0} buf={1:?}", cur, &buf[..m]);
-        m = stream
-            .read_until(&mut buf, 'A' as u8)
-            .expect("cannot read from stream");
-        assert_eq!(m, 10);
-        cur += m;
-        println!("cur={0} buf={1:?}", cur, &buf[..m]);
-        for j in 0..m {
-            assert_eq!(buf[j], DNA_FILE[cur - m + j]);
+        use DelimiterMode::*;
+        for &(delimiter, mode, len, status) in [
+            (b'A', Exclusive, 0, ReadStatus::Found),
+            (b'C', Exclusive, 9, ReadStatus::Found),
+            (b'T', Inclusive, 20, ReadStatus::Found),
+            (b'T', Exclusive, 0, ReadStatus::Found),
+            (b'A', Inclusive, 8, ReadStatus::Eof),
+            (b'A', Inclusive, 0, ReadStatus::Eof),
+        ]
+        .iter()
+        {
+            let (m, st) = stream
+                .read_until(&mut buf, delimiter, mode)
+                .expect("cannot read from stream");
+            assert_eq!((m, st), (len, status));
+            assert_eq!(buf[..m], DNA_FILE[cur..cur + m]);
+            cur += m;
+            if st == ReadStatus::Found && mode == Exclusive {
+                cur += 1;
+            }
         }
+        assert_eq!(cur, DNA_FILE.len());
 
         file_teardown("test_xstrfilestream_read_until.txt");
     }
+
+    #[test]
+    fn test_xstrfilestream_read_until_gz() {
+        use flate2::write::GzEncoder;
+
+        let filename = "test_xstrfilestream_read_until.txt.gz";
+        let mut encoder = GzEncoder::new(
+            File::create(filename).expect("Unable to create the file"),
+            flate2::Compression::default(),
+        );
+        encoder
+            .write_all(DNA_FILE)
+            .expect("Unable to write to file");
+        encoder.finish().expect("Unable to write to file");
+
+        let mut stream: XStrFileReader<u8> =
+            XStrFileReader::new(filename).expect("Cannot open stream");
+        let mut buf: [u8; 50] = [0; 50];
+        let r = stream.read_until(&mut buf, b'C', DelimiterMode::Exclusive);
+        assert_eq!(r.unwrap(), (10, ReadStatus::Found));
+        let r = stream.read_until(&mut buf, b'T', DelimiterMode::Exclusive);
+        assert_eq!(r.unwrap(), (19, ReadStatus::Found));
+        assert_eq!(&buf[..19], &DNA_FILE[11..30]);
+        assert_eq!(stream.get().unwrap(), Some(b'T'));
+        assert_eq!(stream.read(&mut buf).unwrap(), 8);
+        assert_eq!(stream.get().unwrap(), None);
+
+        file_teardown(filename);
+    }
+
+    fn bytes_setup(filename: &str, bytes: &[u8]) {
+        std::fs::write(filename, bytes).expect("Unable to write to file");
+    }
+
+    #[test]
+    fn test_xstrfilestream_encoding() {
+        let filename = "test_xstrfilestream_encoding.bin";
+        let codes: Vec<u16> = vec![0x0041, 0x0102, 0xfffe, 0x20ac];
+        for &enc in [CharEncoding::LittleEndian, CharEncoding::BigEndian].iter() {
+            let mut bytes = vec![];
+            for &c in codes.iter() {
+                c.encode(enc, &mut bytes).unwrap();
+            }
+            bytes_setup(filename, &bytes);
+            let mut stream: XStrFileReader<u16> = XStrFileReader::new(filename).unwrap();
+            stream.set_encoding(enc);
+            let mut buf = [0u16; 8];
+            assert_eq!(stream.read(&mut buf).unwrap(), 4);
+            assert_eq!(&buf[..4], &codes[..]);
+            stream.seek(SeekFrom::Start(2)).unwrap();
+            assert_eq!(
+                stream
+                    .read_until(&mut buf, 0x20ac, DelimiterMode::Inclusive)
+                    .unwrap(),
+                (3, ReadStatus::Found)
+            );
+            assert_eq!(&buf[..3], &codes[1..4]);
+            assert_eq!(stream.stream_position().unwrap(), 8);
+            assert_eq!(stream.get().unwrap(), None);
+            stream.seek(SeekFrom::Start(4)).unwrap();
+            assert_eq!(stream.peek().unwrap(), Some(0xfffe));
+            assert_eq!(stream.stream_position().unwrap(), 4);
+        }
+
+        // odd number of bytes
+        bytes_setup(filename, &[1, 0, 2]);
+        let mut stream: XStrFileReader<u16> = XStrFileReader::new(filename).unwrap();
+        assert_eq!(stream.get().unwrap(), Some(1));
+        assert_eq!(stream.get().unwrap_err().kind(), ErrorKind::UnexpectedEof);
+
+        // surrogate code point in UTF-32
+        bytes_setup(filename, &[0x41, 0, 0, 0, 0, 0xd8, 0, 0]);
+        let mut stream: XStrFileReader<char> = XStrFileReader::new(filename).unwrap();
+        stream.set_encoding(CharEncoding::LittleEndian);
+        assert_eq!(stream.get().unwrap(), Some('A'));
+        assert_eq!(stream.get().unwrap_err().kind(), ErrorKind::InvalidData);
+
+        file_teardown(filename);
+    }
+
+    #[test]
+    fn test_xstrfilestream_utf8() {
+        let filename = "test_xstrfilestream_utf8.txt";
+        // multi-byte chars are split across buffer refills
+        let text: String = "aé€𝄞".repeat(5000);
+        bytes_setup(filename, text.as_bytes());
+        let mut stream: XStrFileReader<char> = XStrFileReader::new(filename).unwrap();
+        assert_eq!(stream.encoding(), CharEncoding::Utf8);
+        let mut chars = vec![];
+        let mut buf = ['\0'; 1000];
+        let mut n = stream.read(&mut buf).unwrap();
+        while n > 0 {
+            chars.extend_from_slice(&buf[..n]);
+            n = stream.read(&mut buf).unwrap();
+        }
+        assert_eq!(chars, text.chars().collect::<Vec<char>>());
+
+        stream.seek(SeekFrom::Start(1)).unwrap();
+        assert_eq!(
+            stream
+                .read_until(&mut buf, '𝄞', DelimiterMode::Exclusive)
+                .unwrap(),
+            (2, ReadStatus::Found)
+        );
+        assert_eq!(&buf[..2], &['é', '€']);
+        assert_eq!(stream.get().unwrap(), Some('a'));
+
+        bytes_setup(filename, b"ab\xe2\x82");
+        let mut stream: XStrFileReader<char> = XStrFileReader::new(filename).unwrap();
+        assert!(stream.read(&mut buf).is_err());
+        bytes_setup(filename, b"ab\xff");
+        let mut stream: XStrFileReader<char> = XStrFileReader::new(filename).unwrap();
+        assert_eq!(
+            stream
+                .read_until(&mut buf, 'b', DelimiterMode::Exclusive)
+                .unwrap(),
+            (1, ReadStatus::Found)
+        );
+        assert_eq!(stream.get().unwrap_err().kind(), ErrorKind::InvalidData);
+
+        file_teardown(filename);
+    }
+
+    /// Checks the lookahead operations on a stream over `DNA_FILE`.
+    fn check_lookahead(stream: &mut impl XStream<CharType = u8>) {
+        assert_eq!(stream.peek().unwrap(), Some(b'A'));
+        assert_eq!(stream.skip_while(|c| c == b'A').unwrap(), 10);
+        let mut buf = [0u8; 4];
+        assert_eq!(stream.peek_n(&mut buf).unwrap(), 4);
+        assert_eq!(&buf, b"CCCC");
+        assert_eq!(stream.read_while(&mut buf, |c| c == b'C').unwrap(), 4);
+        assert_eq!(stream.skip_while(|c| c == b'C').unwrap(), 6);
+        // chars pushed back need not be the ones read
+        stream.unget(b'x');
+        stream.unget(b'y');
+        assert_eq!(stream.peek().unwrap(), Some(b'y'));
+        assert_eq!(stream.peek_n(&mut buf).unwrap(), 4);
+        assert_eq!(&buf, b"yxGG");
+        assert_eq!(
+            stream
+                .read_until(&mut buf, b'x', DelimiterMode::Inclusive)
+                .unwrap(),
+            (2, ReadStatus::Found)
+        );
+        assert_eq!(&buf[..2], b"yx");
+        let mut buf = [0u8; 30];
+        assert_eq!(stream.read_while(&mut buf, |c| c != b'T').unwrap(), 10);
+        assert_eq!(stream.get().unwrap(), Some(b'T'));
+        stream.unget(b'T');
+        assert_eq!(stream.read(&mut buf).unwrap(), 10);
+        assert_eq!(stream.peek().unwrap(), None);
+        assert_eq!(stream.peek_n(&mut buf).unwrap(), 0);
+        stream.unget(b'T');
+        assert_eq!(stream.read(&mut buf).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_lookahead() {
+        check_lookahead(&mut XStrStream::open_slice(DNA_FILE));
+
+        let filename = "test_xstrfilestream_lookahead.txt";
+        file_setup(filename);
+        let mut stream: XStrFileReader<u8> = XStrFileReader::new(filename).unwrap();
+        check_lookahead(&mut stream);
+        file_teardown(filename);
+    }
+
+    #[test]
+    fn test_xstrmmap() {
+        let filename = "test_xstrmmap.txt";
+        file_setup(filename);
+        let mut stream: XStrMmapReader<u8> = XStrMmapReader::new(filename).unwrap();
+        assert_eq!(stream.as_slice(), Some(DNA_FILE));
+        assert_eq!(stream.next_chunk(15).unwrap(), &DNA_FILE[..15]);
+        stream.unget(b'N');
+        assert_eq!(stream.next_chunk(15).unwrap(), b"N");
+        assert_eq!(stream.next_chunk(100).unwrap(), &DNA_FILE[15..]);
+        assert!(stream.next_chunk(100).unwrap().is_empty());
+        stream.seek(SeekFrom::End(-5)).unwrap();
+        let mut buf = [0u8; 10];
+        assert_eq!(stream.read(&mut buf).unwrap(), 5);
+        assert_eq!(&buf[..5], b"TTTTT");
+        assert!(stream.seek(SeekFrom::Current(-41)).is_err());
+        stream.seek(SeekFrom::Start(0)).unwrap();
+        check_lookahead(&mut stream);
+
+        // index in blocks from the mapping
+        let ranker = KmerXStrLexHasher::new(Rc::new(DNAAlphabet::new()), 4);
+        let mut from_mmap = MmIndex::new(&[3], &[4], &[&ranker]);
+        stream.seek(SeekFrom::Start(0)).unwrap();
+        from_mmap.index(&mut stream).unwrap();
+        let mut from_xstr = MmIndex::new(&[3], &[4], &[&ranker]);
+        from_xstr.index_xstr(&XString::from(DNA_FILE)).unwrap();
+        assert_eq!(from_mmap.seq_offsets(), from_xstr.seq_offsets());
+        assert_eq!(from_mmap.stats(), from_xstr.stats());
+
+        // decoded chars
+        let mut bytes = vec![];
+        for c in 0u32..1000 {
+            c.encode(CharEncoding::BigEndian, &mut bytes).unwrap();
+        }
+        bytes.push(0);
+        bytes_setup(filename, &bytes);
+        let mut stream: XStrMmapReader<u32> = XStrMmapReader::new(filename).unwrap();
+        stream.set_encoding(CharEncoding::BigEndian);
+        assert_eq!(stream.as_slice(), None);
+        let chunk: Vec<u32> = stream.next_chunk(600).unwrap().to_vec();
+        assert_eq!(chunk, (0..600).collect::<Vec<u32>>());
+        let mut buf = [0u32; 400];
+        assert_eq!(stream.read(&mut buf).unwrap(), 400);
+        assert_eq!(stream.get().unwrap_err().kind(), ErrorKind::UnexpectedEof);
+
+        // compressed files are rejected
+        let mut enc = Encoder::new(vec![], Compression::Gzip).unwrap();
+        enc.write_all(DNA_FILE).unwrap();
+        bytes_setup(filename, &enc.finish().unwrap());
+        assert!(XStrMmapReader::<u8>::new(filename).is_err());
+
+        file_teardown(filename);
+    }
 }
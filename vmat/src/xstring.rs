@@ -7,7 +7,7 @@ use std::slice::SliceIndex;
 
 use crate::alphabet::Alphabet;
 
-#[derive(PartialEq, Eq, Hash, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub struct XString<C>
 where
     C: Character,
@@ -15,6 +15,15 @@ where
     v: Vec<C>,
 }
 
+impl<C> Default for XString<C>
+where
+    C: Character,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<C> XString<C>
 where
     C: Character,
@@ -31,6 +40,10 @@ where
         self.v.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.v.is_empty()
+    }
+
     pub fn substring(&self, begin: usize, end: usize) -> &[C] {
         &self.v[begin..end]
     }
@@ -91,7 +104,7 @@ where
     C: Character,
     I: SliceIndex<[C]>,
 {
-    fn index_mut<'a>(&'a mut self, index: I) -> &'a mut Self::Output {
+    fn index_mut(&mut self, index: I) -> &mut Self::Output {
         &mut self.v[index]
     }
 }
@@ -118,6 +131,23 @@ where
 pub trait XStrHasher {
     type CharType;
     fn hash(&self, s: &[Self::CharType]) -> u64;
+
+    /**
+     * The chars of the strings the hasher can hash, if known.
+     * Saved indexes hash probe strings made of them to check that they
+     * are loaded with the hasher they were built with.
+     */
+    fn chars(&self) -> Vec<Self::CharType> {
+        vec![]
+    }
+
+    /**
+     * Whether the hasher can hash strings containing the char `c`.
+     * Strings with other chars must not be hashed.
+     */
+    fn accepts(&self, _c: &Self::CharType) -> bool {
+        true
+    }
 }
 
 pub trait XStrRollHasher: XStrHasher {
@@ -125,6 +155,26 @@ pub trait XStrRollHasher: XStrHasher {
      * Updates the hash `h=h(s) `of string `s=s[0..n-1]` by appending the char `c`.
      */
     fn roll_hash(&self, s: &[Self::CharType], h: u64, c: Self::CharType) -> u64;
+
+    /**
+     * Computes the hashes of all the `k`-mers of `s` into `hashes`, in order.
+     * Hashers may override this with a faster bulk computation.
+     */
+    fn kmer_hashes(&self, s: &[Self::CharType], k: usize, hashes: &mut Vec<u64>)
+    where
+        Self::CharType: Copy,
+    {
+        hashes.clear();
+        if s.len() < k {
+            return;
+        }
+        let mut h = self.hash(&s[..k]);
+        hashes.push(h);
+        for i in k..s.len() {
+            h = self.roll_hash(&s[i - k..i], h, s[i]);
+            hashes.push(h);
+        }
+    }
 }
 
 pub struct XStrLexHasher<C, A>
@@ -159,6 +209,16 @@ where
         }
         r
     }
+
+    fn chars(&self) -> Vec<Self::CharType> {
+        (0..self.ab.len())
+            .filter_map(|r| self.ab.chr(r).copied())
+            .collect()
+    }
+
+    fn accepts(&self, c: &Self::CharType) -> bool {
+        self.ab.ord(c).is_some()
+    }
 }
 
 impl<C, A> XStrRollHasher for XStrLexHasher<C, A>
@@ -166,7 +226,7 @@ where
     C: Character,
     A: Alphabet<CharType = C>,
 {
-    fn roll_hash(&self, s: &[Self::CharType], h: u64, c: Self::CharType) -> u64 {
+    fn roll_hash(&self, _s: &[Self::CharType], h: u64, c: Self::CharType) -> u64 {
         (h * self.ab.len() as u64) + (self.ab.ord(&c).expect("Char not in alphabet") as u64)
     }
 }
@@ -214,6 +274,16 @@ where
         }
         r
     }
+
+    fn chars(&self) -> Vec<Self::CharType> {
+        (0..self.ab.len())
+            .filter_map(|r| self.ab.chr(r).copied())
+            .collect()
+    }
+
+    fn accepts(&self, c: &Self::CharType) -> bool {
+        self.ab.ord(c).is_some()
+    }
 }
 
 impl<C, A> XStrRollHasher for KmerXStrLexHasher<C, A>
@@ -234,7 +304,7 @@ mod tests {
 
     #[test]
     fn test_xstr_len() {
-        let mut xstr: XString<u8> = XString::new();
+        let xstr: XString<u8> = XString::new();
         assert_eq!(xstr.len(), 0);
     }
 
@@ -250,19 +320,12 @@ mod tests {
         for i in 0..n {
             assert_eq!(xstr[i], i as u8);
         }
-        let mut i: u8 = 0;
-        for &c in xstr.iter() {
-            assert_eq!(c, i);
-            i += 1;
+        for (i, &c) in xstr.iter().enumerate() {
+            assert_eq!(c, i as u8);
         }
         xstr[0] = 2;
     }
 
-    fn len_deref<T>(slice: &[T]) -> usize {
-        println!("T len is {}", slice.len());
-        slice.len()
-    }
-
     #[test]
     fn test_xstr_slice() {
         let mut xstr: XString<u8> = XString::new();
@@ -274,6 +337,8 @@ mod tests {
         let k = 4;
         for i in 0..n - k {
             let slice = &xstr[i..i + k];
+            assert_eq!(slice.len(), k);
+            assert_eq!(slice[0], i as u8);
         }
     }
 }